@@ -1,16 +1,27 @@
 use winit::{
     dpi::PhysicalSize,
-    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
+    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
     platform::windows::IconExtWindows,
-    window::{Icon, Window},
+    window::{CursorGrabMode, Icon, Window},
 };
 
 use crate::{
     application::state::State,
-    rendering::{camera::Direction, light::Light, sphere::Sphere},
+    rendering::{
+        bookmark::{Bookmark, Bookmarks, CameraPath},
+        camera::{Camera, Direction},
+        controller::{CameraTransition, FlyController, OrbitController, StandardView},
+        environment::{Background, EnvironmentMap},
+        light::{Light, RectLight},
+        material::Material,
+        sky::Daylight,
+        sphere::Sphere,
+    },
+    utils::{image::read_environment, mesh::load_mesh},
 };
 use rand::Rng;
+use std::{path::PathBuf, time::Instant};
 
 #[derive(Default)]
 pub struct App<'a> {
@@ -18,6 +29,101 @@ pub struct App<'a> {
     pub state: Option<State<'a>>,
     pub movements: [bool; 3],
     pub last_mouse_pos: winit::dpi::PhysicalPosition<f64>,
+    pub orbit: OrbitController,
+    pub fly: FlyController,
+    pub fly_mode: bool,              // Tab switches between orbiting and flying
+    pub last_frame: Option<Instant>, // time of the last fly or transition update
+    pub transition: Option<CameraTransition>, // running animation toward a framed view
+    pub modifiers: ModifiersState,
+    pub bookmarks: Bookmarks, // saved with shift and a number key, recalled with the key
+    pub camera_path: CameraPath, // keyframes added with K, rendered to images with P
+    pub mesh_path: Option<PathBuf>, // defaults to assets/monkey.stl
+    pub reference_path: Option<PathBuf>, // shows a deviation heatmap against this mesh when set
+    pub environment_path: Option<PathBuf>, // .hdr, or .exr with the exr feature, lights the scene
+    pub daylight: Option<Daylight>, // starts on the daylight sky with this sun when set
+}
+
+// keys moving the camera in fly mode
+fn fly_direction(code: KeyCode) -> Option<Direction> {
+    match code {
+        KeyCode::KeyW => Some(Direction::Forward),
+        KeyCode::KeyS => Some(Direction::Backward),
+        KeyCode::KeyA => Some(Direction::Left),
+        KeyCode::KeyD => Some(Direction::Right),
+        KeyCode::KeyE => Some(Direction::Up),
+        KeyCode::KeyQ => Some(Direction::Down),
+        _ => None,
+    }
+}
+
+// numpad keys switching to a standard view
+fn standard_view(code: KeyCode) -> Option<StandardView> {
+    match code {
+        KeyCode::Numpad1 => Some(StandardView::Front),
+        KeyCode::Numpad3 => Some(StandardView::Right),
+        KeyCode::Numpad7 => Some(StandardView::Top),
+        KeyCode::Numpad9 => Some(StandardView::Isometric),
+        _ => None,
+    }
+}
+
+// number keys selecting a bookmark slot
+fn bookmark_slot(code: KeyCode) -> Option<usize> {
+    let digits = [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    digits.iter().position(|&digit| digit == code)
+}
+
+impl<'a> App<'a> {
+    // bookmarks are kept next to the mesh, e.g. assets/monkey.views, which git ignores
+    pub fn bookmarks_path(&self) -> PathBuf {
+        self.mesh_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("assets/monkey.stl"))
+            .with_extension("views")
+    }
+
+    // eases the camera into `target` over the next frames instead of jumping there
+    pub fn transition_to(&mut self, target: &Camera) {
+        if let Some(state) = &self.state {
+            self.transition = Some(CameraTransition::new(
+                &state.cam_manager.camera,
+                target,
+                0.4,
+            ));
+            self.last_frame = None;
+        }
+    }
+
+    // flying captures and hides the cursor so mouse motion only turns the view
+    pub fn set_fly_mode(&mut self, fly_mode: bool) {
+        self.fly_mode = fly_mode;
+        self.fly.stop();
+        self.last_frame = None;
+        if let Some(window) = &self.window {
+            let grab = if fly_mode {
+                window
+                    .set_cursor_grab(CursorGrabMode::Locked)
+                    .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+            } else {
+                window.set_cursor_grab(CursorGrabMode::None)
+            };
+            if let Err(e) = grab {
+                println!("Could not grab the cursor: {}", e);
+            }
+            window.set_cursor_visible(!fly_mode);
+        }
+    }
 }
 
 impl<'a> winit::application::ApplicationHandler for App<'a> {
@@ -35,32 +141,83 @@ impl<'a> winit::application::ApplicationHandler for App<'a> {
             ));
             self.window = Some(window.clone());
 
-            let state = pollster::block_on(State::new(window, None));
+            let mesh_path = self
+                .mesh_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("assets/monkey.stl"));
+            let state = pollster::block_on(State::new(window, None, &mesh_path));
             self.state = Some(state);
             let state = self.state.as_mut().unwrap();
+            if let Some(reference_path) = &self.reference_path {
+                match load_mesh(reference_path) {
+                    Ok(reference) => state.compare_with(&reference),
+                    Err(e) => println!("Could not load {:?}: {}", reference_path, e),
+                }
+            }
+            if let Some(environment_path) = &self.environment_path {
+                let loaded = read_environment(&environment_path.to_string_lossy())
+                    .and_then(EnvironmentMap::new)
+                    .and_then(|map| {
+                        state
+                            .environment_manager
+                            .set_map(&state.device, &state.queue, map)
+                    });
+                if let Err(e) = loaded {
+                    println!("Could not load {:?}: {}", environment_path, e);
+                }
+            }
+            if let Some(daylight) = self.daylight {
+                state.environment_manager.daylight = daylight;
+                state.environment_manager.background = Background::Daylight;
+                state.update_environment();
+            }
             state.cam_manager.camera.position.v[2] = -18.;
             state.cam_manager.camera.near = 0.001;
 
             let lights = vec![
-                Light {
-                    position: [-100., -100., -100.],
-                    is_valid: 1,
-                    ..Default::default()
-                },
-                Light {
-                    position: [100., 0., 100.],
-                    is_valid: 1,
-                    ..Default::default()
-                },
+                Light::point([-100., -100., -100.], [1., 1., 1.], 30_000.),
+                Light::point([100., 0., 100.], [1., 1., 1.], 30_000.),
+                // a key light on the mesh from above the camera
+                Light::spot(
+                    [0., 30., -30.],
+                    [0., -1., 1.],
+                    [1., 0.95, 0.9],
+                    3_000.,
+                    0.15,
+                    0.3,
+                ),
             ];
 
             state
                 .light_manager
                 .add_lights(lights, &state.device, &state.queue);
+            // a soft box overhead, shining down
+            state.light_manager.add_rect_light(
+                RectLight::new([-5., 25., -5.], [10., 0., 0.], [0., 0., 10.], [5., 5., 5.]),
+                &state.device,
+                &state.queue,
+            );
             let mut rng = rand::thread_rng();
             let mut spheres: Vec<Sphere> = Vec::new();
             let range_val = 10_f32;
             for _ in 0..5 {
+                let albedo = [
+                    rng.gen_range(0.0..1.0), // Random red value between 0 and 1
+                    rng.gen_range(0.0..1.0), // Random green value between 0 and 1
+                    rng.gen_range(0.0..1.0), // Random blue value between 0 and 1
+                ];
+                // any kind of surface, from matte to polished, clear to frosted, or glowing
+                let material = match rng.gen_range(0..5) {
+                    0 => Material::diffuse(albedo),
+                    1 => Material::plastic(albedo, rng.gen_range(0.05..0.5)),
+                    2 => Material::metal(albedo, rng.gen_range(0.0..0.6)),
+                    3 => Material::emissive(albedo.map(|c| 4. * c)),
+                    _ => Material::glass(
+                        [1., 1., 1.],
+                        rng.gen_range(1.3..1.8),
+                        rng.gen_range(0.0..0.3),
+                    ),
+                };
                 let sphere = Sphere {
                     center: [
                         rng.gen_range(-range_val..range_val), // Random x position
@@ -68,15 +225,12 @@ impl<'a> winit::application::ApplicationHandler for App<'a> {
                         rng.gen_range(-range_val..range_val), // Random z position
                     ],
                     radius: rng.gen_range(1.0..3.0), // Random radius between 0.5 and 5
-                    color: [
-                        rng.gen_range(0.0..1.0), // Random red value between 0 and 1
-                        rng.gen_range(0.0..1.0), // Random green value between 0 and 1
-                        rng.gen_range(0.0..1.0), // Random blue value between 0 and 1
-                        1.0,                     // Alpha (opacity) is fixed at 1.0
-                    ],
-                    refractivity: rng.gen_range(1.0..3.0),
-                    material: rng.gen_range(0.0..1.0), // Material is fixed
-                    ..Default::default()               // Padding is fixed
+                    material: state.material_manager.add_material(
+                        material,
+                        &state.device,
+                        &state.queue,
+                    ),
+                    ..Default::default() // Padding is fixed
                 };
 
                 spheres.push(sphere);
@@ -84,6 +238,20 @@ impl<'a> winit::application::ApplicationHandler for App<'a> {
             state
                 .sphere_manager
                 .add_spheres(spheres, &state.device, &state.queue);
+
+            // start with the whole scene in view, whatever the size and placement of the mesh
+            let aspect_ratio = state.size.width as f32 / state.size.height.max(1) as f32;
+            let bounds = state.scene_bounds();
+            state.cam_manager.camera.frame(&bounds, aspect_ratio);
+            state.update_camera();
+
+            let bookmarks_path = self.bookmarks_path();
+            if bookmarks_path.exists() {
+                match Bookmarks::read(&bookmarks_path.to_string_lossy()) {
+                    Ok(bookmarks) => self.bookmarks = bookmarks,
+                    Err(e) => println!("Could not load bookmarks: {}", e),
+                }
+            }
         }
     }
     fn window_event(
@@ -103,16 +271,43 @@ impl<'a> winit::application::ApplicationHandler for App<'a> {
             WindowEvent::Resized(physical_size) => {
                 let state = self.state.as_mut().unwrap();
                 state.resize(physical_size);
-                state.cam_manager.camera.update_cam_info(&physical_size);
-                state.queue.write_buffer(
-                    &state.cam_manager.camera_buffer,
-                    0,
-                    bytemuck::cast_slice(&[state.cam_manager.camera.cam_info]),
-                );
-                let _ = state.render();
+                state.update_camera();
             }
             WindowEvent::RedrawRequested => {}
 
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::Tab),
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => self.set_fly_mode(!self.fly_mode),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                        ..
+                    },
+                ..
+            } if self.fly_mode => self.set_fly_mode(false),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: key_state,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
+                ..
+            } if self.fly_mode && fly_direction(code).is_some() => {
+                self.fly.set_held(
+                    fly_direction(code).unwrap(),
+                    key_state == ElementState::Pressed,
+                );
+            }
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
@@ -153,70 +348,297 @@ impl<'a> winit::application::ApplicationHandler for App<'a> {
                 self.movements[1] = false;
             }
             WindowEvent::CursorMoved { position, .. } => {
-                if self.movements[0] || self.movements[1] {
-                    let x_diff = self.last_mouse_pos.x - position.x;
-                    let y_diff = self.last_mouse_pos.y - position.y;
+                if !self.fly_mode && (self.movements[0] || self.movements[1]) {
+                    let dx = (position.x - self.last_mouse_pos.x) as f32;
+                    let dy = (position.y - self.last_mouse_pos.y) as f32;
                     let state = self.state.as_mut().unwrap();
-                    if x_diff > 0. {
-                        state.cam_manager.camera.movement(
-                            Direction::Left,
-                            &self.movements[0],
-                            x_diff.abs() as f32,
-                        );
-                    } else if x_diff < 0. {
-                        state.cam_manager.camera.movement(
-                            Direction::Right,
-                            &self.movements[0],
-                            x_diff.abs() as f32,
-                        );
+                    self.transition = None;
+                    // left drag (or ctrl) orbits around the focus, right drag pans
+                    if self.movements[0] {
+                        self.orbit.rotate(&mut state.cam_manager.camera, dx, dy);
+                    } else {
+                        let height = state.size.height as f32;
+                        self.orbit
+                            .pan(&mut state.cam_manager.camera, dx, dy, height);
                     }
-                    if y_diff > 0. {
-                        state.cam_manager.camera.movement(
-                            Direction::Up,
-                            &self.movements[0],
-                            y_diff.abs() as f32,
-                        );
-                    } else if y_diff < 0. {
-                        state.cam_manager.camera.movement(
-                            Direction::Down,
-                            &self.movements[0],
-                            y_diff.abs() as f32,
-                        );
-                    }
-                    state.cam_manager.camera.update_cam_info(&state.size);
-                    state.queue.write_buffer(
-                        &state.cam_manager.camera_buffer,
-                        0,
-                        bytemuck::cast_slice(&[state.cam_manager.camera.cam_info]),
-                    );
-                    let _ = state.render();
+                    state.update_camera();
                 }
                 self.last_mouse_pos = position;
             }
 
-            WindowEvent::MouseWheel {
-                delta: MouseScrollDelta::LineDelta(_x, y),
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_x, y) => y,
+                    // trackpads report pixels, roughly 20 of them per line
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.,
+                };
+                if self.fly_mode {
+                    // the wheel sets the flying speed
+                    self.fly.speed *= (lines * 0.1).exp();
+                    return;
+                }
+                let state = self.state.as_mut().unwrap();
+                self.transition = None;
+                self.orbit.dolly(&mut state.cam_manager.camera, lines);
+                state.update_camera();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                        ..
+                    },
+                ..
+            } => {
+                // toggle between the binary and the 4-wide BVH layout
+                let state = self.state.as_mut().unwrap();
+                let wide = state.bvh_manager.bvh4.is_none();
+                state
+                    .bvh_manager
+                    .set_wide(&state.device, &state.queue, wide);
+                state.accumulation_manager.reset();
+                let _ = state.render();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                        ..
+                    },
+                ..
+            } => {
+                // toggle the cross-section overlay
+                let state = self.state.as_mut().unwrap();
+                state.overlay_manager.visible = !state.overlay_manager.visible;
+                let _ = state.render();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
+                ..
+            } if code == KeyCode::Home
+                || code == KeyCode::KeyF
+                || standard_view(code).is_some() =>
+            {
+                // Home fits the whole scene, F the object under the cursor, the numpad keys turn
+                // to a standard view of the whole scene
+                let state = self.state.as_ref().unwrap();
+                let bounds = if code == KeyCode::KeyF {
+                    let (x, y) = (self.last_mouse_pos.x as f32, self.last_mouse_pos.y as f32);
+                    match state.pick_bounds(x, y) {
+                        Some(bounds) => bounds,
+                        None => return,
+                    }
+                } else {
+                    state.scene_bounds()
+                };
+                let aspect_ratio = state.size.width as f32 / state.size.height.max(1) as f32;
+                let mut target = state.cam_manager.camera.clone();
+                if let Some(view) = standard_view(code) {
+                    view.apply(&mut target);
+                }
+                target.frame(&bounds, aspect_ratio);
+                self.transition_to(&target);
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
+                ..
+            } if bookmark_slot(code).is_some() => {
+                // shift and a number key saves the view, the number key alone goes back to it
+                let slot = bookmark_slot(code).unwrap();
+                let camera = self.state.as_ref().unwrap().cam_manager.camera.clone();
+                if self.modifiers.shift_key() {
+                    // the name stays the one given in the bookmarks file, if any
+                    self.bookmarks.save(slot, "", &camera);
+                    let name = &self.bookmarks.get(slot).unwrap().name;
+                    let bookmarks_path = self.bookmarks_path();
+                    match self.bookmarks.write(&bookmarks_path.to_string_lossy()) {
+                        Ok(()) => println!("saved {:?} to {:?}", name, bookmarks_path),
+                        Err(e) => println!("Could not save bookmarks: {}", e),
+                    }
+                } else if let Some(bookmark) = self.bookmarks.get(slot) {
+                    println!("going to {:?}", bookmark.name);
+                    let target = bookmark.camera(&camera);
+                    self.transition_to(&target);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::KeyK),
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                // shift+K clears the path, K appends the current view two seconds after the last key
+                if self.modifiers.shift_key() {
+                    self.camera_path = CameraPath::default();
+                    println!("cleared the camera path");
+                    return;
+                }
+                let camera = &self.state.as_ref().unwrap().cam_manager.camera;
+                let key = Bookmark::from_camera("", camera);
+                self.camera_path.push(key, 2.);
+                println!("camera path has {} keys", self.camera_path.keys.len());
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                // renders the keyframed path, without one a path through the saved bookmarks and
+                // with fewer than two of them a turntable of the current view
+                let state = self.state.as_mut().unwrap();
+                let bookmarks = CameraPath::from_bookmarks(&self.bookmarks, 2.);
+                let path = if self.camera_path.keys.len() >= 2 {
+                    self.camera_path.clone()
+                } else if bookmarks.keys.len() >= 2 {
+                    bookmarks
+                } else {
+                    CameraPath::turntable(&state.cam_manager.camera, 8.)
+                };
+                if let Err(e) = state.render_path(&path, 30., 16, "frames") {
+                    println!("Could not render the camera path: {}", e);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key:
+                            PhysicalKey::Code(code @ (KeyCode::BracketLeft | KeyCode::BracketRight)),
+                        ..
+                    },
                 ..
             } => {
+                // ] opens the lens for a shallower depth of field, [ closes it down to a pinhole.
+                // Steps are relative to the focus distance so they suit any scene scale
                 let state = self.state.as_mut().unwrap();
-                if y > 0. {
-                    let forward = (&state.cam_manager.camera.focus
-                        - &state.cam_manager.camera.position)
-                        .normalize();
-                    state.cam_manager.camera.position += forward * 1.01;
+                let camera = &mut state.cam_manager.camera;
+                let smallest = 0.002 * camera.sharp_distance();
+                camera.aperture = if code == KeyCode::BracketRight {
+                    (camera.aperture * 1.5).max(smallest)
+                } else if camera.aperture / 1.5 < smallest {
+                    0.
                 } else {
-                    let forward = (&state.cam_manager.camera.focus
-                        - &state.cam_manager.camera.position)
-                        .normalize();
-                    state.cam_manager.camera.position -= forward * 0.99;
+                    camera.aperture / 1.5
+                };
+                println!("aperture radius {}", camera.aperture);
+                state.update_camera();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::KeyM),
+                        ..
+                    },
+                ..
+            } => {
+                // switch between the procedural sky, the loaded environment map and daylight
+                let state = self.state.as_mut().unwrap();
+                state.environment_manager.next_background();
+                state.update_environment();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key:
+                            PhysicalKey::Code(
+                                code @ (KeyCode::Comma
+                                | KeyCode::Period
+                                | KeyCode::Minus
+                                | KeyCode::Equal),
+                            ),
+                        ..
+                    },
+                ..
+            } => {
+                // , and . turn the environment about the vertical, - and = dim and brighten it
+                let state = self.state.as_mut().unwrap();
+                let environment = &mut state.environment_manager;
+                match code {
+                    KeyCode::Comma => environment.rotation -= 15f32.to_radians(),
+                    KeyCode::Period => environment.rotation += 15f32.to_radians(),
+                    KeyCode::Minus => environment.intensity /= 1.25,
+                    _ => environment.intensity *= 1.25,
                 }
-                state.cam_manager.camera.update_cam_info(&state.size);
-                state.queue.write_buffer(
-                    &state.cam_manager.camera_buffer,
-                    0,
-                    bytemuck::cast_slice(&[state.cam_manager.camera.cam_info]),
+                environment.rotation = environment.rotation.rem_euclid(std::f32::consts::TAU);
+                println!(
+                    "environment rotation {:.0} degrees, intensity {}",
+                    environment.rotation.to_degrees(),
+                    environment.intensity
                 );
-                let _ = state.render();
+                state.update_environment();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key:
+                            PhysicalKey::Code(
+                                code @ (KeyCode::ArrowUp
+                                | KeyCode::ArrowDown
+                                | KeyCode::ArrowLeft
+                                | KeyCode::ArrowRight),
+                            ),
+                        ..
+                    },
+                ..
+            } => {
+                // the up and down arrows raise and lower the daylight sun, left and right turn it
+                let state = self.state.as_mut().unwrap();
+                let daylight = &mut state.environment_manager.daylight;
+                match code {
+                    KeyCode::ArrowUp => daylight.elevation += 5f32.to_radians(),
+                    KeyCode::ArrowDown => daylight.elevation -= 5f32.to_radians(),
+                    KeyCode::ArrowLeft => daylight.azimuth -= 10f32.to_radians(),
+                    _ => daylight.azimuth += 10f32.to_radians(),
+                }
+                daylight.elevation = daylight
+                    .elevation
+                    .clamp(-10f32.to_radians(), std::f32::consts::FRAC_PI_2);
+                daylight.azimuth = daylight.azimuth.rem_euclid(std::f32::consts::TAU);
+                println!(
+                    "sun elevation {:.0} degrees, azimuth {:.0} degrees",
+                    daylight.elevation.to_degrees(),
+                    daylight.azimuth.to_degrees()
+                );
+                state.update_environment();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::KeyO),
+                        ..
+                    },
+                ..
+            } => {
+                // switch between perspective and orthographic projection
+                let state = self.state.as_mut().unwrap();
+                state.cam_manager.camera.toggle_projection();
+                state.update_camera();
             }
             WindowEvent::KeyboardInput {
                 event:
@@ -241,70 +663,88 @@ impl<'a> winit::application::ApplicationHandler for App<'a> {
                 self.movements[0] = false;
             }
             WindowEvent::KeyboardInput {
-                event: KeyEvent { physical_key, .. },
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
                 ..
             } => {
+                // outside fly mode W and S zoom through the orbit controller, A, D, Z and X pan by
+                // a fortieth of the view, so its focus follows
                 let state = self.state.as_mut().unwrap();
-                match physical_key {
-                    PhysicalKey::Code(KeyCode::KeyW) => {
-                        state.cam_manager.camera.movement(
-                            Direction::Forward,
-                            &self.movements[0],
-                            2.5,
-                        );
-                    }
-                    PhysicalKey::Code(KeyCode::KeyS) => {
-                        state.cam_manager.camera.movement(
-                            Direction::Backward,
-                            &self.movements[0],
-                            2.5,
-                        );
-                    }
-                    PhysicalKey::Code(KeyCode::KeyA) => {
-                        state
-                            .cam_manager
-                            .camera
-                            .movement(Direction::Left, &self.movements[0], 2.5);
-                    }
-                    PhysicalKey::Code(KeyCode::KeyD) => {
-                        state.cam_manager.camera.movement(
-                            Direction::Right,
-                            &self.movements[0],
-                            2.5,
-                        );
-                    }
-                    PhysicalKey::Code(KeyCode::KeyZ) => {
-                        state
-                            .cam_manager
-                            .camera
-                            .movement(Direction::Up, &self.movements[0], 2.5);
-                    }
-                    PhysicalKey::Code(KeyCode::KeyX) => {
-                        state
-                            .cam_manager
-                            .camera
-                            .movement(Direction::Down, &self.movements[0], 2.5);
-                    }
-                    _ => {
-                        return;
-                    }
+                let camera = &mut state.cam_manager.camera;
+                let height = state.size.height as f32;
+                let step = height / 40.;
+                match code {
+                    KeyCode::KeyW => self.orbit.dolly(camera, 0.25),
+                    KeyCode::KeyS => self.orbit.dolly(camera, -0.25),
+                    KeyCode::KeyA => self.orbit.pan(camera, step, 0., height),
+                    KeyCode::KeyD => self.orbit.pan(camera, -step, 0., height),
+                    KeyCode::KeyZ => self.orbit.pan(camera, 0., step, height),
+                    KeyCode::KeyX => self.orbit.pan(camera, 0., -step, height),
+                    _ => return,
                 }
-                state.cam_manager.camera.update_cam_info(&state.size);
-                let state = self.state.as_ref().unwrap();
-                state.queue.write_buffer(
-                    &state.cam_manager.camera_buffer,
-                    0,
-                    bytemuck::cast_slice(&[self
-                        .state
-                        .as_ref()
-                        .unwrap()
-                        .cam_manager
-                        .camera
-                        .cam_info]),
-                );
-                let _ = self.state.as_mut().unwrap().render();
+                self.transition = None;
+                state.update_camera();
             }
             _ => {}
         }
     }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: DeviceEvent,
+    ) {
+        // raw mouse motion keeps working while the cursor is grabbed
+        if let (true, DeviceEvent::MouseMotion { delta }, Some(state)) =
+            (self.fly_mode, event, self.state.as_mut())
+        {
+            self.fly.look(
+                &mut state.cam_manager.camera,
+                delta.0 as f32,
+                delta.1 as f32,
+            );
+            state.update_camera();
+        }
+    }
+
+    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        if self.fly_mode || self.transition.is_some() {
+            let now = Instant::now();
+            // long stalls, e.g. while the window is dragged, should not teleport the camera
+            let dt = self
+                .last_frame
+                .map_or(0., |last| (now - last).as_secs_f32())
+                .min(0.1);
+            self.last_frame = Some(now);
+            let camera = &mut state.cam_manager.camera;
+            let mut moved = false;
+            if let Some(transition) = self.transition.as_mut() {
+                moved = transition.update(camera, dt);
+                if transition.finished() {
+                    self.transition = None;
+                }
+            }
+            if self.fly_mode {
+                moved |= self.fly.update(camera, dt);
+            }
+            if moved {
+                state.update_camera();
+                return;
+            }
+        } else {
+            self.last_frame = None;
+        }
+        // keep refining a still image until it has converged
+        if !state.accumulation_manager.converged() {
+            let _ = state.render();
+        }
+    }
 }
@@ -1,6 +1,20 @@
-use std::{path::PathBuf, str::FromStr};
+use std::io;
+use std::path::PathBuf;
 
+use crate::rendering::accumulation::AccumulationManager;
+use crate::rendering::bookmark::CameraPath;
+use crate::rendering::environment::{Background, EnvironmentManager};
+use crate::rendering::material::{Material, MaterialManager};
+use crate::utils::bvh::create_bvh;
+use crate::utils::compare::{compare, heatmap_shading};
+use crate::utils::image::write_ppm;
 use crate::utils::mesh::{load_mesh, Mesh};
+use crate::utils::slice::Plane;
+
+// bind groups of main.wgsl, and the storage buffers its fragment stage reads: spheres, lights,
+// rect lights, four BVH buffers, materials and the environment CDF
+pub const BIND_GROUPS: u32 = 7;
+pub const STORAGE_BUFFERS: u32 = 9;
 
 pub struct State<'a> {
     pub surface: wgpu::Surface<'a>,
@@ -12,6 +26,11 @@ pub struct State<'a> {
     pub sphere_manager: crate::rendering::sphere::SphereManager,
     pub light_manager: crate::rendering::light::LightManager,
     pub bvh_manager: crate::utils::bvh::BvhManager,
+    pub overlay_manager: crate::rendering::overlay::OverlayManager,
+    pub accumulation_manager: AccumulationManager,
+    pub material_manager: MaterialManager,
+    pub environment_manager: EnvironmentManager,
+    pub sun_light: usize, // index of the daylight sky's sun in the light manager
     pub render_pipeline: wgpu::RenderPipeline,
 }
 
@@ -19,6 +38,7 @@ impl<'a> State<'a> {
     pub async fn new(
         window: std::sync::Arc<winit::window::Window>,
         camera: Option<crate::rendering::camera::Camera>,
+        mesh_path: &PathBuf,
     ) -> Self {
         // Creating a default camera for perspective view if default not provided by the user
         let camera = camera.unwrap_or_default();
@@ -41,12 +61,34 @@ impl<'a> State<'a> {
             )
             .await
             .unwrap();
+        // the ray tracing pipeline uses more than the default four bind groups and eight storage
+        // buffers in the fragment stage, and the sampling table of an 8K environment map is larger
+        // than the default 128 MiB binding
+        let limits = adapter.limits();
+        if limits.max_bind_groups < BIND_GROUPS
+            || limits.max_storage_buffers_per_shader_stage < STORAGE_BUFFERS
+        {
+            panic!(
+                "needs {} bind groups and {} storage buffers per stage, the GPU has {} and {}",
+                BIND_GROUPS,
+                STORAGE_BUFFERS,
+                limits.max_bind_groups,
+                limits.max_storage_buffers_per_shader_stage
+            );
+        }
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     memory_hints: wgpu::MemoryHints::Performance,
                     required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::default(),
+                    required_limits: wgpu::Limits {
+                        max_bind_groups: limits.max_bind_groups,
+                        max_storage_buffers_per_shader_stage: limits
+                            .max_storage_buffers_per_shader_stage,
+                        max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size,
+                        max_buffer_size: limits.max_buffer_size,
+                        ..Default::default()
+                    },
                     label: None,
                 },
                 None,
@@ -84,18 +126,35 @@ impl<'a> State<'a> {
             vec![crate::rendering::sphere::Sphere::default()],
         );
 
+        let environment_manager = EnvironmentManager::new(&device, &queue);
+        // the sun of the daylight sky, off until that background is shown
         let light_manager = crate::rendering::light::LightManager::new(
             &device,
             &queue,
             vec![crate::rendering::light::Light {
-                position: [-10., 10., 0.],
                 is_valid: 0,
-                ..Default::default()
+                ..environment_manager.daylight.sun_light()
             }],
         );
-        let path = PathBuf::from_str("assets/monkey.stl").unwrap();
-        let mesh = load_mesh(&path).unwrap();
+        let mesh = load_mesh(mesh_path).unwrap();
         let bvh_manager = crate::utils::bvh::BvhManager::new(&device, &queue, &mesh);
+        let accumulation_manager = AccumulationManager::new(&device, &window_size);
+        let material_manager = MaterialManager::new(&device, &queue, vec![Material::default()]);
+
+        // horizontal cross-section through the middle of the mesh, shown with the overlay toggle
+        let mut overlay_manager = crate::rendering::overlay::OverlayManager::new(
+            &device,
+            config.format,
+            &cam_manager.bind_group_layout,
+        );
+        let bounds = bvh_manager.bvh.nodes[0].bounds;
+        let center = [
+            (bounds[0] + bounds[3]) / 2.,
+            (bounds[1] + bounds[4]) / 2.,
+            (bounds[2] + bounds[5]) / 2.,
+        ];
+        let contours = bvh_manager.bvh.slice(&Plane::new(center, [0., 1., 0.]));
+        overlay_manager.set_polylines(&device, &queue, &contours);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -105,6 +164,9 @@ impl<'a> State<'a> {
                     &sphere_manager.bind_group_layout,
                     &light_manager.bind_group_layout,
                     &bvh_manager.bind_group_layout,
+                    &accumulation_manager.bind_group_layout,
+                    &material_manager.bind_group_layout,
+                    &environment_manager.bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -124,11 +186,19 @@ impl<'a> State<'a> {
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
                 entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
+                // the running average for the screen, and at full precision for the next frame
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: config.format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: AccumulationManager::FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                ],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             }),
 
@@ -167,7 +237,84 @@ impl<'a> State<'a> {
             render_pipeline,
             light_manager,
             bvh_manager,
+            overlay_manager,
+            accumulation_manager,
+            material_manager,
+            environment_manager,
+            sun_light: 0,
+        }
+    }
+
+    // colors the mesh by its distance to `reference` and prints the deviation statistics
+    pub fn compare_with(&mut self, reference: &Mesh) {
+        let reference = create_bvh(reference, 25);
+        let comparison = compare(&self.bvh_manager.bvh, &reference, 100_000);
+        println!(
+            "Hausdorff distance: {} (mesh to reference {}, reference to mesh {})",
+            comparison.hausdorff, comparison.forward.hausdorff, comparison.backward.hausdorff
+        );
+        println!(
+            "RMS deviation: {} (mesh to reference {}, reference to mesh {})",
+            comparison.rms, comparison.forward.rms, comparison.backward.rms
+        );
+        let shading = heatmap_shading(&comparison.face_deviation, comparison.hausdorff);
+        self.bvh_manager.set_face_shading(&self.queue, shading);
+        self.accumulation_manager.reset();
+    }
+
+    // uploads the camera after it moved and redraws, starting a new average
+    pub fn update_camera(&mut self) {
+        self.cam_manager.camera.update_cam_info(&self.size);
+        self.cam_manager.update_buffers(&self.queue);
+        self.accumulation_manager.reset();
+        let _ = self.render();
+    }
+
+    // uploads the background settings after a change and redraws, starting a new average. The
+    // sun follows the daylight sky and shines only while it is shown
+    pub fn update_environment(&mut self) {
+        self.environment_manager.update_buffers(&self.queue);
+        let environment = &self.environment_manager;
+        let mut sun = environment.daylight.sun_light();
+        sun.intensity *= environment.intensity;
+        if environment.background != Background::Daylight {
+            sun.is_valid = 0;
         }
+        self.light_manager
+            .set_light(self.sun_light, sun, &self.queue);
+        self.accumulation_manager.reset();
+        let _ = self.render();
+    }
+
+    // bounds of the mesh and every sphere, for framing the whole scene
+    pub fn scene_bounds(&self) -> [f32; 6] {
+        let mut bounds = self.bvh_manager.bvh.nodes[0].bounds;
+        for sphere in self.sphere_manager.spheres.iter().filter(|s| s.radius > 0.) {
+            let sphere_bounds = sphere.bounds();
+            for k in 0..3 {
+                bounds[k] = bounds[k].min(sphere_bounds[k]);
+                bounds[k + 3] = bounds[k + 3].max(sphere_bounds[k + 3]);
+            }
+        }
+        bounds
+    }
+
+    // bounds of the object under the pixel at (x, y): the sphere that was hit or the whole mesh
+    pub fn pick_bounds(&self, x: f32, y: f32) -> Option<[f32; 6]> {
+        let ray = self.cam_manager.camera.ray(x, y, &self.size);
+        let mesh = self
+            .bvh_manager
+            .bvh
+            .intersect(&ray)
+            .map(|hit| (hit.distance, self.bvh_manager.bvh.nodes[0].bounds));
+        self.sphere_manager
+            .spheres
+            .iter()
+            .filter(|s| s.radius > 0.)
+            .filter_map(|s| s.intersect(&ray).map(|t| (t, s.bounds())))
+            .chain(mesh)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, bounds)| bounds)
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -176,9 +323,11 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.accumulation_manager.resize(&self.device, &new_size);
         }
     }
 
+    // traces one more frame into the running average and shows it
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let drawable = self.surface.get_current_texture()?;
         let image_view = drawable
@@ -187,9 +336,27 @@ impl<'a> State<'a> {
         let mut command_encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.draw(&mut command_encoder, &image_view);
+        self.queue.submit(std::iter::once(command_encoder.finish()));
+        self.accumulation_manager.end_frame();
+        drawable.present();
+        Ok(())
+    }
+
+    fn draw(&self, command_encoder: &mut wgpu::CommandEncoder, image_view: &wgpu::TextureView) {
+        let (previous_frames, accumulation_view) =
+            self.accumulation_manager.begin_frame(&self.queue);
         {
             let color_attachament = wgpu::RenderPassColorAttachment {
-                view: &image_view,
+                view: image_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            };
+            let accumulation_attachment = wgpu::RenderPassColorAttachment {
+                view: &accumulation_view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
@@ -198,7 +365,7 @@ impl<'a> State<'a> {
             };
             let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
-                color_attachments: &[Some(color_attachament)],
+                color_attachments: &[Some(color_attachament), Some(accumulation_attachment)],
                 depth_stencil_attachment: None,
                 occlusion_query_set: None,
                 timestamp_writes: None,
@@ -208,10 +375,151 @@ impl<'a> State<'a> {
             render_pass.set_bind_group(1, &self.sphere_manager.bind_group, &[]);
             render_pass.set_bind_group(2, &self.light_manager.bind_group, &[]);
             render_pass.set_bind_group(3, &self.bvh_manager.bind_group, &[]);
+            render_pass.set_bind_group(4, previous_frames, &[]);
+            render_pass.set_bind_group(5, &self.material_manager.bind_group, &[]);
+            render_pass.set_bind_group(6, &self.environment_manager.bind_group, &[]);
             render_pass.draw(0..6, 0..1);
         }
+        // the overlay goes on top of the average without being accumulated
+        let color_attachament = wgpu::RenderPassColorAttachment {
+            view: image_view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+        };
+        let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(color_attachament)],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        self.overlay_manager
+            .draw(&mut render_pass, &self.cam_manager.bind_group);
+    }
+
+    // averages `passes` frames of the current view in an offscreen texture of the window's size
+    // and reads it back as tightly packed RGBA, top row first
+    pub fn render_image(&mut self, passes: u32) -> Result<Vec<u8>, String> {
+        let format = self.config.format;
+        if format.block_copy_size(None) != Some(4) {
+            return Err(format!("Cannot read back {:?} images", format));
+        }
+        let (width, height) = (self.size.width, self.size.height);
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        // buffer rows have to be aligned to 256 bytes
+        let row_bytes = 4 * width;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row_bytes * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.accumulation_manager.reset();
+        for _ in 1..passes {
+            let mut command_encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            self.draw(&mut command_encoder, &view);
+            self.queue.submit(std::iter::once(command_encoder.finish()));
+            self.accumulation_manager.end_frame();
+        }
+        let mut command_encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.draw(&mut command_encoder, &view);
+        command_encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            extent,
+        );
         self.queue.submit(std::iter::once(command_encoder.finish()));
-        drawable.present();
-        Ok(())
+        self.accumulation_manager.end_frame();
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+        let bgra = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_row_bytes as usize) {
+                for pixel in row[..row_bytes as usize].chunks_exact(4) {
+                    if bgra {
+                        pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                    } else {
+                        pixels.extend_from_slice(pixel);
+                    }
+                }
+            }
+        }
+        buffer.unmap();
+        Ok(pixels)
+    }
+
+    // renders `path` at `fps` into numbered PPM files in `directory`, averaging `passes` frames
+    // for each image, then restores the camera
+    pub fn render_path(
+        &mut self,
+        path: &CameraPath,
+        fps: f32,
+        passes: u32,
+        directory: &str,
+    ) -> io::Result<()> {
+        std::fs::create_dir_all(directory)?;
+        let camera = self.cam_manager.camera.clone();
+        let frames = (path.duration() * fps).ceil() as usize + 1;
+        let mut result = Ok(());
+        for frame in 0..frames {
+            path.apply(&mut self.cam_manager.camera, frame as f32 / fps);
+            self.cam_manager.camera.update_cam_info(&self.size);
+            self.cam_manager.update_buffers(&self.queue);
+            let file_path = format!("{}/frame_{:05}.ppm", directory, frame);
+            result = self
+                .render_image(passes)
+                .map_err(io::Error::other)
+                .and_then(|pixels| {
+                    write_ppm(&pixels, self.size.width, self.size.height, &file_path)
+                });
+            if result.is_err() {
+                break;
+            }
+        }
+        self.cam_manager.camera = camera;
+        self.update_camera();
+        if result.is_ok() {
+            println!("saved {} frames to {}", frames, directory);
+        }
+        result
     }
 }
@@ -2,13 +2,40 @@ pub mod application;
 pub mod rendering;
 pub mod utils;
 
+use std::path::PathBuf;
+
 use winit::event_loop::{ControlFlow, EventLoop};
 
+use crate::rendering::sky::Daylight;
+
 fn main() {
     env_logger::init();
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
+    // renderer [mesh.stl] [--compare reference.stl] [--environment sky.hdr]
+    //          [--daylight day_of_year solar_hour latitude]
     let mut app = crate::application::app::App::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--compare" {
+            app.reference_path = args.next().map(PathBuf::from);
+        } else if arg == "--environment" {
+            app.environment_path = args.next().map(PathBuf::from);
+        } else if arg == "--daylight" {
+            // exactly three numbers, anything else stops with a usage error
+            let day = args.next().and_then(|a| a.parse::<u32>().ok());
+            let hour = args.next().and_then(|a| a.parse::<f32>().ok());
+            let latitude = args.next().and_then(|a| a.parse::<f32>().ok());
+            let (Some(day), Some(hour), Some(latitude)) = (day, hour, latitude) else {
+                eprintln!("usage: --daylight day_of_year solar_hour latitude, e.g. 172 14.5 48.2");
+                std::process::exit(2);
+            };
+            let turbidity = Daylight::default().turbidity;
+            app.daylight = Some(Daylight::from_date(day, hour, latitude, turbidity));
+        } else {
+            app.mesh_path = Some(PathBuf::from(arg));
+        }
+    }
     let _ = event_loop.run_app(&mut app);
 }
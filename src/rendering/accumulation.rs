@@ -0,0 +1,145 @@
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FrameInfo {
+    pub frame: u32, // frames already averaged into the previous texture
+    pub padding_: [u32; 3],
+}
+
+// running average of the traced frames in two float textures. Each frame reads the average so
+// far from one and writes the updated average into the other, then they swap
+pub struct AccumulationManager {
+    pub textures: [wgpu::Texture; 2],
+    pub bind_groups: [wgpu::BindGroup; 2], // bind_groups[i] reads textures[i]
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub frame_buffer: wgpu::Buffer,
+    pub frame: u32,
+    pub max_frames: u32, // stops refining a still image after this many frames
+    pub current: usize,  // texture holding the latest average
+}
+
+impl AccumulationManager {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+    pub fn new(device: &wgpu::Device, size: &winit::dpi::PhysicalSize<u32>) -> Self {
+        let frame_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[FrameInfo::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let (textures, bind_groups) =
+            Self::create_textures(device, &bind_group_layout, &frame_buffer, size);
+        Self {
+            textures,
+            bind_groups,
+            bind_group_layout,
+            frame_buffer,
+            frame: 0,
+            max_frames: 1024,
+            current: 0,
+        }
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        frame_buffer: &wgpu::Buffer,
+        size: &winit::dpi::PhysicalSize<u32>,
+    ) -> ([wgpu::Texture; 2], [wgpu::BindGroup; 2]) {
+        let textures = [0, 1].map(|_| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: None,
+                size: wgpu::Extent3d {
+                    width: size.width.max(1),
+                    height: size.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        });
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(
+                            &textures[i].create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: frame_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        (textures, bind_groups)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: &winit::dpi::PhysicalSize<u32>) {
+        (self.textures, self.bind_groups) =
+            Self::create_textures(device, &self.bind_group_layout, &self.frame_buffer, size);
+        self.reset();
+    }
+
+    // starts a new average, after anything that changes the image
+    pub fn reset(&mut self) {
+        self.frame = 0;
+    }
+
+    pub fn converged(&self) -> bool {
+        self.frame >= self.max_frames
+    }
+
+    // uploads the frame index and returns the bind group reading the latest average with the
+    // view the next one gets written to
+    pub fn begin_frame(&self, queue: &wgpu::Queue) -> (&wgpu::BindGroup, wgpu::TextureView) {
+        let info = FrameInfo {
+            frame: self.frame,
+            ..Default::default()
+        };
+        queue.write_buffer(&self.frame_buffer, 0, bytemuck::cast_slice(&[info]));
+        let target =
+            self.textures[1 - self.current].create_view(&wgpu::TextureViewDescriptor::default());
+        (&self.bind_groups[self.current], target)
+    }
+
+    pub fn end_frame(&mut self) {
+        self.current = 1 - self.current;
+        self.frame = self.frame.saturating_add(1);
+    }
+}
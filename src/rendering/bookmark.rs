@@ -0,0 +1,359 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::rendering::camera::{Camera, Projection};
+use crate::rendering::controller::CameraPose;
+use crate::utils::quaternion::Quat;
+use crate::utils::vector::Vec3;
+
+// a saved camera state
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Vec3<f32>,
+    pub focus: Vec3<f32>,
+    pub up: Vec3<f32>,
+    pub projection: Projection,
+}
+
+impl Bookmark {
+    pub fn from_camera(name: &str, camera: &Camera) -> Self {
+        Self {
+            name: name.to_string(),
+            position: camera.position,
+            focus: camera.focus,
+            up: camera.up,
+            projection: camera.projection,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.focus = self.focus;
+        camera.up = self.up;
+        camera.projection = self.projection;
+    }
+
+    pub fn camera(&self, base: &Camera) -> Camera {
+        let mut camera = base.clone();
+        self.apply(&mut camera);
+        camera
+    }
+}
+
+// bookmarks in slots 0 to 9, one per number key
+#[derive(Clone, Debug, Default)]
+pub struct Bookmarks {
+    pub slots: [Option<Bookmark>; 10],
+}
+
+impl Bookmarks {
+    // an empty name keeps the name the slot already has, so names edited in the bookmarks file
+    // survive saving the view again, and falls back to "view N" for a new slot
+    pub fn save(&mut self, slot: usize, name: &str, camera: &Camera) {
+        let name = match (name, &self.slots[slot]) {
+            ("", Some(old)) if !old.name.is_empty() => old.name.clone(),
+            ("", _) => format!("view {}", slot),
+            _ => name.to_string(),
+        };
+        self.slots[slot] = Some(Bookmark::from_camera(&name, camera));
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Bookmark> {
+        self.slots.get(slot).and_then(|bookmark| bookmark.as_ref())
+    }
+
+    // one line per bookmark: slot, position, focus and up, the projection with its field of
+    // view or height, and the name, which may contain spaces
+    pub fn write(&self, file_path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(file_path)?);
+        writeln!(file, "# slot position focus up projection name")?;
+        for (slot, bookmark) in self.slots.iter().enumerate() {
+            let Some(b) = bookmark else {
+                continue;
+            };
+            let (kind, value) = match b.projection {
+                Projection::Perspective { fov_y } => ("perspective", fov_y),
+                Projection::Orthographic { height } => ("orthographic", height),
+            };
+            write!(file, "{}", slot)?;
+            for v in [b.position, b.focus, b.up] {
+                write!(file, " {} {} {}", v.v[0], v.v[1], v.v[2])?;
+            }
+            writeln!(file, " {} {} {}", kind, value, b.name)?;
+        }
+        file.flush()
+    }
+
+    pub fn read(file_path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(file_path).map_err(|e| e.to_string())?;
+        let mut bookmarks = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |what: &str| format!("{}:{}: {}", file_path, number + 1, what);
+            let fields: Vec<&str> = line.splitn(13, ' ').collect();
+            if fields.len() < 12 {
+                return Err(error("expected at least 12 fields"));
+            }
+            let slot: usize = fields[0].parse().map_err(|_| error("invalid slot"))?;
+            if slot >= bookmarks.slots.len() {
+                return Err(error("slot out of range"));
+            }
+            let mut values = [0f32; 10];
+            for (value, field) in values
+                .iter_mut()
+                .zip(fields[1..10].iter().chain(&fields[11..12]))
+            {
+                *value = field.parse().map_err(|_| error("invalid number"))?;
+            }
+            let projection = match fields[10] {
+                "perspective" => Projection::Perspective { fov_y: values[9] },
+                "orthographic" => Projection::Orthographic { height: values[9] },
+                _ => return Err(error("unknown projection")),
+            };
+            bookmarks.slots[slot] = Some(Bookmark {
+                name: fields.get(12).unwrap_or(&"").to_string(),
+                position: Vec3::new(values[0], values[1], values[2]),
+                focus: Vec3::new(values[3], values[4], values[5]),
+                up: Vec3::new(values[6], values[7], values[8]),
+                projection,
+            });
+        }
+        Ok(bookmarks)
+    }
+}
+
+// keyframes at increasing times in seconds. Positions follow a Catmull-Rom spline through the
+// keys, orientations are slerped between neighbouring keys
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    pub keys: Vec<(f32, Bookmark)>,
+}
+
+impl CameraPath {
+    // appends a key `seconds` after the last one
+    pub fn push(&mut self, bookmark: Bookmark, seconds: f32) {
+        let time = self.keys.last().map_or(0., |(time, _)| time + seconds);
+        self.keys.push((time, bookmark));
+    }
+
+    // the saved bookmarks in slot order, `seconds` apart
+    pub fn from_bookmarks(bookmarks: &Bookmarks, seconds: f32) -> Self {
+        let mut path = Self::default();
+        for bookmark in bookmarks.slots.iter().flatten() {
+            path.push(bookmark.clone(), seconds);
+        }
+        path
+    }
+
+    // a full turn around the focus about `camera.up` in `seconds`
+    pub fn turntable(camera: &Camera, seconds: f32) -> Self {
+        let keys = 12;
+        let mut path = Self::default();
+        for i in 0..keys {
+            let angle = std::f32::consts::TAU * i as f32 / keys as f32;
+            let rotation = Quat::from_axis_angle(&camera.up, angle);
+            let mut key = camera.clone();
+            key.position = camera.focus + rotation.rotate(&(camera.position - camera.focus));
+            path.push(Bookmark::from_camera("", &key), seconds / keys as f32);
+        }
+        // closing on the exact first key makes the spline wrap around smoothly
+        path.push(path.keys[0].1.clone(), seconds / keys as f32);
+        path
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0., |(time, _)| *time)
+    }
+
+    // moves the camera to the path's state at `time`, clamped to the first and last key
+    pub fn apply(&self, camera: &mut Camera, time: f32) {
+        let Some((first, _)) = self.keys.first() else {
+            return;
+        };
+        let time = time.clamp(*first, self.duration());
+        let segment = self
+            .keys
+            .windows(2)
+            .position(|w| time <= w[1].0)
+            .unwrap_or(0);
+        let last = self.keys.len() - 1;
+        let key = |i: usize| &self.keys[i.min(last)].1;
+        let (k1, k2) = (key(segment), key(segment + 1));
+        let span = self.keys[(segment + 1).min(last)].0 - self.keys[segment].0;
+        let t = if span > 0. {
+            (time - self.keys[segment].0) / span
+        } else {
+            0.
+        };
+
+        // the first and last segments repeat their end key as the outer control point, unless
+        // the path ends where it started and wraps around
+        let looped = last > 1 && self.keys[0].1.position == self.keys[last].1.position;
+        let p0 = match segment {
+            0 if looped => key(last - 1).position,
+            _ => key(segment.saturating_sub(1)).position,
+        };
+        let p3 = match segment + 1 {
+            end if looped && end == last => key(1).position,
+            _ => key(segment + 2).position,
+        };
+        let position = catmull_rom(&p0, &k1.position, &k2.position, &p3, t);
+
+        let pose = CameraPose::from_camera(&k1.camera(camera))
+            .interpolate(&CameraPose::from_camera(&k2.camera(camera)), t);
+        let forward = pose.rotation.rotate(&Vec3::new(0., 0., 1.));
+        camera.position = position;
+        camera.focus = position + forward * pose.distance;
+        camera.up = pose.rotation.rotate(&Vec3::new(0., 1., 0.));
+        camera.projection = match (k1.projection, k2.projection) {
+            (Projection::Perspective { fov_y: a }, Projection::Perspective { fov_y: b }) => {
+                Projection::Perspective {
+                    fov_y: a + (b - a) * t,
+                }
+            }
+            (Projection::Orthographic { height: a }, Projection::Orthographic { height: b }) => {
+                Projection::Orthographic {
+                    height: a + (b - a) * t,
+                }
+            }
+            (a, b) => {
+                if t < 0.5 {
+                    a
+                } else {
+                    b
+                }
+            }
+        };
+    }
+}
+
+// uniform Catmull-Rom segment from p1 to p2
+pub fn catmull_rom(
+    p0: &Vec3<f32>,
+    p1: &Vec3<f32>,
+    p2: &Vec3<f32>,
+    p3: &Vec3<f32>,
+    t: f32,
+) -> Vec3<f32> {
+    let (p0, p1, p2, p3) = (*p0, *p1, *p2, *p3);
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let a = p1 * 2.;
+    let b = (p2 - p0) * t;
+    let c = (p0 * 2. - p1 * 5. + p2 * 4. - p3) * t2;
+    let d = (p1 * 3. - p0 - p2 * 3. + p3) * t3;
+    (a + b + c + d) * 0.5
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rendering::camera::{Camera, Projection};
+    use crate::rendering::controller::CameraPose;
+    use crate::utils::vector::Vec3;
+
+    use super::{catmull_rom, Bookmark, Bookmarks, CameraPath};
+
+    fn camera() -> Camera {
+        Camera {
+            position: Vec3::new(0., 0., -10.),
+            focus: Vec3::new(1., 2., 3.),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bookmark_file_test() {
+        let mut bookmarks = Bookmarks::default();
+        let mut camera = camera();
+        bookmarks.save(1, "front view", &camera);
+        camera.projection = Projection::Orthographic { height: 4.5 };
+        camera.position = Vec3::new(-3., 0.25, 7.);
+        bookmarks.save(7, "", &camera);
+        assert_eq!(bookmarks.get(7).unwrap().name, "view 7");
+        bookmarks.save(1, "", &camera);
+        assert_eq!(bookmarks.get(1).unwrap().name, "front view");
+        bookmarks.save(1, "front view", &self::camera());
+
+        let file_path = std::env::temp_dir().join("bookmark_file_test.views");
+        let file_path = file_path.to_str().unwrap();
+        bookmarks.write(file_path).unwrap();
+        let read = Bookmarks::read(file_path).unwrap();
+        assert_eq!(read.slots, bookmarks.slots);
+        assert_eq!(read.get(1).unwrap().name, "front view");
+        assert!(read.get(0).is_none());
+
+        std::fs::write(file_path, "3 1 2 3\n").unwrap();
+        assert!(Bookmarks::read(file_path).is_err());
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn camera_path_test() {
+        // the spline passes through its control points
+        let points = [
+            Vec3::new(0., 0., 0.),
+            Vec3::new(1., 2., 0.),
+            Vec3::new(3., 2., 1.),
+            Vec3::new(4., 0., 1.),
+        ];
+        let [p0, p1, p2, p3] = &points;
+        assert!(catmull_rom(p0, p1, p2, p3, 0.).approx_eq(p1, 1e-6));
+        assert!(catmull_rom(p0, p1, p2, p3, 1.).approx_eq(p2, 1e-6));
+
+        // sampling at a key time gives that key
+        let mut path = CameraPath::default();
+        let mut camera = camera();
+        for (i, p) in points.iter().enumerate() {
+            camera.position = *p;
+            camera.projection = Projection::Perspective {
+                fov_y: 30. + i as f32,
+            };
+            path.push(Bookmark::from_camera("", &camera), 2.);
+        }
+        assert_eq!(path.duration(), 6.);
+        for (time, key) in path.keys.clone() {
+            path.apply(&mut camera, time);
+            assert!(camera.position.approx_eq(&key.position, 1e-5));
+            assert!(camera.focus.approx_eq(&key.focus, 1e-3));
+            assert_eq!(camera.projection, key.projection);
+        }
+        path.apply(&mut camera, 3.);
+        assert_eq!(camera.projection, Projection::Perspective { fov_y: 31.5 });
+
+        // a turntable keeps the distance to the focus and ends where it started
+        let camera = self::camera();
+        let distance = (camera.focus - camera.position).length();
+        let turntable = CameraPath::turntable(&camera, 4.);
+        let mut moved = camera.clone();
+        for i in 0..=40 {
+            turntable.apply(&mut moved, i as f32 * 0.1);
+            assert!(((moved.focus - moved.position).length() - distance).abs() < 1e-3);
+            assert!(((moved.position - camera.focus).length() - distance).abs() < 0.01 * distance);
+        }
+        assert!(moved.position.approx_eq(&camera.position, 1e-3));
+
+        // a path through the saved bookmarks reaches each of them in slot order
+        let mut bookmarks = Bookmarks::default();
+        let mut camera = self::camera();
+        for (slot, p) in [(2, [4., 0., -8.]), (5, [-6., 3., 0.]), (9, [0., 9., 2.])] {
+            camera.position = Vec3::from(p);
+            bookmarks.save(slot, "", &camera);
+        }
+        let path = CameraPath::from_bookmarks(&bookmarks, 1.5);
+        assert_eq!(path.duration(), 3.);
+        for (i, slot) in [2, 5, 9].into_iter().enumerate() {
+            let bookmark = bookmarks.get(slot).unwrap();
+            path.apply(&mut camera, 1.5 * i as f32);
+            assert!(camera.position.approx_eq(&bookmark.position, 1e-5));
+            assert!(camera.focus.approx_eq(&bookmark.focus, 1e-3));
+            // the path keeps the up vector perpendicular to the view, as a pose does
+            let mut expected = bookmark.camera(&camera);
+            CameraPose::from_camera(&expected).apply(&mut expected);
+            assert!(camera.up.approx_eq(&expected.up, 1e-4));
+        }
+    }
+}
@@ -1,9 +1,19 @@
-use std::f32::consts::PI;
-
 use wgpu::util::DeviceExt;
 
+use crate::utils::matrix::Mat4;
+use crate::utils::random::Pcg;
+use crate::utils::ray::Ray;
 use crate::utils::vector::Vec3;
 
+// vertical field of view of new cameras, in degrees
+pub const DEFAULT_FOV_Y: f32 = 35.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective { fov_y: f32 },   // vertical field of view in degrees
+    Orthographic { height: f32 }, // visible height in world units
+}
+
 #[derive(Clone)]
 pub struct Camera {
     pub position: Vec3<f32>,
@@ -11,11 +21,21 @@ pub struct Camera {
     pub focus: Vec3<f32>,
     pub near: f32,
     pub far: f32,
-    pub view_angle: f32,
-    pub zoom: f32,
+    pub projection: Projection,
+    pub zoom: f32,           // divides the field of view or the orthographic height
+    pub aperture: f32,       // lens radius, 0 renders everything sharp
+    pub focus_distance: f32, // distance of the sharp plane, 0 focuses on `focus`
     pub cam_info: [[f32; 4]; 4],
 }
 
+// layout of the camera uniform shared by main.wgsl and overlay.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub cam_info: [[f32; 4]; 4],
+    pub projection: [f32; 4], // 1 for orthographic else 0, far, aperture, focus distance
+}
+
 pub enum Direction {
     Forward,
     Backward,
@@ -34,7 +54,11 @@ impl Default for Camera {
             far: 10000000.,
             near: 0.0001,
             zoom: 1.,
-            view_angle: 35. * PI / 180.,
+            projection: Projection::Perspective {
+                fov_y: DEFAULT_FOV_Y,
+            },
+            aperture: 0.,
+            focus_distance: 0.,
             cam_info: [[0.; 4]; 4],
             //[cam_pos[0], cam_pos[1], cam_pos[2], view_port_center[0]]
             //[view_port_center[1], view_port_center[2], pixel_width, pixel_height]
@@ -51,7 +75,7 @@ impl Camera {
         focus: Option<Vec3<f32>>,
         near: Option<f32>,
         far: Option<f32>,
-        view_angle: Option<f32>,
+        projection: Option<Projection>,
         zoom: Option<f32>,
     ) -> Self {
         Self {
@@ -60,37 +84,144 @@ impl Camera {
             focus: focus.unwrap_or(Vec3::new(0.0, 0.0, 1.0)),
             near: near.unwrap_or(0.1),
             far: far.unwrap_or(10000000000.),
-            view_angle: view_angle.unwrap_or(30.),
+            projection: projection.unwrap_or(Projection::Perspective {
+                fov_y: DEFAULT_FOV_Y,
+            }),
             zoom: zoom.unwrap_or(1.),
+            aperture: 0.,
+            focus_distance: 0.,
             cam_info: [[0.; 4]; 4],
         }
     }
 
-    pub fn movement(&mut self, direction: Direction, rotate: &bool, amount: f32) {
-        let factor = (&self.focus - &self.position).length() as f32 / 1000. * amount;
+    // half of the visible height at `distance` in front of the camera
+    pub fn half_height_at(&self, distance: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective { fov_y } => {
+                (fov_y.to_radians() / (2. * self.zoom)).tan() * distance
+            }
+            Projection::Orthographic { height } => height / (2. * self.zoom),
+        }
+    }
+
+    // switches between perspective and orthographic while keeping the size of the focus plane
+    // on screen. Going back to perspective uses the default field of view and moves the camera
+    // along the view direction to match
+    pub fn toggle_projection(&mut self) {
+        let offset = self.position - self.focus;
+        let distance = offset.length();
+        let half_height = self.half_height_at(distance);
+        match self.projection {
+            Projection::Perspective { .. } => {
+                self.projection = Projection::Orthographic {
+                    height: 2. * half_height * self.zoom,
+                };
+            }
+            Projection::Orthographic { .. } => {
+                self.projection = Projection::Perspective {
+                    fov_y: DEFAULT_FOV_Y,
+                };
+                let new_distance = half_height / self.half_height_at(1.);
+                if distance > 0. {
+                    self.position = self.focus + offset * (new_distance / distance);
+                }
+            }
+        }
+    }
 
-        let movement_direction = match direction {
-            Direction::Backward => (&self.focus - &self.position).normalize() * -factor,
-            Direction::Forward => (&self.focus - &self.position).normalize() * factor,
-            Direction::Down => self.up.clone().normalize() * -factor,
-            Direction::Up => self.up.clone().normalize() * factor,
-            Direction::Left => (&self.focus - &self.position).cross(&self.up).normalize() * -factor,
-            Direction::Right => (&self.focus - &self.position).cross(&self.up).normalize() * factor,
+    // keeps the view direction and moves the camera so the bounding sphere of `bounds`
+    // ([min x, min y, min z, max x, max y, max z]) fills the view
+    pub fn frame(&mut self, bounds: &[f32; 6], aspect_ratio: f32) {
+        let min = Vec3::new(bounds[0], bounds[1], bounds[2]);
+        let max = Vec3::new(bounds[3], bounds[4], bounds[5]);
+        let center = (min + max) * 0.5;
+        let radius = ((max - min).length() * 0.5).max(1e-3);
+        let mut forward = (self.focus - self.position).normalize();
+        if forward.v.iter().any(|c| !c.is_finite()) {
+            forward = Vec3::new(0., 0., 1.);
+        }
+        let distance = match self.projection {
+            Projection::Perspective { fov_y } => {
+                // the narrower of the vertical and horizontal half angles decides
+                let half_y = fov_y.to_radians() / (2. * self.zoom);
+                let half_x = (half_y.tan() * aspect_ratio).atan();
+                radius / half_y.min(half_x).sin()
+            }
+            Projection::Orthographic { .. } => {
+                self.projection = Projection::Orthographic {
+                    height: 2. * radius * (1. / aspect_ratio).max(1.) * self.zoom,
+                };
+                // rays start at the camera, so it has to stay outside of the sphere
+                2. * radius
+            }
         };
-        let new_position = &self.position + &movement_direction;
-        if !rotate {
-            self.focus += movement_direction;
+        self.focus = center;
+        self.position = center - forward * distance;
+    }
+
+    pub fn sharp_distance(&self) -> f32 {
+        if self.focus_distance > 0. {
+            self.focus_distance
         } else {
-            match direction {
-                Direction::Up | Direction::Down => {
-                    self.up = (&self.focus - &new_position)
-                        .cross(&(self.up).cross(&(&self.focus - &self.position)).normalize())
-                        .normalize();
-                }
-                _ => {}
+            (self.focus - self.position).length()
+        }
+    }
+
+    // ray through the pixel at (x, y), measured from the top left corner, through the center of
+    // the lens. Needs `update_cam_info` for the current size
+    pub fn ray(&self, x: f32, y: f32, size: &winit::dpi::PhysicalSize<u32>) -> Ray {
+        self.lens_ray(x, y, size, [0.5, 0.5])
+    }
+
+    // the ray fs_main traces for a sample of `pixel`, drawing the jitter within the pixel and then
+    // the lens position from `rng` in the same order as the shader
+    pub fn sample_ray(
+        &self,
+        pixel: [u32; 2],
+        size: &winit::dpi::PhysicalSize<u32>,
+        rng: &mut Pcg,
+    ) -> Ray {
+        let [jx, jy] = rng.next_vec2();
+        let x = pixel[0] as f32 + jx;
+        let y = pixel[1] as f32 + jy;
+        let lens = match self.projection {
+            Projection::Perspective { .. } if self.aperture > 0. => rng.next_vec2(),
+            _ => [0.5, 0.5],
+        };
+        self.lens_ray(x, y, size, lens)
+    }
+
+    // the ray main.wgsl traces for the pixel at (x, y) and the lens sample `sample` in [0, 1)².
+    // With an aperture the ray starts on the lens disk and passes through the point the center
+    // ray hits on the sharp plane
+    pub fn lens_ray(
+        &self,
+        x: f32,
+        y: f32,
+        size: &winit::dpi::PhysicalSize<u32>,
+        sample: [f32; 2],
+    ) -> Ray {
+        let info = &self.cam_info;
+        let cam_pos = Vec3::new(info[0][0], info[0][1], info[0][2]);
+        let view_port_center = Vec3::new(info[0][3], info[1][0], info[1][1]);
+        let right = Vec3::new(info[2][2], info[2][3], info[3][0]);
+        let down = Vec3::new(info[3][1], info[3][2], info[3][3]);
+        let u = (2. * x / size.width as f32 - 1.) * info[2][0];
+        let v = (2. * y / size.height as f32 - 1.) * info[2][1];
+        let pixel_center = view_port_center + right * u + down * v;
+        match self.projection {
+            Projection::Perspective { .. } if self.aperture > 0. => {
+                let direction = (pixel_center - cam_pos).normalize();
+                let forward = (view_port_center - cam_pos).normalize();
+                let focal_point =
+                    cam_pos + direction * (self.sharp_distance() / direction.dot(&forward));
+                let [lx, ly] = concentric_disk(sample);
+                let origin = cam_pos + (right * lx + down * ly) * self.aperture;
+                Ray::new(origin, focal_point - origin)
             }
+            Projection::Perspective { .. } => Ray::new(cam_pos, pixel_center - cam_pos),
+            Projection::Orthographic { .. } => Ray::new(pixel_center, view_port_center - cam_pos),
         }
-        self.position = new_position;
     }
 
     pub fn update_cam_info(&mut self, size: &winit::dpi::PhysicalSize<u32>) {
@@ -98,23 +229,67 @@ impl Camera {
         self.cam_info[0][0] = self.position.v[0];
         self.cam_info[0][1] = self.position.v[1];
         self.cam_info[0][2] = self.position.v[2];
-        self.cam_info[2][1] = (self.view_angle / (2. * self.zoom)).tan() * self.near;
+        // one pixel in the shader's [0, 2] screen coordinates, the extent of the per-sample jitter
+        self.cam_info[1][2] = 2. / size.width.max(1) as f32;
+        self.cam_info[1][3] = 2. / size.height.max(1) as f32;
+        // perspective rays pass through a viewport at the near distance, orthographic rays start
+        // on a viewport of the visible size and all run along the view direction
+        self.cam_info[2][1] = match self.projection {
+            Projection::Perspective { .. } => self.half_height_at(self.near),
+            Projection::Orthographic { .. } => self.half_height_at(0.),
+        };
         self.cam_info[2][0] = aspect_ratio * self.cam_info[2][1];
-        let z = (&self.focus - &self.position).normalize();
-        let x = self.up.cross(&z).normalize();
+        // the rows of the view matrix are the camera axes, its x axis points the other way than
+        // the shader's screen x
+        let view = Mat4::look_at(&self.position, &self.focus, &self.up);
+        let axis = |r: usize| Vec3::from([0, 1, 2].map(|c| view.row(r)[c]));
+        let z = -axis(2);
+        let x = -axis(0);
         self.cam_info[2][2] = x.v[0];
         self.cam_info[2][3] = x.v[1];
         self.cam_info[3][0] = x.v[2];
-        let y = z.cross(&x).normalize();
+        let y = axis(1);
         self.cam_info[3][1] = y.v[0];
         self.cam_info[3][2] = y.v[1];
         self.cam_info[3][3] = y.v[2];
 
-        let view_port_center = &self.position + z * self.near;
+        let view_port_center = self.position + z * self.near;
         self.cam_info[0][3] = view_port_center.v[0];
         self.cam_info[1][0] = view_port_center.v[1];
         self.cam_info[1][1] = view_port_center.v[2];
     }
+
+    pub fn uniform(&self) -> CameraUniform {
+        let orthographic = matches!(self.projection, Projection::Orthographic { .. });
+        CameraUniform {
+            cam_info: self.cam_info,
+            projection: [
+                orthographic as u32 as f32,
+                self.far,
+                self.aperture,
+                self.sharp_distance(),
+            ],
+        }
+    }
+}
+
+// maps the unit square onto the unit disk keeping areas even, the middle of the square lands in
+// the middle of the disk. Same as `concentric_disk` in main.wgsl
+pub fn concentric_disk(sample: [f32; 2]) -> [f32; 2] {
+    let a = 2. * sample[0] - 1.;
+    let b = 2. * sample[1] - 1.;
+    if a == 0. && b == 0. {
+        return [0., 0.];
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f32::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    [r * theta.cos(), r * theta.sin()]
 }
 
 pub struct CamManager {
@@ -135,14 +310,14 @@ impl CamManager {
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&[camera.cam_info]),
+            contents: bytemuck::cast_slice(&[camera.uniform()]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -174,7 +349,149 @@ impl CamManager {
         queue.write_buffer(
             &self.camera_buffer,
             0,
-            bytemuck::cast_slice(&[self.camera.cam_info]),
+            bytemuck::cast_slice(&[self.camera.uniform()]),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::vector::Vec3;
+
+    use crate::utils::random::Pcg;
+    use crate::utils::ray::Ray;
+
+    use super::{concentric_disk, Camera, Projection, DEFAULT_FOV_Y};
+
+    #[test]
+    fn projection_test() {
+        let mut camera = Camera {
+            position: Vec3::new(0., 0., -10.),
+            focus: Vec3::new(0., 0., 0.),
+            ..Default::default()
+        };
+        // the field of view is in degrees, at 90 degrees the visible height equals twice the distance
+        camera.projection = Projection::Perspective { fov_y: 90. };
+        assert!((camera.half_height_at(10.) - 10.).abs() < 1e-4);
+        camera.projection = Projection::Perspective {
+            fov_y: DEFAULT_FOV_Y,
+        };
+        let half_height = camera.half_height_at(10.);
+
+        // switching keeps the size of the focus plane, and switching back restores the view
+        camera.toggle_projection();
+        assert!(matches!(camera.projection, Projection::Orthographic { .. }));
+        assert!((camera.half_height_at(0.) - half_height).abs() < 1e-4);
+        assert!((camera.half_height_at(1000.) - half_height).abs() < 1e-4);
+        camera.toggle_projection();
+        assert_eq!(
+            camera.projection,
+            Projection::Perspective {
+                fov_y: DEFAULT_FOV_Y
+            }
+        );
+        assert!(camera.position.approx_eq(&Vec3::new(0., 0., -10.), 1e-3));
+
+        camera.update_cam_info(&winit::dpi::PhysicalSize::new(200, 100));
+        assert!((camera.cam_info[2][0] - 2. * camera.cam_info[2][1]).abs() < 1e-6);
+        // screen x runs along up × forward and screen y along forward × x
+        let info = camera.cam_info;
+        let forward = (camera.focus - camera.position).normalize();
+        let right = camera.up.cross(&forward).normalize();
+        assert!(Vec3::new(info[2][2], info[2][3], info[3][0]).approx_eq(&right, 1e-5));
+        let down = Vec3::new(info[3][1], info[3][2], info[3][3]);
+        assert!(down.approx_eq(&forward.cross(&right), 1e-5));
+        assert_eq!(camera.uniform().projection[0], 0.);
+    }
+
+    #[test]
+    fn frame_test() {
+        let size = winit::dpi::PhysicalSize::new(200, 100);
+        let bounds = [4., 4., 4., 6., 6., 6.];
+        let center = Vec3::new(5., 5., 5.);
+        let radius = 3f32.sqrt();
+        let mut camera = Camera {
+            position: Vec3::new(0., 0., -10.),
+            focus: Vec3::new(0., 0., 0.),
+            near: 0.1,
+            ..Default::default()
+        };
+        camera.frame(&bounds, 2.);
+        camera.update_cam_info(&size);
+        assert!(camera.focus.approx_eq(&center, 1e-5));
+        assert!((camera.position - center)
+            .normalize()
+            .approx_eq(&Vec3::new(0., 0., -1.), 1e-5));
+
+        // the center pixel looks at the focus, the middle of the top edge grazes the bounding
+        // sphere since the view is wider than high
+        let distance_to = |ray: &Ray| {
+            let d = center - ray.origin;
+            (d - ray.direction * d.dot(&ray.direction)).length()
+        };
+        assert!(distance_to(&camera.ray(100., 50., &size)) < 1e-4);
+        assert!((distance_to(&camera.ray(100., 0., &size)) - radius).abs() < 1e-3);
+
+        // orthographic rays run parallel and the visible height is the sphere's diameter
+        camera.toggle_projection();
+        camera.frame(&bounds, 2.);
+        camera.update_cam_info(&size);
+        assert!((camera.half_height_at(0.) - radius).abs() < 1e-4);
+        let top = camera.ray(100., 0., &size);
+        assert!(top
+            .direction
+            .approx_eq(&camera.ray(0., 100., &size).direction, 1e-6));
+        assert!((distance_to(&top) - radius).abs() < 1e-3);
+    }
+
+    #[test]
+    fn lens_test() {
+        let size = winit::dpi::PhysicalSize::new(200, 100);
+        let mut camera = Camera {
+            position: Vec3::new(0., 0., -10.),
+            focus: Vec3::new(0., 0., 0.),
+            near: 0.1,
+            ..Default::default()
+        };
+        camera.update_cam_info(&size);
+        let pinhole = camera.ray(30., 70., &size);
+        camera.aperture = 0.5;
+        assert!(camera
+            .ray(30., 70., &size)
+            .origin
+            .approx_eq(&pinhole.origin, 1e-6));
+
+        // every lens sample of a pixel meets the pinhole ray on the sharp plane, 10 units away
+        let sharp = pinhole.at(10. / pinhole.direction.v[2]);
+        for sample in [[0., 0.], [0.9, 0.1], [0.3, 0.75], [0.5, 1.]] {
+            let ray = camera.lens_ray(30., 70., &size, sample);
+            assert!((ray.origin - camera.position).length() <= camera.aperture + 1e-5);
+            let t = (sharp - ray.origin).dot(&ray.direction);
+            assert!(ray.at(t).approx_eq(&sharp, 1e-4));
+        }
+
+        // the disk mapping stays inside the unit disk and reaches its edge
+        let mut max_radius = 0f32;
+        for i in 0..=20 {
+            for j in 0..=20 {
+                let [x, y] = concentric_disk([i as f32 / 20., j as f32 / 20.]);
+                max_radius = max_radius.max((x * x + y * y).sqrt());
+            }
+        }
+        assert!((max_radius - 1.).abs() < 1e-5);
+        assert_eq!(camera.uniform().projection[2..], [0.5, 10.]);
+
+        // sampled rays are reproducible and stay within their pixel's cone
+        let mut rng = Pcg::new([30, 70], 4, 1);
+        let ray = camera.sample_ray([30, 70], &size, &mut rng.clone());
+        assert_eq!(
+            ray.origin,
+            camera.sample_ray([30, 70], &size, &mut rng).origin
         );
+        camera.aperture = 0.;
+        let ray = camera.sample_ray([30, 70], &size, &mut Pcg::new([30, 70], 4, 1));
+        let pixel_angle = 2. * camera.cam_info[2][1] / camera.near / size.height as f32;
+        let center = camera.ray(30.5, 70.5, &size);
+        assert!(ray.direction.angle(&center.direction) < pixel_angle);
     }
 }
@@ -0,0 +1,465 @@
+use crate::rendering::camera::{Camera, Direction, Projection};
+use crate::utils::matrix::Mat3;
+use crate::utils::quaternion::Quat;
+use crate::utils::vector::Vec3;
+
+// orbits the camera around its focus point. Rotations are applied to the position and the up
+// vector together, so the view never flips when passing over the poles
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitController {
+    pub rotate_speed: f32, // radians per dragged pixel
+    pub zoom_speed: f32,   // distance factor is exp(zoom_speed) per wheel line
+    pub min_distance: f32,
+    pub max_distance: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            rotate_speed: 0.005,
+            zoom_speed: 0.1,
+            min_distance: 1e-4,
+            max_distance: 1e7,
+        }
+    }
+}
+
+// orthonormal frame of the view direction and the directions pointing right and up on screen.
+// The ray setup in main.wgsl puts `camera.up` toward the bottom of the image, so the screen's
+// up is its opposite
+pub fn camera_frame(camera: &Camera) -> (Vec3<f32>, Vec3<f32>, Vec3<f32>) {
+    let forward = (camera.focus - camera.position).normalize();
+    let right = camera.up.cross(&forward).normalize();
+    let up = right.cross(&forward);
+    (forward, right, up)
+}
+
+impl OrbitController {
+    // trackball rotation for a drag of (dx, dy) pixels, right and down being positive
+    pub fn rotate(&self, camera: &mut Camera, dx: f32, dy: f32) {
+        let (_, right, up) = camera_frame(camera);
+        // dragging right swings the camera left around the pivot, dragging down tilts it up
+        let rotation = Quat::from_axis_angle(&up, -dx * self.rotate_speed)
+            * Quat::from_axis_angle(&right, -dy * self.rotate_speed);
+        let offset = rotation.rotate(&(camera.position - camera.focus));
+        camera.position = camera.focus + offset;
+        camera.up = -rotation.rotate(&up).normalize();
+    }
+
+    // moves position and focus in the view plane so the point under the cursor follows it,
+    // `viewport_height` in pixels
+    pub fn pan(&self, camera: &mut Camera, dx: f32, dy: f32, viewport_height: f32) {
+        let (forward, right, up) = camera_frame(camera);
+        let distance = (camera.focus - camera.position).dot(&forward);
+        let half_height = camera.half_height_at(distance);
+        let world_per_pixel = 2. * half_height / viewport_height.max(1.);
+        let offset = (right * -dx + up * dy) * world_per_pixel;
+        camera.position += offset;
+        camera.focus += offset;
+    }
+
+    // moves toward the focus for positive `lines`, the distance changes by a constant factor per
+    // line so zooming feels the same close up and far away. Orthographic views shrink the
+    // visible height instead, since moving does not change their size on screen
+    pub fn dolly(&self, camera: &mut Camera, lines: f32) {
+        if let Projection::Orthographic { height } = camera.projection {
+            let new_height = (height * (-lines * self.zoom_speed).exp())
+                .clamp(self.min_distance, self.max_distance);
+            camera.projection = Projection::Orthographic { height: new_height };
+            return;
+        }
+        let offset = camera.position - camera.focus;
+        let distance = offset.length();
+        if distance <= 0. {
+            return;
+        }
+        let new_distance = (distance * (-lines * self.zoom_speed).exp())
+            .clamp(self.min_distance, self.max_distance);
+        camera.position = camera.focus + offset * (new_distance / distance);
+    }
+}
+
+// first person movement: the mouse turns the view in place and held keys move the camera at
+// `speed` units per second, easing in and out with `acceleration`
+#[derive(Clone, Copy, Debug)]
+pub struct FlyController {
+    pub speed: f32,
+    pub acceleration: f32, // rate the velocity approaches the held direction, per second
+    pub look_speed: f32,   // radians per pixel of mouse motion
+    pub held: [bool; 6],   // forward, backward, left, right, up, down
+    pub velocity: Vec3<f32>,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            speed: 5.,
+            acceleration: 10.,
+            look_speed: 0.002,
+            held: [false; 6],
+            velocity: Vec3::new(0., 0., 0.),
+        }
+    }
+}
+
+impl FlyController {
+    pub fn set_held(&mut self, direction: Direction, pressed: bool) {
+        let index = match direction {
+            Direction::Forward => 0,
+            Direction::Backward => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+            Direction::Up => 4,
+            Direction::Down => 5,
+        };
+        self.held[index] = pressed;
+    }
+
+    // stops at once, e.g. when leaving fly mode
+    pub fn stop(&mut self) {
+        self.held = [false; 6];
+        self.velocity = Vec3::new(0., 0., 0.);
+    }
+
+    // yaw around `camera.up` and pitch around the screen's right axis for a mouse motion of
+    // (dx, dy) pixels. Pitch stops short of straight up or down so yaw keeps a defined axis
+    pub fn look(&self, camera: &mut Camera, dx: f32, dy: f32) {
+        let (forward, right, _) = camera_frame(camera);
+        let up = camera.up.normalize();
+        let distance = (camera.focus - camera.position).length();
+        let max_pitch = 89f32.to_radians();
+        // pitch measured from the horizon, positive when looking toward the screen's up
+        let pitch = (-forward.dot(&up)).clamp(-1., 1.).asin();
+        let new_pitch = (pitch - dy * self.look_speed).clamp(-max_pitch, max_pitch);
+        let rotation = Quat::from_axis_angle(&up, dx * self.look_speed)
+            * Quat::from_axis_angle(&right, new_pitch - pitch);
+        camera.focus = camera.position + rotation.rotate(&forward) * distance;
+    }
+
+    // advances the camera by `dt` seconds, false when it did not move
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        let (forward, right, _) = camera_frame(camera);
+        let up = -camera.up.normalize();
+        let axes = [forward, -forward, -right, right, up, -up];
+        let mut target = Vec3::new(0., 0., 0.);
+        for (axis, _) in axes.iter().zip(self.held).filter(|(_, held)| *held) {
+            target += axis;
+        }
+        if target.squared_length() > 0. {
+            target = target.normalize() * self.speed;
+        }
+        // frame rate independent easing toward the target velocity
+        let blend = 1. - (-self.acceleration * dt).exp();
+        self.velocity = self.velocity.lerp(&target, blend);
+        if self.velocity.squared_length() < 1e-10 {
+            self.velocity = Vec3::new(0., 0., 0.);
+            return false;
+        }
+        let offset = self.velocity * dt;
+        camera.position += offset;
+        camera.focus += offset;
+        true
+    }
+}
+
+// views along the axes of the startup camera, which looks along +z with +y as `camera.up`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StandardView {
+    Front,
+    Top,
+    Right,
+    Isometric,
+}
+
+impl StandardView {
+    // direction from the focus to the camera and the matching `camera.up`. The screen's up is
+    // -y in the front view, so the top view looks from there
+    pub fn orientation(&self) -> (Vec3<f32>, Vec3<f32>) {
+        match self {
+            StandardView::Front => (Vec3::new(0., 0., -1.), Vec3::new(0., 1., 0.)),
+            StandardView::Top => (Vec3::new(0., -1., 0.), Vec3::new(0., 0., -1.)),
+            StandardView::Right => (Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)),
+            StandardView::Isometric => {
+                let direction = Vec3::new(1., -1., -1.).normalize();
+                let up = Vec3::new(0., 1., 0.);
+                (direction, (up - direction * up.dot(&direction)).normalize())
+            }
+        }
+    }
+
+    // turns the camera around its focus, keeping the distance
+    pub fn apply(&self, camera: &mut Camera) {
+        let (direction, up) = self.orientation();
+        let distance = (camera.position - camera.focus).length();
+        camera.position = camera.focus + direction * distance;
+        camera.up = up;
+    }
+}
+
+// everything a transition interpolates: the orientation as a rotation of the (right,
+// `camera.up`, forward) frame, the focus, its distance and the orthographic height
+#[derive(Clone, Copy, Debug)]
+pub struct CameraPose {
+    pub rotation: Quat,
+    pub focus: Vec3<f32>,
+    pub distance: f32,
+    pub height: Option<f32>,
+}
+
+impl CameraPose {
+    pub fn from_camera(camera: &Camera) -> Self {
+        let forward = (camera.focus - camera.position).normalize();
+        let up = (camera.up - forward * camera.up.dot(&forward)).normalize();
+        let right = up.cross(&forward);
+        let height = match camera.projection {
+            Projection::Orthographic { height } => Some(height),
+            Projection::Perspective { .. } => None,
+        };
+        Self {
+            rotation: Quat::from_mat3(&Mat3::from_cols(&right, &up, &forward)),
+            focus: camera.focus,
+            distance: (camera.focus - camera.position).length(),
+            height,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        let forward = self.rotation.rotate(&Vec3::new(0., 0., 1.));
+        camera.up = self.rotation.rotate(&Vec3::new(0., 1., 0.));
+        camera.focus = self.focus;
+        camera.position = self.focus - forward * self.distance;
+        if let (Some(height), Projection::Orthographic { .. }) = (self.height, camera.projection) {
+            camera.projection = Projection::Orthographic { height };
+        }
+    }
+
+    // slerps the orientation and interpolates the distance geometrically, so zooming far in or
+    // out runs at an even pace, a zero distance has no ratio and falls back to a linear step
+    pub fn interpolate(&self, other: &CameraPose, t: f32) -> CameraPose {
+        let geometric = |a: f32, b: f32| {
+            if a <= 0. || b <= 0. {
+                a + (b - a) * t
+            } else {
+                a * (b / a).powf(t)
+            }
+        };
+        let height = match (self.height, other.height) {
+            (Some(a), Some(b)) => Some(geometric(a, b)),
+            (a, b) => b.or(a),
+        };
+        CameraPose {
+            rotation: self.rotation.slerp(&other.rotation, t),
+            focus: self.focus.lerp(&other.focus, t),
+            distance: geometric(self.distance, other.distance),
+            height,
+        }
+    }
+}
+
+// eases the camera from one pose to another over `duration` seconds
+#[derive(Clone, Copy, Debug)]
+pub struct CameraTransition {
+    pub start: CameraPose,
+    pub end: CameraPose,
+    pub duration: f32,
+    pub elapsed: f32,
+}
+
+impl CameraTransition {
+    pub fn new(from: &Camera, to: &Camera, duration: f32) -> Self {
+        Self {
+            start: CameraPose::from_camera(from),
+            end: CameraPose::from_camera(to),
+            duration,
+            elapsed: 0.,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    // advances by `dt` seconds and moves the camera, false once the end pose was reached before
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        if self.finished() {
+            return false;
+        }
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+        let t = if self.duration > 0. {
+            self.elapsed / self.duration
+        } else {
+            1.
+        };
+        // smoothstep, starts and stops without a jolt
+        let t = t * t * (3. - 2. * t);
+        self.start.interpolate(&self.end, t).apply(camera);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rendering::camera::{Camera, Direction};
+    use crate::utils::vector::Vec3;
+
+    use super::{
+        camera_frame, CameraPose, CameraTransition, FlyController, OrbitController, StandardView,
+    };
+
+    fn camera() -> Camera {
+        Camera {
+            position: Vec3::new(0., 0., -10.),
+            focus: Vec3::new(1., 2., 3.),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn orbit_test() {
+        let orbit = OrbitController::default();
+        let mut camera = camera();
+        let distance = (camera.focus - camera.position).length();
+
+        // many drags in both directions keep the pivot distance and an orthonormal frame,
+        // also when passing over the poles
+        for i in 0..2000 {
+            orbit.rotate(&mut camera, (i % 7) as f32 - 2., 3.);
+            assert!(((camera.focus - camera.position).length() - distance).abs() < 1e-3);
+            let (forward, _, _) = camera_frame(&camera);
+            assert!(forward.dot(&camera.up).abs() < 1e-4);
+            assert!((camera.up.length() - 1.).abs() < 1e-4);
+        }
+        assert_eq!(camera.focus, Vec3::new(1., 2., 3.));
+
+        // a drag and its reverse end where they started
+        let mut camera = self::camera();
+        let start = camera.position;
+        orbit.rotate(&mut camera, 40., 0.);
+        orbit.rotate(&mut camera, -40., 0.);
+        assert!(camera.position.approx_eq(&start, 1e-3));
+    }
+
+    #[test]
+    fn pan_and_dolly_test() {
+        let orbit = OrbitController::default();
+        let mut camera = camera();
+        let direction = camera.focus - camera.position;
+        orbit.pan(&mut camera, 30., -12., 600.);
+        assert!((camera.focus - camera.position).approx_eq(&direction, 1e-4));
+        let (forward, _, _) = camera_frame(&camera);
+        assert!((camera.focus - Vec3::new(1., 2., 3.)).dot(&forward).abs() < 1e-4);
+
+        // panning by the viewport height moves the focus by the visible height at its distance
+        let mut camera = self::camera();
+        let distance = direction.length();
+        orbit.pan(&mut camera, 0., 600., 600.);
+        let visible = 2. * camera.half_height_at(distance);
+        assert!(((camera.focus - Vec3::new(1., 2., 3.)).length() - visible).abs() < 1e-3);
+
+        let mut camera = self::camera();
+        orbit.dolly(&mut camera, 3.);
+        orbit.dolly(&mut camera, -3.);
+        assert!(((camera.focus - camera.position).length() - distance).abs() < 1e-4);
+        orbit.dolly(&mut camera, 1000.);
+        assert!(((camera.focus - camera.position).length() - orbit.min_distance).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fly_test() {
+        let mut fly = FlyController::default();
+        let mut camera = camera();
+        let (forward, _, _) = camera_frame(&camera);
+
+        // holding forward for a second at 60 fps reaches full speed and covers a bit less than
+        // `speed` units while accelerating, the same distance at half the frame rate
+        fly.set_held(Direction::Forward, true);
+        let mut slow_fly = fly;
+        let mut slow_camera = camera.clone();
+        for _ in 0..60 {
+            assert!(fly.update(&mut camera, 1. / 60.));
+        }
+        for _ in 0..30 {
+            slow_fly.update(&mut slow_camera, 1. / 30.);
+        }
+        let travelled = (camera.position - Vec3::new(0., 0., -10.)).dot(&forward);
+        assert!(travelled > 0.8 * fly.speed && travelled < fly.speed);
+        assert!((fly.velocity.length() - fly.speed).abs() < 1e-3);
+        let slow = (slow_camera.position - Vec3::new(0., 0., -10.)).dot(&forward);
+        assert!((travelled - slow).abs() < 0.1);
+
+        // the view direction does not change while moving and comes to rest after release
+        assert!(camera_frame(&camera).0.approx_eq(&forward, 1e-5));
+        fly.set_held(Direction::Forward, false);
+        for _ in 0..120 {
+            fly.update(&mut camera, 1. / 60.);
+        }
+        assert!(!fly.update(&mut camera, 1. / 60.));
+
+        // looking far up clamps the pitch short of the up axis
+        fly.look(&mut camera, 0., -100_000.);
+        let (forward, _, _) = camera_frame(&camera);
+        let angle = forward.angle(&-camera.up).to_degrees();
+        assert!((angle - 1.).abs() < 0.01);
+        // turning a full circle returns to the same direction
+        let steps = (std::f32::consts::TAU / fly.look_speed / 100.).round();
+        let look_speed = std::f32::consts::TAU / (steps * 100.);
+        fly.look_speed = look_speed;
+        for _ in 0..steps as usize {
+            fly.look(&mut camera, 100., 0.);
+        }
+        assert!(camera_frame(&camera).0.approx_eq(&forward, 1e-3));
+    }
+
+    #[test]
+    fn transition_test() {
+        let start = camera();
+        let mut target = camera();
+        StandardView::Top.apply(&mut target);
+        target.focus = Vec3::new(-4., 0., 8.);
+        target.position = target.focus + Vec3::new(0., -30., 0.);
+
+        // a pose read back from the camera it was applied to is the same camera
+        let mut copy = camera();
+        CameraPose::from_camera(&target).apply(&mut copy);
+        assert!(copy.position.approx_eq(&target.position, 1e-4));
+        assert!(copy.up.approx_eq(&target.up, 1e-5));
+
+        // the transition moves in steps without jumps and stops on the target
+        let mut transition = CameraTransition::new(&start, &target, 0.5);
+        let mut camera = start.clone();
+        let mut last = camera.position;
+        let mut steps = 0;
+        while transition.update(&mut camera, 1. / 60.) {
+            assert!((camera.position - last).length() < 3.);
+            assert!(camera_frame(&camera).0.dot(&camera.up).abs() < 1e-4);
+            last = camera.position;
+            steps += 1;
+        }
+        assert!(transition.finished());
+        assert_eq!(steps, 30);
+        assert!(camera.position.approx_eq(&target.position, 1e-3));
+        assert!(camera.focus.approx_eq(&target.focus, 1e-4));
+        assert!(camera.up.approx_eq(&target.up, 1e-4));
+
+        // every standard view has a unit direction perpendicular to its up vector
+        for view in [
+            StandardView::Front,
+            StandardView::Top,
+            StandardView::Right,
+            StandardView::Isometric,
+        ] {
+            let (direction, up) = view.orientation();
+            assert!(direction.dot(&up).abs() < 1e-6);
+            assert!((direction.length() - 1.).abs() < 1e-6);
+        }
+
+        // a pose with zero distance to its focus still moves linearly away from it
+        let to = CameraPose::from_camera(&start);
+        let from = CameraPose { distance: 0., ..to };
+        for i in 0..=10 {
+            let pose = from.interpolate(&to, i as f32 / 10.);
+            assert!(pose.distance.is_finite());
+            assert!((pose.distance - to.distance * i as f32 / 10.).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::f32::consts::PI;
+
+use crate::rendering::sky::{Daylight, SkyInfo};
+use crate::utils::image::HdrImage;
+use crate::utils::vector::Vec3;
+
+// what rays leaving the scene see
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Background {
+    Clouds, // the procedural sky
+    Map,
+    Daylight, // the analytic clear sky and its sun
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EnvironmentInfo {
+    pub width: u32,
+    pub height: u32,
+    pub background: u32, // 0 for the clouds, 1 for the map, 2 for the daylight sky
+    pub padding_0: u32,
+    pub rotation: f32, // radians about +y
+    pub intensity: f32,
+    pub total: f32, // sum of the sampling weights, 0 when there is nothing to sample
+    pub padding_1: f32,
+}
+
+// equirectangular environment with tables for drawing directions in proportion to their
+// brightness. Pixel (x, y) is weighted by its luminance times the sine of its polar angle, the
+// solid angle it covers. Same mapping and sampling as in main.wgsl
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    pub image: HdrImage,
+    pub cdf: Vec<f32>, // over the rows (height + 1 entries), then within each row (width + 1 each)
+    pub total: f32,
+}
+
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+// rotates about +y
+fn rotate_y(direction: &Vec3<f32>, angle: f32) -> Vec3<f32> {
+    let (sin, cos) = angle.sin_cos();
+    let [x, y, z] = direction.v;
+    Vec3::new(x * cos + z * sin, y, z * cos - x * sin)
+}
+
+// u goes around +y starting and ending behind -z, v runs from +y down to -y
+pub fn direction_to_uv(direction: &Vec3<f32>, rotation: f32) -> [f32; 2] {
+    let d = rotate_y(direction, -rotation);
+    let u = 0.5 + d.v[0].atan2(-d.v[2]) / (2. * PI);
+    let v = d.v[1].clamp(-1., 1.).acos() / PI;
+    [u.rem_euclid(1.), v]
+}
+
+pub fn uv_to_direction(uv: [f32; 2], rotation: f32) -> Vec3<f32> {
+    let phi = (uv[0] - 0.5) * 2. * PI;
+    let (sin_theta, cos_theta) = (uv[1] * PI).sin_cos();
+    let d = Vec3::new(sin_theta * phi.sin(), cos_theta, -sin_theta * phi.cos());
+    rotate_y(&d, rotation)
+}
+
+// index i with cdf[i] <= value < cdf[i + 1], skipping empty intervals
+fn find_interval(cdf: &[f32], value: f32) -> usize {
+    let (mut low, mut high) = (0, cdf.len() - 2);
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if cdf[middle] <= value {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    low
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage) -> Result<Self, String> {
+        let (width, height) = (image.width as usize, image.height as usize);
+        if width == 0 || height == 0 || image.pixels.len() != width * height {
+            return Err(format!(
+                "environment map of {}x{} with {} pixels",
+                width,
+                height,
+                image.pixels.len()
+            ));
+        }
+        let mut cdf = vec![0.; height + 1 + height * (width + 1)];
+        let mut total = 0.;
+        for y in 0..height {
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * PI).sin();
+            let row = height + 1 + y * (width + 1);
+            for x in 0..width {
+                let weight = luminance(image.pixels[y * width + x]) * sin_theta;
+                cdf[row + x + 1] = cdf[row + x] + weight;
+            }
+            let row_total = cdf[row + width];
+            if row_total > 0. {
+                for value in &mut cdf[row + 1..=row + width] {
+                    *value /= row_total;
+                }
+            }
+            cdf[y + 1] = cdf[y] + row_total;
+            total += row_total;
+        }
+        if total > 0. {
+            for value in &mut cdf[1..=height] {
+                *value /= total;
+            }
+        }
+        Ok(Self { image, cdf, total })
+    }
+
+    // radiance seen in `direction`, without the intensity
+    pub fn radiance(&self, direction: &Vec3<f32>, rotation: f32) -> [f32; 3] {
+        let [x, y] = self.pixel(direction_to_uv(direction, rotation));
+        self.image.get(x, y)
+    }
+
+    fn pixel(&self, uv: [f32; 2]) -> [u32; 2] {
+        [
+            ((uv[0] * self.image.width as f32) as u32).min(self.image.width - 1),
+            ((uv[1] * self.image.height as f32) as u32).min(self.image.height - 1),
+        ]
+    }
+
+    // density per solid angle of `sample` returning `direction`
+    pub fn pdf(&self, direction: &Vec3<f32>, rotation: f32) -> f32 {
+        self.pdf_uv(direction_to_uv(direction, rotation))
+    }
+
+    // the same for the direction at `uv`, which keeps its precision right at the poles
+    fn pdf_uv(&self, uv: [f32; 2]) -> f32 {
+        let [x, y] = self.pixel(uv);
+        let sin_theta = (uv[1] * PI).sin();
+        if self.total <= 0. || sin_theta <= 0. {
+            return 0.;
+        }
+        let (width, height) = (self.image.width as f32, self.image.height as f32);
+        let row_sin = ((y as f32 + 0.5) / height * PI).sin();
+        let weight = luminance(self.image.get(x, y)) * row_sin;
+        weight / self.total * width * height / (2. * PI * PI * sin_theta)
+    }
+
+    // direction for two uniform numbers with its density per solid angle, None when the map is
+    // black
+    pub fn sample(&self, u: [f32; 2], rotation: f32) -> Option<(Vec3<f32>, f32)> {
+        if self.total <= 0. {
+            return None;
+        }
+        let (width, height) = (self.image.width as usize, self.image.height as usize);
+        let rows = &self.cdf[..=height];
+        let y = find_interval(rows, u[1]);
+        let v = (y as f32 + (u[1] - rows[y]) / (rows[y + 1] - rows[y])) / height as f32;
+        let start = height + 1 + y * (width + 1);
+        let columns = &self.cdf[start..=start + width];
+        let x = find_interval(columns, u[0]);
+        let u = (x as f32 + (u[0] - columns[x]) / (columns[x + 1] - columns[x])) / width as f32;
+        Some((uv_to_direction([u, v], rotation), self.pdf_uv([u, v])))
+    }
+}
+
+pub struct EnvironmentManager {
+    pub texture: wgpu::Texture,
+    pub cdf_buffer: wgpu::Buffer,
+    pub info_buffer: wgpu::Buffer,
+    pub sky_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub map: Option<EnvironmentMap>,
+    pub background: Background,
+    pub rotation: f32,
+    pub intensity: f32,
+    pub daylight: Daylight,
+}
+
+impl EnvironmentManager {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let info_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment Info Buffer"),
+            size: std::mem::size_of::<EnvironmentInfo>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sky_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sky Buffer"),
+            size: std::mem::size_of::<SkyInfo>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // a black pixel stands in until a map is loaded
+        let placeholder = EnvironmentMap::new(HdrImage {
+            width: 1,
+            height: 1,
+            pixels: vec![[0.; 3]],
+        })
+        .unwrap();
+        let (texture, cdf_buffer, bind_group) = Self::create_map_resources(
+            device,
+            queue,
+            &bind_group_layout,
+            [&info_buffer, &sky_buffer],
+            &placeholder,
+        );
+        let mut manager = Self {
+            texture,
+            cdf_buffer,
+            info_buffer,
+            sky_buffer,
+            bind_group,
+            bind_group_layout,
+            map: None,
+            background: Background::Clouds,
+            rotation: 0.,
+            intensity: 1.,
+            daylight: Daylight::default(),
+        };
+        manager.update_buffers(queue);
+        manager
+    }
+
+    fn create_map_resources(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        [info_buffer, sky_buffer]: [&wgpu::Buffer; 2],
+        map: &EnvironmentMap,
+    ) -> (wgpu::Texture, wgpu::Buffer, wgpu::BindGroup) {
+        let size = wgpu::Extent3d {
+            width: map.image.width,
+            height: map.image.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Environment Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let texels: Vec<[f32; 4]> = map
+            .image
+            .pixels
+            .iter()
+            .map(|&[r, g, b]| [r, g, b, 1.])
+            .collect();
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(16 * map.image.width),
+                rows_per_image: Some(map.image.height),
+            },
+            size,
+        );
+        let cdf_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment CDF Buffer"),
+            size: std::mem::size_of_val(map.cdf.as_slice()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&cdf_buffer, 0, bytemuck::cast_slice(&map.cdf));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Bind Group"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: cdf_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: sky_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        (texture, cdf_buffer, bind_group)
+    }
+
+    // uploads the map and its sampling tables and shows it
+    pub fn set_map(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        map: EnvironmentMap,
+    ) -> Result<(), String> {
+        let limit = device.limits().max_texture_dimension_2d;
+        if map.image.width > limit || map.image.height > limit {
+            return Err(format!(
+                "environment map of {}x{} exceeds the texture size limit of {}",
+                map.image.width, map.image.height, limit
+            ));
+        }
+        let cdf_size = std::mem::size_of_val(map.cdf.as_slice()) as u64;
+        let limits = device.limits();
+        let max_size = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        if cdf_size > max_size {
+            return Err(format!(
+                "sampling table of the {}x{} environment map needs {} bytes, the GPU binds {}",
+                map.image.width, map.image.height, cdf_size, max_size
+            ));
+        }
+        (self.texture, self.cdf_buffer, self.bind_group) = Self::create_map_resources(
+            device,
+            queue,
+            &self.bind_group_layout,
+            [&self.info_buffer, &self.sky_buffer],
+            &map,
+        );
+        self.map = Some(map);
+        self.background = Background::Map;
+        self.update_buffers(queue);
+        Ok(())
+    }
+
+    // switches to the next background, skipping the map until one is loaded
+    pub fn next_background(&mut self) {
+        self.background = match self.background {
+            Background::Clouds if self.map.is_some() => Background::Map,
+            Background::Clouds | Background::Map => Background::Daylight,
+            Background::Daylight => Background::Clouds,
+        };
+    }
+
+    pub fn update_buffers(&mut self, queue: &wgpu::Queue) {
+        let (width, height, total) = self.map.as_ref().map_or((1, 1, 0.), |map| {
+            (map.image.width, map.image.height, map.total)
+        });
+        let info = EnvironmentInfo {
+            width,
+            height,
+            background: match self.background {
+                Background::Clouds => 0,
+                Background::Map => 1,
+                Background::Daylight => 2,
+            },
+            rotation: self.rotation,
+            intensity: self.intensity,
+            total,
+            ..Default::default()
+        };
+        queue.write_buffer(&self.info_buffer, 0, bytemuck::cast_slice(&[info]));
+        queue.write_buffer(
+            &self.sky_buffer,
+            0,
+            bytemuck::cast_slice(&[self.daylight.sky_info()]),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+    use std::time::Instant;
+
+    use crate::utils::image::HdrImage;
+    use crate::utils::random::Pcg;
+    use crate::utils::vector::Vec3;
+
+    use super::{direction_to_uv, uv_to_direction, EnvironmentMap};
+
+    #[test]
+    fn environment_map_test() {
+        // the mapping round trips, with and without rotation
+        for rotation in [0., 1.3] {
+            for uv in [[0.5, 0.5], [0.1, 0.25], [0.9, 0.8]] {
+                let back = direction_to_uv(&uv_to_direction(uv, rotation), rotation);
+                assert!((back[0] - uv[0]).abs() < 1e-5 && (back[1] - uv[1]).abs() < 1e-5);
+            }
+        }
+        assert!(uv_to_direction([0.5, 0.5], 0.).approx_eq(&Vec3::new(0., 0., -1.), 1e-6));
+        assert!(uv_to_direction([0.5, 0.], 0.).approx_eq(&Vec3::new(0., 1., 0.), 1e-6));
+
+        // samples of a uniform map estimate the area of the sphere without bias
+        let uniform = EnvironmentMap::new(HdrImage {
+            width: 8,
+            height: 4,
+            pixels: vec![[0.5; 3]; 32],
+        })
+        .unwrap();
+        let empty = HdrImage {
+            width: 0,
+            height: 4,
+            pixels: vec![],
+        };
+        assert!(EnvironmentMap::new(empty).is_err());
+        let mut rng = Pcg::new([0, 0], 0, 0);
+        let count = 100_000;
+        let mut area = 0.;
+        for _ in 0..count {
+            let (direction, pdf) = uniform.sample(rng.next_vec2(), 0.3).unwrap();
+            assert!((direction.length() - 1.).abs() < 1e-5);
+            area += 1. / pdf;
+        }
+        area /= count as f32;
+        assert!((area - 4. * PI).abs() < 0.01 * 4. * PI, "{}", area);
+
+        // a bright patch gets the samples it deserves, and the estimate of the light arriving
+        // from the whole sphere matches the sum over the pixels
+        let (width, height) = (64, 32);
+        let mut pixels = vec![[0.1, 0.2, 0.3]; width * height];
+        for y in 10..13 {
+            for x in 40..44 {
+                pixels[y * width + x] = [500., 400., 300.];
+            }
+        }
+        let map = EnvironmentMap::new(HdrImage {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        })
+        .unwrap();
+        let mut expected = 0.;
+        for y in 0..height {
+            let theta = (y as f32 + 0.5) / height as f32 * PI;
+            let solid_angle = 2. * PI * PI / (width * height) as f32 * theta.sin();
+            for x in 0..width {
+                expected += map.image.get(x as u32, y as u32)[0] * solid_angle;
+            }
+        }
+        let start = Instant::now();
+        let count = 100_000;
+        let (mut estimate, mut in_patch) = (0., 0);
+        for _ in 0..count {
+            let (direction, pdf) = map.sample(rng.next_vec2(), 0.7).unwrap();
+            if direction.v[1].abs() < 0.999 {
+                assert!((pdf - map.pdf(&direction, 0.7)).abs() <= 1e-3 * pdf);
+            }
+            estimate += map.radiance(&direction, 0.7)[0] / pdf;
+            let uv = direction_to_uv(&direction, 0.7);
+            if (40. ..44.).contains(&(uv[0] * 64.)) && (10. ..13.).contains(&(uv[1] * 32.)) {
+                in_patch += 1;
+            }
+        }
+        println!("{} environment samples in {:?}", count, start.elapsed());
+        estimate /= count as f32;
+        assert!(
+            (estimate - expected).abs() < 0.02 * expected,
+            "{} against {}",
+            estimate,
+            expected
+        );
+        assert!(in_patch > count * 9 / 10);
+    }
+}
@@ -1,11 +1,21 @@
+use crate::utils::vector::Vec3;
+
+// light without area, of one of the kinds below. Point and spot lights have a radiant intensity
+// of `color * intensity` per steradian, so the irradiance they give a surface facing them falls
+// off with the squared distance. A directional light gives the irradiance `color * intensity`
+// everywhere, like the sun
 #[repr(C)]
-#[derive(Clone, Copy, Debug, bytemuck::NoUninit)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::NoUninit)]
 pub struct Light {
     pub position: [f32; 3],
     pub is_valid: u32,
     pub color: [f32; 4],
+    pub direction: [f32; 3], // the way the light travels, for directional and spot lights
+    pub kind: u32,
     pub intensity: f32,
-    pub _padding: [f32; 3],
+    pub angular_diameter: f32, // apparent size of a directional light in radians, softens shadows
+    pub inner_angle: f32,      // full intensity inside this angle to the spot axis
+    pub outer_angle: f32,      // no light outside this one
 }
 
 impl Default for Light {
@@ -14,29 +24,168 @@ impl Default for Light {
             position: [0., 0., 0.],
             is_valid: 0,
             color: [1., 1., 1., 1.],
+            direction: [0., -1., 0.],
+            kind: Light::POINT,
             intensity: 10.,
-            _padding: [0., 0., 0.],
+            angular_diameter: 0.,
+            inner_angle: 0.,
+            outer_angle: 0.,
+        }
+    }
+}
+
+impl Light {
+    pub const POINT: u32 = 0;
+    pub const DIRECTIONAL: u32 = 1;
+    pub const SPOT: u32 = 2;
+
+    pub fn point(position: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            position,
+            is_valid: 1,
+            color: [color[0], color[1], color[2], 1.],
+            intensity,
+            ..Default::default()
+        }
+    }
+
+    // the sun has an angular diameter of about 0.0093 radians
+    pub fn directional(
+        direction: [f32; 3],
+        color: [f32; 3],
+        irradiance: f32,
+        angular_diameter: f32,
+    ) -> Self {
+        Self {
+            direction: Vec3::from(direction).normalize().to_array(),
+            kind: Light::DIRECTIONAL,
+            angular_diameter,
+            ..Self::point([0., 0., 0.], color, irradiance)
+        }
+    }
+
+    // the intensity fades out from `inner_angle` to `outer_angle` off the axis
+    pub fn spot(
+        position: [f32; 3],
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            direction: Vec3::from(direction).normalize().to_array(),
+            kind: Light::SPOT,
+            inner_angle,
+            outer_angle,
+            ..Self::point(position, color, intensity)
+        }
+    }
+
+    // irradiance in units of the color on a surface at `point` facing the light, ignoring
+    // shadows. Same as `light_falloff` in main.wgsl
+    pub fn falloff(&self, point: &Vec3<f32>) -> f32 {
+        if self.kind == Light::DIRECTIONAL {
+            return self.intensity;
+        }
+        let offset = point - Vec3::from(self.position);
+        let distance2 = offset.squared_length();
+        let mut falloff = self.intensity / distance2;
+        if self.kind == Light::SPOT {
+            let cos_axis = offset.dot(&Vec3::from(self.direction)) / distance2.sqrt();
+            let (edge0, edge1) = (self.outer_angle.cos(), self.inner_angle.cos());
+            // equal angles give a hard edge instead of a division by zero
+            let t = if edge1 > edge0 {
+                ((cos_axis - edge0) / (edge1 - edge0)).clamp(0., 1.)
+            } else {
+                (cos_axis >= edge0) as u32 as f32
+            };
+            falloff *= t * t * (3. - 2. * t);
+        }
+        falloff
+    }
+}
+
+// one sided emitting rectangle spanned by two perpendicular edges from `corner`. It emits
+// `emission` as radiance toward the side `edge_u x edge_v` points to. Spherical area lights are
+// spheres with an emissive material
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RectLight {
+    pub corner: [f32; 3],
+    pub padding_0: f32,
+    pub edge_u: [f32; 3],
+    pub padding_1: f32,
+    pub edge_v: [f32; 3],
+    pub padding_2: f32,
+    pub emission: [f32; 3],
+    pub padding_3: f32,
+}
+
+impl RectLight {
+    pub fn new(corner: [f32; 3], edge_u: [f32; 3], edge_v: [f32; 3], emission: [f32; 3]) -> Self {
+        Self {
+            corner,
+            edge_u,
+            edge_v,
+            emission,
+            ..Default::default()
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        Vec3::from(self.edge_u)
+            .cross(&Vec3::from(self.edge_v))
+            .length()
+    }
+
+    // the direction it emits toward
+    pub fn normal(&self) -> Vec3<f32> {
+        Vec3::from(self.edge_u)
+            .cross(&Vec3::from(self.edge_v))
+            .normalize()
+    }
+
+    // point at fractions `uv` along the edges, uniform samples give uniform points
+    pub fn point(&self, uv: [f32; 2]) -> Vec3<f32> {
+        Vec3::from(self.corner) + Vec3::from(self.edge_u) * uv[0] + Vec3::from(self.edge_v) * uv[1]
+    }
+
+    // density per solid angle seen from `from` of picking `point` uniformly over the area, zero
+    // from behind. Same as in main.wgsl
+    pub fn pdf(&self, from: &Vec3<f32>, point: &Vec3<f32>) -> f32 {
+        let offset = point - from;
+        let distance2 = offset.squared_length();
+        let cos_light = -offset.dot(&self.normal()) / distance2.sqrt();
+        if cos_light <= 0. {
+            return 0.;
         }
+        distance2 / (self.area() * cos_light)
     }
 }
 
 pub struct LightManager {
     pub light_buffer: wgpu::Buffer,
+    pub rect_light_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub lights: Vec<Light>,
+    pub rect_lights: Vec<RectLight>,
 }
 
 impl LightManager {
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, lights: Vec<Light>) -> Self {
-        let (light_buffer, bind_group, bind_group_layout) =
-            Self::create_buffers_and_bind_group(device, &lights);
+        let rect_lights = vec![];
+        let (light_buffer, rect_light_buffer, bind_group, bind_group_layout) =
+            Self::create_buffers_and_bind_group(device, &lights, &rect_lights);
 
         let mut manager = Self {
             light_buffer,
+            rect_light_buffer,
             bind_group,
             bind_group_layout,
             lights,
+            rect_lights,
         };
 
         manager.update_buffers(queue);
@@ -46,7 +195,13 @@ impl LightManager {
     fn create_buffers_and_bind_group(
         device: &wgpu::Device,
         lights: &[Light],
-    ) -> (wgpu::Buffer, wgpu::BindGroup, wgpu::BindGroupLayout) {
+        rect_lights: &[RectLight],
+    ) -> (
+        wgpu::Buffer,
+        wgpu::Buffer,
+        wgpu::BindGroup,
+        wgpu::BindGroupLayout,
+    ) {
         let light_buffer_size = std::mem::size_of_val(lights) as wgpu::BufferAddress;
         let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("light Buffer"),
@@ -54,35 +209,82 @@ impl LightManager {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
+        // never empty, unused entries have no area and are skipped by the shader
+        let rect_light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("rect light Buffer"),
+            size: (std::mem::size_of::<RectLight>() * rect_lights.len().max(1))
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
             label: Some("light Bind Group Layout"),
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: rect_light_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("light Bind Group"),
         });
 
-        (light_buffer, bind_group, bind_group_layout)
+        (
+            light_buffer,
+            rect_light_buffer,
+            bind_group,
+            bind_group_layout,
+        )
     }
 
     pub fn update_buffers(&mut self, queue: &wgpu::Queue) {
         queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&self.lights));
+        queue.write_buffer(
+            &self.rect_light_buffer,
+            0,
+            bytemuck::cast_slice(&self.rect_lights),
+        );
+    }
+
+    pub fn add_rect_light(
+        &mut self,
+        rect_light: RectLight,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        self.rect_lights.push(rect_light);
+        self.recreate_buffer_if_necessary(device);
+        self.update_buffers(queue);
     }
 
     pub fn add_light(&mut self, light: Light, device: &wgpu::Device, queue: &wgpu::Queue) {
@@ -97,6 +299,13 @@ impl LightManager {
         self.update_buffers(queue);
     }
 
+    pub fn set_light(&mut self, index: usize, light: Light, queue: &wgpu::Queue) {
+        if index < self.lights.len() {
+            self.lights[index] = light;
+            self.update_buffers(queue);
+        }
+    }
+
     pub fn remove_light(&mut self, index: usize, queue: &wgpu::Queue) {
         if index < self.lights.len() {
             self.lights.remove(index);
@@ -107,10 +316,15 @@ impl LightManager {
     pub fn recreate_buffer_if_necessary(&mut self, device: &wgpu::Device) {
         let required_size =
             (self.lights.len() * std::mem::size_of::<Light>()) as wgpu::BufferAddress;
-        if required_size > self.light_buffer.size() {
-            let (light_buffer, new_bind_group, _) =
-                Self::create_buffers_and_bind_group(device, &self.lights);
+        let required_rect_size =
+            (self.rect_lights.len() * std::mem::size_of::<RectLight>()) as wgpu::BufferAddress;
+        if required_size > self.light_buffer.size()
+            || required_rect_size > self.rect_light_buffer.size()
+        {
+            let (light_buffer, rect_light_buffer, new_bind_group, _) =
+                Self::create_buffers_and_bind_group(device, &self.lights, &self.rect_lights);
             self.light_buffer = light_buffer;
+            self.rect_light_buffer = rect_light_buffer;
             self.bind_group = new_bind_group;
         }
     }
@@ -119,3 +333,85 @@ impl LightManager {
         self.lights.len() as u32
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use crate::utils::random::Pcg;
+    use crate::utils::vector::Vec3;
+
+    use super::{Light, RectLight};
+
+    #[test]
+    fn light_kind_test() {
+        // matches the WGSL struct: four rows of 16 bytes
+        assert_eq!(std::mem::size_of::<Light>(), 64);
+
+        let point = Light::point([0., 4., 0.], [1., 1., 1.], 32.);
+        assert_eq!(point.falloff(&Vec3::new(0., 0., 0.)), 2.);
+        assert_eq!(point.falloff(&Vec3::new(0., -4., 0.)), 0.5);
+
+        let sun = Light::directional([1., -1., 0.], [1., 0.9, 0.8], 3., 0.0093);
+        assert!(Vec3::from(sun.direction)
+            .approx_eq(&Vec3::new(0.5f32.sqrt(), -(0.5f32.sqrt()), 0.), 1e-6));
+        assert_eq!(sun.falloff(&Vec3::new(100., -50., 7.)), 3.);
+
+        // full strength inside the inner cone, none outside the outer one, fading in between
+        let spot = Light::spot([0., 4., 0.], [0., -2., 0.], [1., 1., 1.], 32., 0.2, 0.4);
+        let at = |angle: f32| {
+            spot.falloff(&Vec3::new(4. * angle.tan(), 0., 0.)) * (1. + angle.tan().powi(2))
+        };
+        assert!((at(0.) - 2.).abs() < 1e-6);
+        assert!((at(0.19) - 2.).abs() < 1e-6);
+        assert!(at(0.25) < 2. && at(0.25) > at(0.35));
+        assert!(at(0.35) > 0.);
+        assert_eq!(at(0.41), 0.);
+
+        // a hard edged spot is all or nothing
+        let hard = Light::spot([0., 4., 0.], [0., -2., 0.], [1., 1., 1.], 32., 0.3, 0.3);
+        let at = |angle: f32| {
+            hard.falloff(&Vec3::new(4. * angle.tan(), 0., 0.)) * (1. + angle.tan().powi(2))
+        };
+        assert!((at(0.29) - 2.).abs() < 1e-6);
+        assert_eq!(at(0.31), 0.);
+    }
+
+    #[test]
+    fn rect_light_test() {
+        // a 2 by 3 rectangle 1.5 above the origin, shining down
+        let light = RectLight::new([-1., 1.5, -1.5], [2., 0., 0.], [0., 0., 3.], [4., 4., 4.]);
+        assert!(light.normal().approx_eq(&Vec3::new(0., -1., 0.), 1e-6));
+        assert!((light.area() - 6.).abs() < 1e-6);
+        let origin = Vec3::new(0., 0., 0.);
+        assert_eq!(
+            light.pdf(&Vec3::new(0., 2., 0.), &light.point([0.5, 0.5])),
+            0.
+        );
+
+        // irradiance at the origin by sampling the area, against the form factor of a parallel
+        // rectangle summed over the four equal quarters around the foot point
+        let mut rng = Pcg::new([0, 0], 0, 0);
+        let count = 200_000;
+        let mut irradiance = 0.;
+        for _ in 0..count {
+            let point = light.point(rng.next_vec2());
+            let direction = (point - origin).normalize();
+            irradiance += light.emission[1] * direction.v[1] / light.pdf(&origin, &point);
+        }
+        irradiance /= count as f32;
+        let quarter = |a: f32, b: f32, c: f32| {
+            let (a, b) = (a / c, b / c);
+            let (sa, sb) = ((1. + a * a).sqrt(), (1. + b * b).sqrt());
+            (a / sa * (b / sa).atan() + b / sb * (a / sb).atan()) / (2. * PI)
+        };
+        let form_factor = 4. * quarter(1., 1.5, 1.5);
+        let expected = PI * light.emission[1] * form_factor;
+        assert!(
+            (irradiance - expected).abs() < 0.01 * expected,
+            "{} against {}",
+            irradiance,
+            expected
+        );
+    }
+}
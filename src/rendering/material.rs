@@ -0,0 +1,232 @@
+use std::f32::consts::PI;
+use std::mem;
+
+// surface description shared by spheres and mesh faces, which refer to it by its index in the
+// material buffer. Metals tint their reflection with the albedo, dielectrics with transmission
+// refract into the surface and tint the transmitted light
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    pub albedo: [f32; 3],
+    pub metallic: f32,
+    pub emission: [f32; 3],
+    pub roughness: f32, // perceptual roughness, the GGX alpha is its square
+    pub ior: f32,
+    pub transmission: f32,
+    pub padding_: [f32; 2],
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::diffuse([1., 1., 1.])
+    }
+}
+
+impl Material {
+    pub fn diffuse(albedo: [f32; 3]) -> Self {
+        Self {
+            albedo,
+            metallic: 0.,
+            emission: [0., 0., 0.],
+            roughness: 1.,
+            ior: 1.5,
+            transmission: 0.,
+            padding_: [0., 0.],
+        }
+    }
+
+    // a dielectric coat over a diffuse base, e.g. plastic or paint
+    pub fn plastic(albedo: [f32; 3], roughness: f32) -> Self {
+        Self {
+            roughness,
+            ..Self::diffuse(albedo)
+        }
+    }
+
+    // rough values give brushed metal
+    pub fn metal(albedo: [f32; 3], roughness: f32) -> Self {
+        Self {
+            metallic: 1.,
+            roughness,
+            ..Self::diffuse(albedo)
+        }
+    }
+
+    // a light source of any shape, giving off `emission` as radiance from every point
+    pub fn emissive(emission: [f32; 3]) -> Self {
+        Self {
+            emission,
+            ..Self::diffuse([0., 0., 0.])
+        }
+    }
+
+    // rough values give frosted glass
+    pub fn glass(tint: [f32; 3], ior: f32, roughness: f32) -> Self {
+        Self {
+            roughness,
+            ior,
+            transmission: 1.,
+            ..Self::diffuse(tint)
+        }
+    }
+}
+
+// fraction of unpolarized light reflected by a smooth dielectric boundary, `eta` is the index
+// of refraction on the incident side over the one on the far side. Same as `fresnel_dielectric`
+// in main.wgsl
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin_t2 = eta * eta * (1. - cos_i * cos_i);
+    if sin_t2 >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin_t2).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// GGX normal distribution for a microfacet normal at `cos_m` to the surface normal
+pub fn ggx_distribution(cos_m: f32, alpha: f32) -> f32 {
+    if cos_m <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    let d = cos_m * cos_m * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+pub struct MaterialManager {
+    pub material_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub materials: Vec<Material>,
+}
+
+impl MaterialManager {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, materials: Vec<Material>) -> Self {
+        let (material_buffer, bind_group, bind_group_layout) =
+            Self::create_buffers_and_bind_group(device, &materials);
+
+        let mut manager = Self {
+            material_buffer,
+            bind_group,
+            bind_group_layout,
+            materials,
+        };
+
+        manager.update_buffers(queue);
+        manager
+    }
+
+    fn create_buffers_and_bind_group(
+        device: &wgpu::Device,
+        materials: &[Material],
+    ) -> (wgpu::Buffer, wgpu::BindGroup, wgpu::BindGroupLayout) {
+        let material_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Material Buffer"),
+            size: (mem::size_of::<Material>() * materials.len().max(1)) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Material Bind Group Layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: material_buffer.as_entire_binding(),
+            }],
+            label: Some("Material Bind Group"),
+        });
+
+        (material_buffer, bind_group, bind_group_layout)
+    }
+
+    pub fn update_buffers(&mut self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.material_buffer,
+            0,
+            bytemuck::cast_slice(&self.materials),
+        );
+    }
+
+    // returns the index spheres and mesh faces refer to the material by
+    pub fn add_material(
+        &mut self,
+        material: Material,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> u32 {
+        self.materials.push(material);
+        self.recreate_buffer_if_necessary(device);
+        self.update_buffers(queue);
+        self.materials.len() as u32 - 1
+    }
+
+    pub fn set_material(&mut self, index: usize, material: Material, queue: &wgpu::Queue) {
+        if index < self.materials.len() {
+            self.materials[index] = material;
+            self.update_buffers(queue);
+        }
+    }
+
+    pub fn recreate_buffer_if_necessary(&mut self, device: &wgpu::Device) {
+        let required_size =
+            (self.materials.len() * mem::size_of::<Material>()) as wgpu::BufferAddress;
+        if required_size > self.material_buffer.size() {
+            let (new_material_buffer, new_bind_group, _) =
+                Self::create_buffers_and_bind_group(device, &self.materials);
+            self.material_buffer = new_material_buffer;
+            self.bind_group = new_bind_group;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use super::{fresnel_dielectric, ggx_distribution, Material};
+
+    #[test]
+    fn material_test() {
+        // matches the WGSL struct: two vec3 + f32 rows and a row of four scalars
+        assert_eq!(std::mem::size_of::<Material>(), 48);
+
+        // normal incidence gives ((n1 - n2) / (n1 + n2))^2, grazing light is fully reflected
+        let f0 = fresnel_dielectric(1., 1. / 1.5);
+        assert!((f0 - 0.04).abs() < 1e-6);
+        assert!(fresnel_dielectric(0., 1. / 1.5) > 0.999);
+        assert!(fresnel_dielectric(0.5, 1. / 1.5) > f0);
+        // light leaving glass past the critical angle is reflected entirely
+        assert_eq!(fresnel_dielectric(0.5, 1.5), 1.);
+        assert!(fresnel_dielectric(0.9, 1.5) < 1.);
+
+        // the projected microfacet area covers the surface once, whatever the roughness
+        let steps = 20_000;
+        for alpha in [0.05, 0.3, 1.] {
+            let mut area = 0.;
+            for i in 0..steps {
+                let theta = (i as f32 + 0.5) / steps as f32 * PI / 2.;
+                let (sin, cos) = theta.sin_cos();
+                area +=
+                    ggx_distribution(cos, alpha) * cos * sin * 2. * PI * (PI / 2.) / steps as f32;
+            }
+            assert!((area - 1.).abs() < 2e-2, "alpha {} gives {}", alpha, area);
+        }
+    }
+}
@@ -1,3 +1,10 @@
+pub mod accumulation;
+pub mod bookmark;
 pub mod camera;
+pub mod controller;
+pub mod environment;
 pub mod light;
+pub mod material;
+pub mod overlay;
+pub mod sky;
 pub mod sphere;
@@ -0,0 +1,124 @@
+use crate::utils::polyline::Polyline;
+
+// draws polylines as lines on top of the ray traced image
+pub struct OverlayManager {
+    pub vertex_buffer: wgpu::Buffer,
+    pub vertex_count: u32,
+    pub render_pipeline: wgpu::RenderPipeline,
+    pub visible: bool,
+}
+
+impl OverlayManager {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/overlay.wgsl").into()),
+        });
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Overlay Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                }],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+        });
+
+        Self {
+            vertex_buffer: Self::create_vertex_buffer(device, 1),
+            vertex_count: 0,
+            render_pipeline,
+            visible: false,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, vertex_count: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            size: (vertex_count.max(1) * std::mem::size_of::<[f32; 3]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // replaces the drawn lines, every polyline segment becomes one line list entry
+    pub fn set_polylines(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        polylines: &[Polyline],
+    ) {
+        let mut vertices: Vec<[f32; 3]> = vec![];
+        for polyline in polylines {
+            for pair in polyline.points.windows(2) {
+                vertices.extend_from_slice(pair);
+            }
+            if polyline.closed && polyline.points.len() > 2 {
+                vertices.push(polyline.points[polyline.points.len() - 1]);
+                vertices.push(polyline.points[0]);
+            }
+        }
+        let required_size = std::mem::size_of_val(vertices.as_slice()) as wgpu::BufferAddress;
+        if required_size > self.vertex_buffer.size() {
+            self.vertex_buffer = Self::create_vertex_buffer(device, vertices.len());
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.visible || self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
@@ -0,0 +1,314 @@
+use std::f32::consts::PI;
+
+use crate::rendering::light::Light;
+use crate::utils::vector::Vec3;
+
+// scene radiance per cd/m2 and irradiance per lux. Puts a clear midday sun near an irradiance of
+// 3 and the sky around 0.25, next to the point and area lights of the default scene
+pub const SCENE_UNITS_PER_LUX: f32 = 3e-5;
+
+// illuminance of the sun above the atmosphere
+const SOLAR_ILLUMINANCE: f32 = 128_000.;
+
+// the sun's apparent diameter in radians
+pub const SUN_ANGULAR_DIAMETER: f32 = 0.0093;
+
+// sky model parameters for main.wgsl. Rows of the Perez coefficients A to E and the zenith
+// values hold the luminance Y and the chromaticities x and y of the Preetham model
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkyInfo {
+    pub perez: [[f32; 4]; 5],
+    pub zenith: [f32; 4], // Y x y divided by the Perez function at the zenith, and the scale of Y
+    pub sun: [f32; 4],    // direction toward the sun, and the cosine of its angular radius
+    pub sun_radiance: [f32; 4],
+}
+
+// clear sky lit by the sun (Preetham, Shirley and Smits, "A Practical Analytic Model for
+// Daylight"). Y is up, the horizon's north is -z and east is +x
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Daylight {
+    pub elevation: f32, // radians above the horizon
+    pub azimuth: f32,   // radians from north toward east
+    pub turbidity: f32, // 2 for a very clear sky, around 10 for haze
+}
+
+impl Default for Daylight {
+    fn default() -> Self {
+        Self {
+            elevation: 35f32.to_radians(),
+            azimuth: 150f32.to_radians(),
+            turbidity: 3.,
+        }
+    }
+}
+
+// day of the year from 1 on January 1st, ignoring leap years
+pub fn day_of_year(month: u32, day: u32) -> u32 {
+    const DAYS_BEFORE: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    DAYS_BEFORE[(month.clamp(1, 12) - 1) as usize] + day
+}
+
+impl Daylight {
+    // the sun at `solar_hour` local solar time, 12 being noon, on a day of the year at a
+    // latitude in degrees, north positive
+    pub fn from_date(day: u32, solar_hour: f32, latitude: f32, turbidity: f32) -> Self {
+        let declination = 23.44f32.to_radians() * (2. * PI * (284. + day as f32) / 365.).sin();
+        let hour_angle = (15. * (solar_hour - 12.)).to_radians();
+        let latitude = latitude.to_radians();
+        let sin_elevation = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1., 1.).asin();
+        let cos_azimuth = (declination.sin() - sin_elevation * latitude.sin())
+            / (elevation.cos() * latitude.cos()).max(1e-6);
+        let azimuth = cos_azimuth.clamp(-1., 1.).acos();
+        Self {
+            elevation,
+            // mornings have the sun in the east, afternoons in the west
+            azimuth: if hour_angle > 0. {
+                2. * PI - azimuth
+            } else {
+                azimuth
+            },
+            turbidity,
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vec3<f32> {
+        let (sin_e, cos_e) = self.elevation.sin_cos();
+        let (sin_a, cos_a) = self.azimuth.sin_cos();
+        Vec3::new(cos_e * sin_a, sin_e, -cos_e * cos_a)
+    }
+
+    // the sky stays defined with the sun just under the horizon, as a faint twilight
+    fn sun_zenith_angle(&self) -> f32 {
+        (PI / 2. - self.elevation).min(PI / 2. - 0.01)
+    }
+
+    fn perez_coefficients(&self) -> [[f32; 3]; 5] {
+        let t = self.turbidity;
+        [
+            [
+                0.1787 * t - 1.4630,
+                -0.0193 * t - 0.2592,
+                -0.0167 * t - 0.2608,
+            ],
+            [
+                -0.3554 * t + 0.4275,
+                -0.0665 * t + 0.0008,
+                -0.0950 * t + 0.0092,
+            ],
+            [
+                -0.0227 * t + 5.3251,
+                -0.0004 * t + 0.2125,
+                -0.0079 * t + 0.2102,
+            ],
+            [
+                0.1206 * t - 2.5771,
+                -0.0641 * t - 0.8989,
+                -0.0441 * t - 1.6537,
+            ],
+            [
+                -0.0670 * t + 0.3703,
+                -0.0033 * t + 0.0452,
+                -0.0109 * t + 0.0529,
+            ],
+        ]
+    }
+
+    // Y in kcd/m2 and the chromaticities x and y straight up
+    fn zenith(&self) -> [f32; 3] {
+        let t = self.turbidity;
+        let theta = self.sun_zenith_angle();
+        let (t2, theta2) = (t * t, theta * theta);
+        let theta3 = theta2 * theta;
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta);
+        let y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cx = t2 * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+        let cy = t2 * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+        [y, cx, cy]
+    }
+
+    pub fn sky_info(&self) -> SkyInfo {
+        let perez = self.perez_coefficients();
+        let zenith = self.zenith();
+        let theta_s = self.sun_zenith_angle();
+        let mut info = SkyInfo::default();
+        for (row, coefficients) in info.perez.iter_mut().zip(&perez) {
+            *row = [coefficients[0], coefficients[1], coefficients[2], 0.];
+        }
+        for k in 0..3 {
+            let c = perez.map(|row| row[k]);
+            info.zenith[k] = zenith[k] / perez_function(&c, 1., theta_s.cos(), theta_s);
+        }
+        info.zenith[3] = 1000. * SCENE_UNITS_PER_LUX;
+        let sun = self.sun_direction();
+        info.sun = [
+            sun.v[0],
+            sun.v[1],
+            sun.v[2],
+            (SUN_ANGULAR_DIAMETER / 2.).cos(),
+        ];
+        // the sun's irradiance spread over its disk
+        let light = self.sun_light();
+        let solid_angle = 2. * PI * (1. - info.sun[3]);
+        let radiance = light.intensity / solid_angle * light.is_valid as f32;
+        info.sun_radiance = [
+            light.color[0] * radiance,
+            light.color[1] * radiance,
+            light.color[2] * radiance,
+            0.,
+        ];
+        info
+    }
+
+    // linear RGB sky radiance in scene units, same as `daylight_radiance` in main.wgsl. Below the
+    // horizon the ground reflects a share of the horizon's light
+    pub fn radiance(&self, direction: &Vec3<f32>) -> [f32; 3] {
+        let info = self.sky_info();
+        let d = direction.normalize();
+        let cos_theta = d.v[1].max(0.001);
+        let sun = Vec3::new(info.sun[0], info.sun[1], info.sun[2]);
+        let gamma = d.dot(&sun).clamp(-1., 1.).acos();
+        let [y, cx, cy] = [0, 1, 2].map(|k| {
+            let c = info.perez.map(|row| row[k]);
+            info.zenith[k] * perez_function(&c, cos_theta, gamma.cos(), gamma)
+        });
+        let ground = if d.v[1] < 0. { 0.3 } else { 1. };
+        xyy_to_rgb(cx, cy, y * info.zenith[3] * ground)
+    }
+
+    // the sun as a directional light, reddened by the air it passes through and switched off
+    // below the horizon
+    pub fn sun_light(&self) -> Light {
+        let direction = self.sun_direction();
+        let transmittance = sun_transmittance(self.elevation, self.turbidity);
+        let irradiance = SOLAR_ILLUMINANCE * SCENE_UNITS_PER_LUX;
+        Light {
+            is_valid: (self.elevation > 0.) as u32,
+            ..Light::directional(
+                (-direction).to_array(),
+                transmittance,
+                irradiance,
+                SUN_ANGULAR_DIAMETER,
+            )
+        }
+    }
+}
+
+// Perez et al. sky luminance distribution for one of Y, x and y
+fn perez_function(c: &[f32; 5], cos_theta: f32, cos_gamma: f32, gamma: f32) -> f32 {
+    (1. + c[0] * (c[1] / cos_theta).exp())
+        * (1. + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+// CIE xyY to linear sRGB, negative components of colors outside the gamut are dropped
+pub fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> [f32; 3] {
+    if y <= 0. {
+        return [0., 0., 0.];
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1. - x - y) / y * luminance;
+    [
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    ]
+    .map(|c| c.max(0.))
+}
+
+// fraction of red, green and blue sunlight crossing the atmosphere at an elevation, from the
+// Rayleigh and aerosol optical depths of Preetham et al. and the Kasten and Young air mass
+pub fn sun_transmittance(elevation: f32, turbidity: f32) -> [f32; 3] {
+    if elevation <= 0. {
+        return [0., 0., 0.];
+    }
+    let zenith = 90. - elevation.to_degrees();
+    let air_mass = 1. / (zenith.to_radians().cos() + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+    let beta = 0.04608 * turbidity - 0.04586;
+    [0.65f32, 0.57, 0.475].map(|wavelength| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::vector::Vec3;
+
+    use super::{day_of_year, sun_transmittance, Daylight, SkyInfo};
+
+    #[test]
+    fn solar_position_test() {
+        assert_eq!(day_of_year(1, 1), 1);
+        assert_eq!(day_of_year(3, 21), 80);
+
+        // around the equinox the noon sun stands at 90 degrees minus the latitude, due south in
+        // the north, and rises at six
+        let equinox = day_of_year(3, 21);
+        let noon = Daylight::from_date(equinox, 12., 45., 3.);
+        assert!((noon.elevation.to_degrees() - 45.).abs() < 1.);
+        assert!((noon.azimuth.to_degrees() - 180.).abs() < 0.1);
+        assert!(noon
+            .sun_direction()
+            .approx_eq(&Vec3::new(0., 0.5f32.sqrt(), 0.5f32.sqrt()), 0.02));
+        let sunrise = Daylight::from_date(equinox, 6., 45., 3.);
+        assert!(sunrise.elevation.to_degrees().abs() < 1.);
+        assert!((sunrise.azimuth.to_degrees() - 90.).abs() < 2.);
+        let evening = Daylight::from_date(equinox, 17., 45., 3.);
+        assert!(evening.azimuth.to_degrees() > 250. && evening.azimuth.to_degrees() < 270.);
+        // midsummer noon is higher, midwinter noon lower, and the sun is down at midnight
+        let summer = Daylight::from_date(day_of_year(6, 21), 12., 45., 3.);
+        let winter = Daylight::from_date(day_of_year(12, 21), 12., 45., 3.);
+        assert!((summer.elevation.to_degrees() - 68.4).abs() < 1.);
+        assert!((winter.elevation.to_degrees() - 21.6).abs() < 1.);
+        assert!(Daylight::from_date(equinox, 0., 45., 3.).elevation < 0.);
+    }
+
+    #[test]
+    fn daylight_test() {
+        let sky = Daylight::default();
+        let info: SkyInfo = sky.sky_info();
+        let sun = sky.sun_direction();
+
+        // the zenith has the model's zenith luminance, a blue sky gets brighter toward the sun
+        let zenith = sky.radiance(&Vec3::new(0., 1., 0.));
+        assert!(zenith[2] > zenith[0]);
+        let luminance = |c: [f32; 3]| 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2];
+        assert!(
+            (luminance(zenith) / info.zenith[3] - sky.zenith()[0]).abs() < 0.02 * sky.zenith()[0]
+        );
+        let near_sun = (sun + Vec3::new(0., 0.2, 0.)).normalize();
+        let away = Vec3::new(-sun.v[0], sun.v[1], -sun.v[2]);
+        assert!(luminance(sky.radiance(&near_sun)) > luminance(sky.radiance(&away)));
+        assert!(sky
+            .radiance(&Vec3::new(0.3, -1., 0.))
+            .iter()
+            .all(|&c| c >= 0.));
+
+        // the sun light shines from the sun, redder and weaker when it is low, and is off at night
+        let light = sky.sun_light();
+        assert!(Vec3::from(light.direction).approx_eq(&(-sun), 1e-6));
+        assert_eq!(light.is_valid, 1);
+        let high = sun_transmittance(60f32.to_radians(), 3.);
+        let low = sun_transmittance(5f32.to_radians(), 3.);
+        assert!(high.iter().all(|&t| t > 0.5 && t < 1.));
+        assert!(low[0] / low[2] > high[0] / high[2]);
+        assert!(low[1] < high[1]);
+        let night = Daylight {
+            elevation: -0.2,
+            ..Default::default()
+        };
+        assert_eq!(night.sun_light().is_valid, 0);
+        assert_eq!(night.sky_info().sun_radiance, [0.; 4]);
+        assert!(night
+            .radiance(&Vec3::new(0., 1., 0.))
+            .iter()
+            .all(|c| c.is_finite()));
+    }
+}
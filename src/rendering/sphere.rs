@@ -1,14 +1,15 @@
 use std::mem;
 
+use crate::utils::ray::Ray;
+use crate::utils::vector::Vec3;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Sphere {
     pub center: [f32; 3],
     pub radius: f32,
-    pub color: [f32; 4],
-    pub material: f32,
-    pub refractivity: f32,
-    pub padding_: [f32; 2],
+    pub material: u32, // index into the material buffer
+    pub padding_: [u32; 3],
 }
 
 impl Default for Sphere {
@@ -16,11 +17,31 @@ impl Default for Sphere {
         Sphere {
             center: [0., 0., 0.],
             radius: 0.,
-            color: [1.0, 1.0, 0., 1.0],
-            material: 0.0,
-            refractivity: 1.0,
-            padding_: [0., 0.],
+            material: 0,
+            padding_: [0; 3],
+        }
+    }
+}
+
+impl Sphere {
+    // [min x, min y, min z, max x, max y, max z]
+    pub fn bounds(&self) -> [f32; 6] {
+        let [x, y, z] = self.center;
+        let r = self.radius;
+        [x - r, y - r, z - r, x + r, y + r, z + r]
+    }
+
+    // distance along a normalized ray to the first hit in front of its origin
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let oc = ray.origin - Vec3::from(self.center);
+        let b = oc.dot(&ray.direction);
+        let c = oc.dot(&oc) - self.radius * self.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0. {
+            return None;
         }
+        let root = discriminant.sqrt();
+        [-b - root, -b + root].into_iter().find(|&t| t > 0.)
     }
 }
 
@@ -8,22 +8,55 @@ const MAX_U32: u32 = 0xffffffff; // Define max u32 value
 struct Sphere {
   center: vec3<f32>, // Center point of the sphere
   radius: f32, // Radius of the sphere
-  color: vec4<f32>, // Color of the sphere
-  material: f32, // Material properties of the sphere
-  refactivity: f32, // Refractive index of the sphere
+  material: u32, // Index into materials
+  padding_: array<u32, 3>,
+}
+
+// Surface description shared by spheres and mesh faces, see rendering/material.rs
+struct Material {
+  albedo: vec3<f32>, // Diffuse color, or the reflection tint of metals
+  metallic: f32,
+  emission: vec3<f32>,
+  roughness: f32, // Perceptual roughness, the GGX alpha is its square
+  ior: f32, // Index of refraction
+  transmission: f32, // Fraction of the dielectric part that refracts instead of scattering diffusely
+  padding_: vec2<f32>,
 }
 
 // Define a struct to represent camera information
 struct CamInfos {
   cam_info: mat4x4<f32>, // 4x4 matrix containing camera information
+  projection: vec4<f32>, // x: 1 for orthographic else 0, y: far, z: aperture, w: focus distance
 }
 
 // Define a struct to represent a light source
+// Kinds of Light, see rendering/light.rs
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
 struct Light {
   position: vec3<f32>, // Position of the light source
   is_valid: u32, // Flag indicating whether the light is valid
   color: vec4<f32>, // Color of the light source
-  intensity: f32, // Intensity of the light source
+  direction: vec3<f32>, // The way directional and spot light travels
+  kind: u32,
+  intensity: f32, // Radiant intensity per steradian, or irradiance for directional lights
+  angular_diameter: f32, // Apparent size of a directional light in radians
+  inner_angle: f32, // Spot angles to the axis in radians, full strength inside the inner one
+  outer_angle: f32,
+}
+
+// One sided emitting rectangle spanned by two perpendicular edges, see rendering/light.rs
+struct RectLight {
+  corner: vec3<f32>,
+  padding_0: f32,
+  edge_u: vec3<f32>,
+  padding_1: f32,
+  edge_v: vec3<f32>, // Emits toward cross(edge_u, edge_v)
+  padding_2: f32,
+  emission: vec3<f32>, // Radiance
+  padding_3: f32,
 }
 
 // Define a struct to represent a ray
@@ -36,20 +69,8 @@ struct Ray {
 
 //shpere
 
-fn random_hemisphere_direction(normal: vec3<f32>, seed: vec2<f32>) -> vec3<f32> {
-    // Generate random angles for spherical coordinates in the hemisphere
-    let phi = 2.0 * 3.14159 * random_float(seed); // Random angle around the hemisphere
-    let cos_theta = random_float(seed + vec2<f32>(1.0, 0.0)); // Random angle from the normal
-    let sin_theta = sqrt(1.0 - cos_theta * cos_theta); // Compute sin(theta) from cos(theta)
-
-    // Create a direction vector in local tangent space
-    let local_dir = vec3<f32>(
-        cos(phi) * sin_theta,
-        sin(phi) * sin_theta,
-        cos_theta
-    );
-
-    // Define a tangent coordinate system around the normal
+// Orthonormal basis around a unit normal, the normal being the last column
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
     let tangent = normalize(cross(
         select(vec3<f32>(1.0, 0.0, 0.0),
             vec3<f32>(0.0, 1.0, 0.0),
@@ -57,23 +78,62 @@ fn random_hemisphere_direction(normal: vec3<f32>, seed: vec2<f32>) -> vec3<f32>
         normal
     ));
     let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
 
-    // Convert the local direction to world space by transforming with the tangent space basis
-    return normalize(
-        tangent * local_dir.x + bitangent * local_dir.y + normal * local_dir.z
-    );
+// Cosine weighted direction in the hemisphere around the normal, the pdf is cos(theta) / pi
+fn random_cosine_direction(normal: vec3<f32>, rng: ptr<function, u32>) -> vec3<f32> {
+    let disk = concentric_disk(random_vec2(rng));
+    let local_dir = vec3<f32>(disk, sqrt(max(0.0, 1.0 - dot(disk, disk))));
+    return normalize(tangent_frame(normal) * local_dir);
+}
+
+// PCG hash of a single word (Jarzynski and Olano, "Hash Functions for GPU Rendering").
+// utils/random.rs has the same functions for the CPU
+fn pcg_hash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random number state for one sample of one pixel in one frame
+fn rng_seed(pixel: vec2<u32>, frame: u32, sample: u32) -> u32 {
+    return pcg_hash(pixel.x ^ pcg_hash(pixel.y ^ pcg_hash(frame ^ pcg_hash(sample))));
+}
+
+// Advances the state and returns the next random word
+fn random_u32(rng: ptr<function, u32>) -> u32 {
+    *rng = *rng * 747796405u + 2891336453u;
+    let state = *rng;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
 }
 
-// single pseudo-random float between 0-1
-fn rand(seed: vec2<f32>) -> f32 {
-  return 0.5 + 0.5 * 
-     fract(sin(dot(seed.xy, vec2(12.9898, 78.233)))* 43758.5453);
+// Uniform float in [0, 1) from the top 24 bits, which a float holds exactly
+fn random_float(rng: ptr<function, u32>) -> f32 {
+    return f32(random_u32(rng) >> 8u) * (1.0 / 16777216.0);
 }
 
-// Generates a pseudo-random float based on a 2D seed
-fn random_float(seed: vec2<f32>) -> f32 {
-    return fract(sin(dot(seed, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+fn random_vec2(rng: ptr<function, u32>) -> vec2<f32> {
+    let a = random_float(rng);
+    return vec2<f32>(a, random_float(rng));
 }
+// Maps the unit square onto the unit disk keeping areas even, same as concentric_disk in camera.rs
+fn concentric_disk(u: vec2<f32>) -> vec2<f32> {
+    let a = 2.0 * u.x - 1.0;
+    let b = 2.0 * u.y - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return vec2<f32>(0.0);
+    }
+    var r = b;
+    var theta = 1.5707963 - 0.7853982 * (a / b);
+    if abs(a) > abs(b) {
+        r = a;
+        theta = 0.7853982 * (b / a);
+    }
+    return r * vec2<f32>(cos(theta), sin(theta));
+}
+
 // Calculates the point along a ray at a given distance
 fn ray_at(ray: Ray, distance: f32) -> vec3<f32> {
     return ray.origin + ray.direction * distance;
@@ -94,38 +154,216 @@ fn hit_sphere_result(r: Ray, center: vec3<f32>, radius: f32) -> HitResult {
     let disc = half_b * half_b - c;            // discriminant simplified
 
     if disc < 0.0 {
-        return HitResult(MAX_FLOAT, vec3<f32>(0.0));
+        return HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
     }
 
     let sq = sqrt(disc);
     var t  = -half_b - sq;       // no /2a since a=1
     if t < 0.001 { t = -half_b + sq; }    // 0.001 replaces your epsilon push
     if t < 0.001 {
-        return HitResult(MAX_FLOAT, vec3<f32>(0.0));
+        return HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
     }
 
     let hit_point = ray_at(r, t);
     let normal    = normalize(hit_point - center);
-    return HitResult(t, normal);
+    return HitResult(t, normal, MAX_U32);
 }
 
-// Calculates refraction or reflection of a ray through a surface
-fn refract_ray(incident: vec3<f32>, normal: vec3<f32>, n1: f32, n2: f32) -> vec3<f32> {
-    let n = n1 / n2; // Ratio of refraction indices
+// Refracts a ray through a surface whose normal faces the incident side, `eta` is the index of
+// refraction on the incident side over the one on the far side. Reflects on total internal reflection
+fn refract_ray(incident: vec3<f32>, normal: vec3<f32>, eta: f32) -> vec3<f32> {
     let cos_i = -dot(normal, incident); // Cosine of incident angle
-    let sin_t2 = n * n * (1.0 - cos_i * cos_i); // Calculate sin^2 of transmission angle
+    let sin_t2 = eta * eta * (1.0 - cos_i * cos_i); // Calculate sin^2 of transmission angle
     
     // Check for total internal reflection
     if sin_t2 > 1.0 {
-        // If total internal reflection occurs, return the reflected vector
         return reflect(incident, normal);
     }
 
-    // Calculate cos(theta) for the transmitted angle
+    // Compute the refracted direction using Snell's law
     let cos_t = sqrt(1.0 - sin_t2);
+    return eta * incident + (eta * cos_i - cos_t) * normal;
+}
 
-    // Compute the refracted direction using Snell's law
-    return n * incident + (n * cos_i - cos_t) * normal;
+// materials
+
+// Reflected fraction at a smooth dielectric boundary, same as fresnel_dielectric in material.rs
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let c = clamp(cos_i, 0.0, 1.0);
+    let sin_t2 = eta * eta * (1.0 - c * c);
+    if sin_t2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = sqrt(1.0 - sin_t2);
+    let rs = (eta * c - cos_t) / (eta * c + cos_t);
+    let rp = (c - eta * cos_t) / (c + eta * cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// GGX distribution of microfacet normals at `cos_m` to the surface normal
+fn ggx_distribution(cos_m: f32, alpha: f32) -> f32 {
+    if cos_m <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = cos_m * cos_m * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith masking of one direction for the GGX distribution
+fn smith_g1(cos_v: f32, alpha: f32) -> f32 {
+    let c = abs(cos_v);
+    let a2 = alpha * alpha;
+    return 2.0 * c / (c + sqrt(a2 + (1.0 - a2) * c * c));
+}
+
+// Microfacet normal drawn with density D(m) * cos(m)
+fn sample_ggx_normal(normal: vec3<f32>, alpha: f32, rng: ptr<function, u32>) -> vec3<f32> {
+    let u = random_vec2(rng);
+    let tan2 = alpha * alpha * u.x / max(1.0 - u.x, 1e-7);
+    let cos_theta = 1.0 / sqrt(1.0 + tan2);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * u.y;
+    let local_dir = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_frame(normal) * local_dir);
+}
+
+// GGX alpha of a material, kept above zero so polished surfaces stay well defined
+fn material_alpha(material: Material) -> f32 {
+    return max(material.roughness * material.roughness, 1e-3);
+}
+
+// Reflectance at normal incidence: 4% for dielectrics, the albedo for metals
+fn material_f0(material: Material, albedo: vec3<f32>) -> vec3<f32> {
+    return mix(vec3<f32>(0.04), albedo, material.metallic);
+}
+
+// Chance of sampling the specular lobe of an opaque surface rather than the diffuse one
+fn specular_probability(material: Material, albedo: vec3<f32>, cos_o: f32) -> f32 {
+    let f = fresnel_schlick(material_f0(material, albedo), cos_o);
+    return mix(clamp((f.x + f.y + f.z) / 3.0, 0.1, 0.9), 1.0, material.metallic);
+}
+
+// BSDF times the cosine at `wi` for light arriving from `wi` and leaving toward `wo`, both
+// pointing away from the surface on the side of `normal`. Only the opaque lobes, a direction
+// chosen in advance practically never lines up with reflection or refraction through glass
+fn evaluate_bsdf(
+    material: Material,
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    wo: vec3<f32>,
+    wi: vec3<f32>
+) -> vec3<f32> {
+    let cos_o = dot(normal, wo);
+    let cos_i = dot(normal, wi);
+    if cos_o <= 0.0 || cos_i <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let alpha = material_alpha(material);
+    let h = normalize(wo + wi);
+    let f = fresnel_schlick(material_f0(material, albedo), dot(wo, h));
+    let specular = f * ggx_distribution(dot(normal, h), alpha) * smith_g1(cos_o, alpha)
+        * smith_g1(cos_i, alpha) / (4.0 * cos_o * cos_i);
+    let diffuse = (1.0 - f) * (1.0 - material.metallic) * albedo / PI;
+    let opaque = 1.0 - material.transmission * (1.0 - material.metallic);
+    return (diffuse + specular) * cos_i * opaque;
+}
+
+// Density per solid angle of sample_bsdf choosing `wi` through the opaque lobes that
+// evaluate_bsdf covers
+fn bsdf_pdf(
+    material: Material,
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    wo: vec3<f32>,
+    wi: vec3<f32>
+) -> f32 {
+    let cos_o = dot(normal, wo);
+    let cos_i = dot(normal, wi);
+    if cos_o <= 0.0 || cos_i <= 0.0 {
+        return 0.0;
+    }
+    let h = normalize(wo + wi);
+    let cos_h = dot(normal, h);
+    let specular = ggx_distribution(cos_h, material_alpha(material)) * cos_h / (4.0 * dot(wo, h));
+    let diffuse = cos_i / PI;
+    let p = specular_probability(material, albedo, cos_o);
+    let opaque = 1.0 - material.transmission * (1.0 - material.metallic);
+    return opaque * (p * specular + (1.0 - p) * diffuse);
+}
+
+// Next direction of a path leaving the surface toward `-incident`, the BSDF times the cosine
+// over the pdf of having chosen it, and bsdf_pdf for the direction, 0 when it went through a
+// glass lobe that light sampling does not cover. `normal` is the outward normal
+struct BsdfSample {
+  direction: vec3<f32>,
+  weight: vec3<f32>,
+  pdf: f32,
+}
+
+fn sample_bsdf(
+    material: Material,
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    incident: vec3<f32>,
+    rng: ptr<function, u32>
+) -> BsdfSample {
+    let front_face = dot(incident, normal) < 0.0;
+    let n = select(-normal, normal, front_face);
+    let wo = -incident;
+    let cos_o = max(dot(n, wo), 1e-6);
+    let alpha = material_alpha(material);
+    let dielectric = 1.0 - material.metallic;
+
+    // Glass: reflect or refract on a sampled microfacet with the Fresnel probability
+    if random_float(rng) < material.transmission * dielectric {
+        let eta = select(material.ior, 1.0 / material.ior, front_face);
+        let m = sample_ggx_normal(n, alpha, rng);
+        let cos_om = dot(wo, m);
+        if cos_om <= 0.0 {
+            return BsdfSample(n, vec3<f32>(0.0), 0.0);
+        }
+        // total internal reflection has a Fresnel reflectance of one, so it never refracts
+        let transmitted = random_float(rng) >= fresnel_dielectric(cos_om, eta);
+        var direction = reflect(incident, m);
+        var tint = vec3<f32>(1.0);
+        if transmitted {
+            direction = refract_ray(incident, m, eta);
+            tint = albedo;
+        }
+        // rough microfacets can send the path to the wrong side of the surface
+        let cos_i = dot(n, direction);
+        if (cos_i < 0.0) != transmitted || cos_i == 0.0 {
+            return BsdfSample(n, vec3<f32>(0.0), 0.0);
+        }
+        // the microfacet weight |wo.m| G / (|wo.n| |m.n|) of sampling D(m) cos(m)
+        let weight = cos_om * smith_g1(cos_o, alpha) * smith_g1(cos_i, alpha) / (cos_o * dot(n, m));
+        return BsdfSample(direction, tint * weight, 0.0);
+    }
+
+    // Opaque: specular reflection or a diffuse bounce, picked by the Fresnel reflectance
+    let f = fresnel_schlick(material_f0(material, albedo), cos_o);
+    let p = specular_probability(material, albedo, cos_o);
+    if random_float(rng) < p {
+        let m = sample_ggx_normal(n, alpha, rng);
+        let direction = reflect(incident, m);
+        let cos_i = dot(n, direction);
+        let cos_om = dot(wo, m);
+        if cos_i <= 0.0 || cos_om <= 0.0 {
+            return BsdfSample(n, vec3<f32>(0.0), 0.0);
+        }
+        let fm = fresnel_schlick(material_f0(material, albedo), cos_om);
+        let weight = cos_om * smith_g1(cos_o, alpha) * smith_g1(cos_i, alpha) / (cos_o * dot(n, m));
+        let pdf = bsdf_pdf(material, albedo, n, wo, direction);
+        return BsdfSample(direction, fm * weight / p, pdf);
+    }
+    let direction = random_cosine_direction(n, rng);
+    let weight = (1.0 - f) * dielectric * albedo / (1.0 - p);
+    return BsdfSample(direction, weight, bsdf_pdf(material, albedo, n, wo, direction));
 }
 
 
@@ -241,6 +479,148 @@ fn sample_skybox(direction: vec3<f32>) -> vec4<f32> {
     return mix(sky_color, cloud_color, cloud_mask * 0.7); // 0.7 to make clouds slightly transparent
 }
 
+// environment map
+
+fn rotate_y(direction: vec3<f32>, angle: f32) -> vec3<f32> {
+    let s = sin(angle);
+    let c = cos(angle);
+    return vec3<f32>(direction.x * c + direction.z * s, direction.y, direction.z * c - direction.x * s);
+}
+
+// u goes around +y starting and ending behind -z, v runs from +y down to -y
+fn direction_to_uv(direction: vec3<f32>) -> vec2<f32> {
+    let d = rotate_y(direction, -environment.rotation);
+    let u = 0.5 + atan2(d.x, -d.z) / (2.0 * PI);
+    return vec2<f32>(fract(u), acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+fn uv_to_direction(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let sin_theta = sin(uv.y * PI);
+    let d = vec3<f32>(sin_theta * sin(phi), cos(uv.y * PI), -sin_theta * cos(phi));
+    return rotate_y(d, environment.rotation);
+}
+
+fn environment_pixel(uv: vec2<f32>) -> vec2<u32> {
+    return min(
+        vec2<u32>(uv * vec2<f32>(f32(environment.width), f32(environment.height))),
+        vec2<u32>(environment.width - 1u, environment.height - 1u)
+    );
+}
+
+fn luminance(rgb: vec3<f32>) -> f32 {
+    return dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Density per solid angle of sample_environment picking the direction at `uv`
+fn environment_pdf_uv(uv: vec2<f32>) -> f32 {
+    let sin_theta = sin(uv.y * PI);
+    if environment.total <= 0.0 || sin_theta <= 0.0 {
+        return 0.0;
+    }
+    let pixel = environment_pixel(uv);
+    let size = vec2<f32>(f32(environment.width), f32(environment.height));
+    let row_sin = sin((f32(pixel.y) + 0.5) / size.y * PI);
+    let weight = luminance(textureLoad(environment_map, pixel, 0).xyz) * row_sin;
+    return weight / environment.total * size.x * size.y / (2.0 * PI * PI * sin_theta);
+}
+
+fn environment_pdf(direction: vec3<f32>) -> f32 {
+    if environment.background != 1u {
+        return 0.0;
+    }
+    return environment_pdf_uv(direction_to_uv(direction));
+}
+
+// Index i in [0, count - 1) with environment_cdf[start + i] <= value < environment_cdf[start + i + 1]
+fn find_interval(start: u32, count: u32, value: f32) -> u32 {
+    var low = 0u;
+    var high = count - 2u;
+    while low < high {
+        let middle = (low + high + 1u) / 2u;
+        if environment_cdf[start + middle] <= value {
+            low = middle;
+        } else {
+            high = middle - 1u;
+        }
+    }
+    return low;
+}
+
+struct EnvironmentSample {
+    direction: vec3<f32>,
+    pdf: f32,
+}
+
+// Direction drawn in proportion to the brightness of the map, same as EnvironmentMap::sample
+fn sample_environment(rng: ptr<function, u32>) -> EnvironmentSample {
+    let r = random_vec2(rng);
+    let width = environment.width;
+    let height = environment.height;
+    let y = find_interval(0u, height + 1u, r.y);
+    let v = (f32(y) + (r.y - environment_cdf[y]) / (environment_cdf[y + 1u] - environment_cdf[y])) / f32(height);
+    let start = height + 1u + y * (width + 1u);
+    let x = find_interval(start, width + 1u, r.x);
+    let low = environment_cdf[start + x];
+    let u = (f32(x) + (r.x - low) / (environment_cdf[start + x + 1u] - low)) / f32(width);
+    return EnvironmentSample(uv_to_direction(vec2<f32>(u, v)), environment_pdf_uv(vec2<f32>(u, v)));
+}
+
+// daylight sky
+
+// Perez et al. distribution of one of Y, x and y over the sky
+fn perez(c: array<f32, 5>, cos_theta: f32, cos_gamma: f32, gamma: f32) -> f32 {
+    return (1.0 + c[0] * exp(c[1] / cos_theta)) * (1.0 + c[2] * exp(c[3] * gamma) + c[4] * cos_gamma * cos_gamma);
+}
+
+// Clear sky radiance of the Preetham model without the sun's disk, same as Daylight::radiance.
+// Below the horizon the ground reflects a share of the horizon's light
+fn daylight_radiance(direction: vec3<f32>) -> vec3<f32> {
+    let cos_theta = max(direction.y, 0.001);
+    let cos_gamma = clamp(dot(direction, sky.sun.xyz), -1.0, 1.0);
+    let gamma = acos(cos_gamma);
+    var yxy = vec3<f32>(0.0);
+    for (var k = 0; k < 3; k++) {
+        let c = array<f32, 5>(sky.perez[0][k], sky.perez[1][k], sky.perez[2][k], sky.perez[3][k], sky.perez[4][k]);
+        yxy[k] = sky.zenith[k] * perez(c, cos_theta, cos_gamma, gamma);
+    }
+    var sky_luminance = yxy.x * sky.zenith.w;
+    if direction.y < 0.0 {
+        sky_luminance *= 0.3;
+    }
+    if yxy.z <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    // xyY to XYZ to linear sRGB
+    let xyz = vec3<f32>(yxy.y, yxy.z, 1.0 - yxy.y - yxy.z) * sky_luminance / yxy.z;
+    let rgb = vec3<f32>(
+        dot(vec3<f32>(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3<f32>(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3<f32>(0.0557, -0.2040, 1.0570), xyz)
+    );
+    return max(rgb, vec3<f32>(0.0));
+}
+
+// The sun's disk, only seen by rays that did not sample it already as a directional light
+fn sun_disk_radiance(direction: vec3<f32>) -> vec3<f32> {
+    if environment.background != 2u || dot(direction, sky.sun.xyz) < sky.sun.w {
+        return vec3<f32>(0.0);
+    }
+    return sky.sun_radiance.xyz * environment.intensity;
+}
+
+// Light arriving from outside the scene along `direction`
+fn environment_radiance(direction: vec3<f32>) -> vec3<f32> {
+    if environment.background == 1u {
+        let pixel = environment_pixel(direction_to_uv(direction));
+        return textureLoad(environment_map, pixel, 0).xyz * environment.intensity;
+    }
+    if environment.background == 2u {
+        return daylight_radiance(direction) * environment.intensity;
+    }
+    return sample_skybox(direction).xyz * environment.intensity;
+}
+
 // BVH
 struct BVHNode {
     bounds: array<f32, 6>,
@@ -250,6 +630,17 @@ struct BVHNode {
     right_node: u32,
     padding_: array<u32, 6>,
 };
+// 4-wide node, one lane per child. Leaf lanes hold their first triangle in `children`
+struct BVHNode4 {
+    min_x: vec4<f32>,
+    min_y: vec4<f32>,
+    min_z: vec4<f32>,
+    max_x: vec4<f32>,
+    max_y: vec4<f32>,
+    max_z: vec4<f32>,
+    children: vec4<u32>,
+    triangle_counts: vec4<u32>,
+};
 struct Triangle {
     n: vec3<f32>,
     pad_n: f32,
@@ -262,6 +653,24 @@ struct Triangle {
 };
 
 
+// Running average of the previous frames and how many went into it, 0 starts over
+@group(4) @binding(0)
+var previous_frames: texture_2d<f32>;
+
+struct FrameInfo {
+  frame: u32,
+  padding_: vec3<u32>,
+}
+
+@group(4) @binding(1)
+var<uniform> frame_info: FrameInfo;
+
+// The average shown on screen, and the same average at full precision for the next frame
+struct FragmentOutput {
+  @location(0) color: vec4<f32>,
+  @location(1) accumulated: vec4<f32>,
+}
+
 // Define a struct to represent vertex output
 struct VertexOutput {
   @builtin(position) clip_position: vec4<f32>, // Clip space position
@@ -277,11 +686,12 @@ struct VertexInput {
 struct HitResult {
   distance: f32, // Distance to the hit point
   normal: vec3<f32>, // Normal vector at the hit point
+  triangle: u32, // Index into bvh_triangles, MAX_U32 for spheres
 }
 
 // Bind camera information to a uniform buffer
 @group(0) @binding(0)
-var<uniform> cam_info: mat4x4<f32>;
+var<uniform> camera: CamInfos;
 // [cam_pos[0], cam_pos[1], cam_pos[2], view_port_center[0]]
 // [view_port_center[1], view_port_center[2], pixel_width, pixel_height]
 // [half_width, half_height, x[0], x[1]]
@@ -294,6 +704,8 @@ var<storage, read> spheres: array<Sphere>;
 // Bind light data to a storage buffer
 @group(2) @binding(0)
 var<storage, read> lights: array<Light>;
+@group(2) @binding(1)
+var<storage, read> rect_lights: array<RectLight>;
 // Bind light count to a uniform buffer
 
 // Bind light data to a storage buffer
@@ -306,8 +718,51 @@ struct Count {
 
 @group(3) @binding(2) var<uniform> bvh_nodes_count: Count;
 @group(3) @binding(3) var<uniform> bvh_triangles_count: Count;
+@group(3) @binding(4) var<storage, read> bvh_nodes4: array<BVHNode4>;
+@group(3) @binding(5) var<uniform> bvh_nodes4_count: Count;
+
+// Color and material of each entry in bvh_triangles, the color multiplies the albedo
+struct TriangleShading {
+    color: vec4<f32>,
+    material: u32,
+    padding_: array<u32, 3>,
+}
+
+@group(3) @binding(6) var<storage, read> bvh_shading: array<TriangleShading>;
+
+// Materials referenced by spheres and mesh faces
+@group(5) @binding(0) var<storage, read> materials: array<Material>;
+
+// What rays leaving the scene see: the procedural clouds, an equirectangular map with tables for
+// sampling it by brightness, or the daylight sky. See rendering/environment.rs and sky.rs
+struct EnvironmentInfo {
+    width: u32,
+    height: u32,
+    background: u32, // 0 for the clouds, 1 for the map, 2 for the daylight sky
+    padding_0: u32,
+    rotation: f32, // Radians about +y
+    intensity: f32,
+    total: f32, // Sum of the sampling weights, 0 when there is nothing to sample
+    padding_1: f32,
+}
+
+@group(6) @binding(0) var environment_map: texture_2d<f32>;
+// Over the rows (height + 1 entries), then within each row (width + 1 each)
+@group(6) @binding(1) var<storage, read> environment_cdf: array<f32>;
+@group(6) @binding(2) var<uniform> environment: EnvironmentInfo;
+
+// Preetham sky parameters. Rows of the Perez coefficients and the zenith hold Y, x and y
+struct SkyInfo {
+    perez: array<vec4<f32>, 5>,
+    zenith: vec4<f32>, // Y x y over the Perez function at the zenith, and the scale of Y
+    sun: vec4<f32>, // Direction toward the sun, and the cosine of its angular radius
+    sun_radiance: vec4<f32>,
+}
+
+@group(6) @binding(3) var<uniform> sky: SkyInfo;
 
 const offset_count = 8u;
+const max_bounces = 8u;
 
 fn intersect_aabb(ray: Ray, bounds: array<f32, 6>) -> f32 {
     let inv_dir = ray.inv;
@@ -342,20 +797,20 @@ fn intersect_triangle(ray: Ray, tri: Triangle) -> HitResult {
     let det = dot(e1, ray_cross_e2);
 
     if (det > -0.0000001 && det < 0.0000001) {
-        return HitResult(MAX_FLOAT, vec3<f32>(0.0));
+        return HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
     }
 
     let inv_det = 1.0 / det;
     let s = ray.origin - tri.v0;
     let u = inv_det * dot(s, ray_cross_e2);
     if (u < 0.0 || u > 1.0) {
-        return HitResult(MAX_FLOAT, vec3<f32>(0.0));
+        return HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
     }
 
     let s_cross_e1 = cross(s, e1);
     let v = inv_det * dot(ray.direction, s_cross_e1);
     if (v < 0.0 || u + v > 1.0) {
-        return HitResult(MAX_FLOAT, vec3<f32>(0.0));
+        return HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
     }
 
     let t = inv_det * dot(e2, s_cross_e1); 
@@ -364,9 +819,9 @@ fn intersect_triangle(ray: Ray, tri: Triangle) -> HitResult {
         if (dot(ray.direction, normal) > 0.0) {
             normal = -normal;
         }
-        return HitResult(t, normal);
+        return HitResult(t, normal, MAX_U32);
     }
-    return HitResult(MAX_FLOAT, vec3<f32>(0.0));
+    return HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
 }
 
 fn traverse_bvh(ray: Ray) -> HitResult {
@@ -374,7 +829,7 @@ fn traverse_bvh(ray: Ray) -> HitResult {
     var stack_ptr: i32 = 0;
     stack[stack_ptr] = 0u;
     
-    var closest_hit = HitResult(MAX_FLOAT, vec3<f32>(0.0));
+    var closest_hit = HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
 
     while (stack_ptr >= 0) {
         let node_idx = stack[stack_ptr];
@@ -387,21 +842,30 @@ fn traverse_bvh(ray: Ray) -> HitResult {
             continue;
         }
 
-        if (node.triangle_count > 0u) {
+        // Inner nodes keep the triangle range of their subtree, only nodes without children are leaves
+        if (node.left_node == 0u && node.right_node == 0u) {
             for (var i = 0u; i < node.triangle_count; i = i + 1u) {
                 let tri_idx = node.start_triangle + i;
                 let tri = bvh_triangles[tri_idx];
                 let hit = intersect_triangle(ray, tri);
                 if (hit.distance < closest_hit.distance) {
                     closest_hit = hit;
+                    closest_hit.triangle = tri_idx;
                 }
             }
         } else {
             let left_idx = node.left_node;
             let right_idx = node.right_node;
             
-            let t_left = intersect_aabb(ray, bvh_nodes[left_idx].bounds);
-            let t_right = intersect_aabb(ray, bvh_nodes[right_idx].bounds);
+            // index 0 is the root, as a child it marks a missing side
+            var t_left = MAX_FLOAT;
+            var t_right = MAX_FLOAT;
+            if (left_idx != 0u) {
+                t_left = intersect_aabb(ray, bvh_nodes[left_idx].bounds);
+            }
+            if (right_idx != 0u) {
+                t_right = intersect_aabb(ray, bvh_nodes[right_idx].bounds);
+            }
             
             if (t_left < closest_hit.distance && t_right < closest_hit.distance) {
                 if (t_left < t_right) {
@@ -428,6 +892,309 @@ fn traverse_bvh(ray: Ray) -> HitResult {
     return closest_hit;
 }
 
+// Slab test of the ray against all four child boxes of a wide node at once
+fn intersect_aabb4(ray: Ray, node: BVHNode4) -> vec4<f32> {
+    let t1 = (node.min_x - ray.origin.x) * ray.inv.x;
+    let t2 = (node.max_x - ray.origin.x) * ray.inv.x;
+    let t3 = (node.min_y - ray.origin.y) * ray.inv.y;
+    let t4 = (node.max_y - ray.origin.y) * ray.inv.y;
+    let t5 = (node.min_z - ray.origin.z) * ray.inv.z;
+    let t6 = (node.max_z - ray.origin.z) * ray.inv.z;
+
+    let tmin = max(max(min(t1, t2), min(t3, t4)), min(t5, t6));
+    let tmax = min(min(max(t1, t2), max(t3, t4)), max(t5, t6));
+
+    let hit = tmax >= tmin & tmax > vec4<f32>(0.0) & node.children != vec4<u32>(MAX_U32);
+    return select(vec4<f32>(MAX_FLOAT), tmin, hit);
+}
+
+fn traverse_bvh4(ray: Ray) -> HitResult {
+    var stack: array<u32, 64>;
+    var stack_ptr: i32 = 0;
+    stack[stack_ptr] = 0u;
+
+    var closest_hit = HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
+
+    while (stack_ptr >= 0) {
+        let node = bvh_nodes4[stack[stack_ptr]];
+        stack_ptr = stack_ptr - 1;
+
+        let t_children = intersect_aabb4(ray, node);
+
+        // Leaves are tested right away, inner children are collected for the stack
+        var inner_t: array<f32, 4>;
+        var inner_idx: array<u32, 4>;
+        var inner_count = 0u;
+        for (var slot = 0u; slot < 4u; slot = slot + 1u) {
+            if (t_children[slot] >= closest_hit.distance) {
+                continue;
+            }
+            if (node.triangle_counts[slot] > 0u) {
+                for (var i = 0u; i < node.triangle_counts[slot]; i = i + 1u) {
+                    let tri_idx = node.children[slot] + i;
+                    let hit = intersect_triangle(ray, bvh_triangles[tri_idx]);
+                    if (hit.distance < closest_hit.distance) {
+                        closest_hit = hit;
+                        closest_hit.triangle = tri_idx;
+                    }
+                }
+            } else {
+                inner_t[inner_count] = t_children[slot];
+                inner_idx[inner_count] = node.children[slot];
+                inner_count = inner_count + 1u;
+            }
+        }
+
+        // Sort the inner children far to near so the nearest one is popped first
+        for (var a = 1u; a < inner_count; a = a + 1u) {
+            var b = a;
+            while (b > 0u && inner_t[b - 1u] < inner_t[b]) {
+                let t = inner_t[b];
+                inner_t[b] = inner_t[b - 1u];
+                inner_t[b - 1u] = t;
+                let idx = inner_idx[b];
+                inner_idx[b] = inner_idx[b - 1u];
+                inner_idx[b - 1u] = idx;
+                b = b - 1u;
+            }
+        }
+        for (var a = 0u; a < inner_count; a = a + 1u) {
+            if (inner_t[a] < closest_hit.distance) {
+                stack_ptr = stack_ptr + 1;
+                stack[stack_ptr] = inner_idx[a];
+            }
+        }
+    }
+
+    return closest_hit;
+}
+
+// Closest mesh hit through whichever tree layout is uploaded
+fn traverse_mesh(ray: Ray) -> HitResult {
+    if (bvh_nodes4_count.count > 0u) {
+        return traverse_bvh4(ray);
+    }
+    return traverse_bvh(ray);
+}
+
+// lights
+
+// Distance along the ray to a rectangle light, from either side
+fn hit_rect_light(r: Ray, light: RectLight) -> f32 {
+    let n = cross(light.edge_u, light.edge_v);
+    let denom = dot(r.direction, n);
+    if denom == 0.0 {
+        return MAX_FLOAT;
+    }
+    let t = dot(light.corner - r.origin, n) / denom;
+    if t < 0.001 {
+        return MAX_FLOAT;
+    }
+    let offset = ray_at(r, t) - light.corner;
+    let u = dot(offset, light.edge_u) / dot(light.edge_u, light.edge_u);
+    let v = dot(offset, light.edge_v) / dot(light.edge_v, light.edge_v);
+    if u < 0.0 || u > 1.0 || v < 0.0 || v > 1.0 {
+        return MAX_FLOAT;
+    }
+    return t;
+}
+
+// Density per solid angle seen from `viewer` of picking `point` uniformly over the rectangle, 0
+// from behind. Same as RectLight::pdf
+fn rect_light_pdf(light: RectLight, viewer: vec3<f32>, point: vec3<f32>) -> f32 {
+    let n = cross(light.edge_u, light.edge_v);
+    let area = length(n);
+    let offset = point - viewer;
+    let distance2 = dot(offset, offset);
+    let cos_light = -dot(offset, n) / (area * sqrt(distance2));
+    if cos_light <= 0.0 {
+        return 0.0;
+    }
+    return distance2 / (area * cos_light);
+}
+
+// 1 - cos of the half angle of the cone a sphere covers seen from `viewer`, 0 from inside. Written
+// without the cancellation of 1 - sqrt(1 - sin^2) for small or distant spheres
+fn sphere_cone_size(center: vec3<f32>, radius: f32, viewer: vec3<f32>) -> f32 {
+    let offset = center - viewer;
+    let sin2 = radius * radius / dot(offset, offset);
+    if sin2 >= 1.0 {
+        return 0.0;
+    }
+    return sin2 / (1.0 + sqrt(1.0 - sin2));
+}
+
+// Density per solid angle of sampling the cone of a sphere uniformly
+fn sphere_light_pdf(center: vec3<f32>, radius: f32, viewer: vec3<f32>) -> f32 {
+    let size = sphere_cone_size(center, radius, viewer);
+    if size <= 0.0 {
+        return 0.0;
+    }
+    return 1.0 / (2.0 * PI * size);
+}
+
+fn sample_sphere_light(
+    center: vec3<f32>,
+    radius: f32,
+    viewer: vec3<f32>,
+    rng: ptr<function, u32>
+) -> vec3<f32> {
+    let u = random_vec2(rng);
+    let cos_theta = 1.0 - u.x * sphere_cone_size(center, radius, viewer);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * u.y;
+    let local_dir = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_frame(normalize(center - viewer)) * local_dir);
+}
+
+// Weight of a sample from the strategy with density `a` against the one with density `b`
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    let a2 = a * a;
+    return a2 / (a2 + b * b);
+}
+
+// Irradiance in units of the color on a surface at `point` facing the light. Same as
+// Light::falloff
+fn light_falloff(light: Light, point: vec3<f32>) -> f32 {
+    if light.kind == LIGHT_DIRECTIONAL {
+        return light.intensity;
+    }
+    let offset = point - light.position;
+    let distance2 = dot(offset, offset);
+    var falloff = light.intensity / distance2; // Inverse square falloff of the intensity
+    if light.kind == LIGHT_SPOT {
+        let cos_axis = dot(offset, light.direction) / sqrt(distance2);
+        let cos_outer = cos(light.outer_angle);
+        let cos_inner = cos(light.inner_angle);
+        // Equal angles give a hard edge, smoothstep is undefined without a range
+        if cos_inner > cos_outer {
+            falloff *= smoothstep(cos_outer, cos_inner, cos_axis);
+        } else {
+            falloff *= step(cos_outer, cos_axis);
+        }
+    }
+    return falloff;
+}
+
+// Whether anything lies on the ray closer than `distance`
+fn occluded(ray: Ray, distance: f32) -> bool {
+    for (var k = 0u; k < arrayLength(&spheres); k++) {
+        let sphere = spheres[k];
+        if sphere.radius > 0.0 && hit_sphere_result(ray, sphere.center, sphere.radius).distance < distance {
+            return true;
+        }
+    }
+    for (var k = 0u; k < arrayLength(&rect_lights); k++) {
+        if hit_rect_light(ray, rect_lights[k]) < distance {
+            return true;
+        }
+    }
+    return bvh_nodes_count.count > 0u && traverse_mesh(ray).distance < distance;
+}
+
+// Light arriving at a surface point directly from every light, leaving toward `wo`. Area lights
+// get one sample each, weighted against the chance of the BSDF finding them. `skip_sphere` is the
+// sphere the point lies on
+fn sample_lights(
+    point: vec3<f32>,
+    facing: vec3<f32>,
+    wo: vec3<f32>,
+    material: Material,
+    albedo: vec3<f32>,
+    skip_sphere: u32,
+    rng: ptr<function, u32>
+) -> vec3<f32> {
+    var radiance = vec3<f32>(0.0);
+    let origin = point + facing * 0.001;
+
+    for (var l = 0u; l < arrayLength(&lights); l++) {
+        let light = lights[l];
+        if light.is_valid != 1u {
+            continue;
+        }
+        var r_d = normalize(light.position - point); // Direction to the light
+        var dist = length(light.position - point);
+        if light.kind == LIGHT_DIRECTIONAL {
+            // a random direction within the light's disk gives soft shadows, each carrying the
+            // whole irradiance
+            r_d = -light.direction;
+            if light.angular_diameter > 0.0 {
+                let half_sine = sin(0.25 * light.angular_diameter);
+                let cone = 2.0 * half_sine * half_sine; // 1 - cos of the half angle
+                let u = random_vec2(rng);
+                let cos_theta = 1.0 - u.x * cone;
+                let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+                let phi = 2.0 * PI * u.y;
+                let local_dir = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+                r_d = normalize(tangent_frame(r_d) * local_dir);
+            }
+            dist = MAX_FLOAT;
+        }
+        let falloff = light_falloff(light, point);
+        let bsdf = evaluate_bsdf(material, albedo, facing, wo, r_d);
+        if falloff <= 0.0 || all(bsdf == vec3<f32>(0.0)) || occluded(Ray(origin, r_d, 1.0 / r_d), dist) {
+            continue;
+        }
+        radiance += light.color.xyz * falloff * bsdf;
+    }
+
+    for (var k = 0u; k < arrayLength(&spheres); k++) {
+        let sphere = spheres[k];
+        let emission = materials[sphere.material].emission;
+        if k == skip_sphere || sphere.radius <= 0.0 || all(emission == vec3<f32>(0.0)) {
+            continue;
+        }
+        let light_pdf = sphere_light_pdf(sphere.center, sphere.radius, point);
+        if light_pdf == 0.0 {
+            continue;
+        }
+        let r_d = sample_sphere_light(sphere.center, sphere.radius, point, rng);
+        let bsdf = evaluate_bsdf(material, albedo, facing, wo, r_d);
+        let shadow_ray = Ray(origin, r_d, 1.0 / r_d);
+        let dist = hit_sphere_result(shadow_ray, sphere.center, sphere.radius).distance;
+        if all(bsdf == vec3<f32>(0.0)) || dist == MAX_FLOAT || occluded(shadow_ray, dist * 0.999) {
+            continue;
+        }
+        let weight = power_heuristic(light_pdf, bsdf_pdf(material, albedo, facing, wo, r_d));
+        radiance += emission * bsdf * weight / light_pdf;
+    }
+
+    for (var k = 0u; k < arrayLength(&rect_lights); k++) {
+        let light = rect_lights[k];
+        if all(light.emission == vec3<f32>(0.0)) {
+            continue;
+        }
+        let u = random_vec2(rng);
+        let light_point = light.corner + light.edge_u * u.x + light.edge_v * u.y;
+        let light_pdf = rect_light_pdf(light, point, light_point);
+        if light_pdf == 0.0 {
+            continue;
+        }
+        let offset = light_point - origin;
+        let dist = length(offset);
+        let r_d = offset / dist;
+        let bsdf = evaluate_bsdf(material, albedo, facing, wo, r_d);
+        if all(bsdf == vec3<f32>(0.0)) || occluded(Ray(origin, r_d, 1.0 / r_d), dist * 0.999) {
+            continue;
+        }
+        let weight = power_heuristic(light_pdf, bsdf_pdf(material, albedo, facing, wo, r_d));
+        radiance += light.emission * bsdf * weight / light_pdf;
+    }
+
+    // The environment map, drawn by brightness so small bright sources like the sun are found
+    if environment.background == 1u && environment.total > 0.0 {
+        let sample = sample_environment(rng);
+        let bsdf = evaluate_bsdf(material, albedo, facing, wo, sample.direction);
+        if sample.pdf > 0.0 && any(bsdf != vec3<f32>(0.0))
+            && !occluded(Ray(origin, sample.direction, 1.0 / sample.direction), MAX_FLOAT) {
+            let weight = power_heuristic(sample.pdf, bsdf_pdf(material, albedo, facing, wo, sample.direction));
+            radiance += environment_radiance(sample.direction) * bsdf * weight / sample.pdf;
+        }
+    }
+
+    return radiance;
+}
+
 // Define the vertex shader
 @vertex
 fn vs_main(
@@ -450,56 +1217,72 @@ fn vs_main(
 }
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_main(in: VertexOutput) -> FragmentOutput {
     // Convert the vertex position from [-1,1] range to [0,1] for screen space
     var i = (in.vert_pos.x + 1.);
     var j = 1. - (in.vert_pos.y);
 
     // Extract camera information (position, viewport center, dimensions, orientation) from camInfos
+    let cam_info = camera.cam_info;
+    let orthographic = camera.projection.x > 0.5;
     let cam_pos = vec3<f32>(cam_info[0].x, cam_info[0].y, cam_info[0].z);
     let view_port_center = vec3<f32>(cam_info[0].w, cam_info[1].x, cam_info[1].y);
     let x = vec3<f32>(cam_info[2].z, cam_info[2].w, cam_info[3].x);
     let y = vec3<f32>(cam_info[3].y, cam_info[3].z, cam_info[3].w);
 
     // Map i and j into screen space coordinates
-    // Initialize the final color as black and set initial attenuation for lighting calculations
-    var final_color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
-    let half_count = offset_count / 2;
-    var offsets: array<vec2<f32>, offset_count>;
-    for (var idx = 0u; idx < half_count; idx++) {
-        for (var idx2 = 0u; idx2 < half_count; idx2++) {
-            let seed = vec2<f32>(in.vert_pos.x,in.vert_pos.y) * f32(idx + 1u) * 127.1;
-            offsets[idx * half_count + idx2] = vec2<f32>(rand(seed), rand(seed + vec2<f32>(1.0, 0.0)));
-        }
-    }
+    // Initialize the final color as black
+    var final_color = vec4<f32>(0.0);
+    let pixel = vec2<u32>(in.clip_position.xy);
 
     for (var idx = 0u; idx < offset_count; idx++) {
-        var ray_color = vec4(0., 0., 0., 1.);
-        let u = (i - 1. + offsets[idx].x * cam_info[1].z) * cam_info[2].x;
-        let v = (j - 1. + offsets[idx].y * cam_info[1].w) * cam_info[2].y;
+        // Every sample of every frame draws its own numbers, jitter within the pixel comes first
+        var rng = rng_seed(pixel, frame_info.frame, idx);
+        let offset = random_vec2(&rng) - 0.5;
+        let u = (i - 1. + offset.x * cam_info[1].z) * cam_info[2].x;
+        let v = (j - 1. + offset.y * cam_info[1].w) * cam_info[2].y;
     
         // Calculate the center of the pixel in world space
         let pixel_center = view_port_center + x * u + y * v;
     
-        // Define ray direction and inverse direction based on camera position and pixel center
-        let ray_direction = normalize(pixel_center - cam_pos);
+        // Define ray direction and inverse direction based on camera position and pixel center.
+        // Orthographic rays start on the pixel and all run parallel to the view direction
+        var ray_origin = cam_pos;
+        var ray_direction = normalize(pixel_center - cam_pos);
+        if orthographic {
+            ray_origin = pixel_center;
+            ray_direction = normalize(view_port_center - cam_pos);
+        } else if camera.projection.z > 0.0 {
+            // thin lens: start on the lens disk and aim at the point the center ray reaches on the
+            // sharp plane, so only that plane stays in focus
+            let forward = normalize(view_port_center - cam_pos);
+            let focal_point = cam_pos + ray_direction * (camera.projection.w / dot(ray_direction, forward));
+            let lens = concentric_disk(random_vec2(&rng));
+            ray_origin = cam_pos + (x * lens.x + y * lens.y) * camera.projection.z;
+            ray_direction = normalize(focal_point - ray_origin);
+        }
         let ray_inv = 1.0 / ray_direction;
     
-        // Initialize the ray with origin (camera position), direction, and inverse direction
-        var ray: Ray = Ray(cam_pos, ray_direction, ray_inv);
-
-
-        var attenuation = vec3<f32>(1.0, 1.0, 1.0);
+        // Initialize the ray with origin, direction, and inverse direction
+        var ray: Ray = Ray(ray_origin, ray_direction, ray_inv);
+
+
+        // Light gathered along the path, and the fraction of light arriving at the current vertex
+        // that reaches the camera
+        var radiance = vec3<f32>(0.0);
+        var throughput = vec3<f32>(1.0);
+        // Where the path last scattered, and the bsdf_pdf of the direction it took, 0 for camera
+        // rays and glass whose emitters light sampling cannot have counted already
+        var previous_point = ray.origin;
+        var previous_pdf = 0.0;
     
-        // Boolean to check if a sphere is hit during ray tracing
-        var is_hit_sphere = false;
-    
-        // Loop over possible bounces (up to 5) for reflection/refraction effects
-        for (var bounce = 0u; bounce < 5u; bounce++) {
+        for (var bounce = 0u; bounce < max_bounces; bounce++) {
             // Initialize closest hit result with maximum distance and no hit point
-            var closest_hit = HitResult(MAX_FLOAT, vec3<f32>(0.0));
-            var hit_sphere: Sphere; // Placeholder for the hit sphere
-            var hit_light: Light;   // Placeholder for the hit light
+            var closest_hit = HitResult(MAX_FLOAT, vec3<f32>(0.0), MAX_U32);
+            var material_index = 0u;
+            var tint = vec3<f32>(1.0);
+            var hit_sphere = MAX_U32;
+            var hit_rect = MAX_U32;
     
             // Check for intersections with each sphere in the scene
             for (var l = 0u; l < arrayLength(&spheres); l++) {
@@ -514,109 +1297,114 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
                 // Update closest hit if a nearer hit is found
                 if hit_result.distance < closest_hit.distance {
                     closest_hit = hit_result;
-                    hit_sphere = sphere;
-                    is_hit_sphere = true;
+                    material_index = sphere.material;
+                    hit_sphere = l;
+                }
+            }
+
+            // Rectangle lights are seen directly and end the path
+            for (var l = 0u; l < arrayLength(&rect_lights); l++) {
+                let t = hit_rect_light(ray, rect_lights[l]);
+                if t < closest_hit.distance {
+                    closest_hit = HitResult(t, vec3<f32>(0.0), MAX_U32);
+                    hit_sphere = MAX_U32;
+                    hit_rect = l;
                 }
             }
 
             // Check for intersections with the BVH
             if (bvh_nodes_count.count > 0u) {
-                let hit_bvh = traverse_bvh(ray);
+                let hit_bvh = traverse_mesh(ray);
                 if (hit_bvh.distance < closest_hit.distance) {
                     closest_hit = hit_bvh;
-                    is_hit_sphere = true; // Set to true to proceed with lighting/skybox logic
-                    // Assign default material for meshes
-                    hit_sphere = Sphere(vec3<f32>(0.0), 0.0, vec4<f32>(0.8, 0.8, 0.8, 1.0), 2, 0.15);
+                    hit_sphere = MAX_U32;
+                    hit_rect = MAX_U32;
+                    // Meshes take the material and color of the hit triangle
+                    let shading = bvh_shading[closest_hit.triangle];
+                    material_index = shading.material;
+                    tint = shading.color.xyz;
                 }
             }
     
-            // If no intersection with spheres, calculate skybox color and break out of loop
+            // Rays leaving the scene pick up the environment, weighted against sampling the map
             if closest_hit.distance == MAX_FLOAT {
-                if is_hit_sphere {
-                    let sky_contribution = (sample_skybox(ray.direction)) / (10.0 * f32(bounce + 1u));
-                    ray_color += sky_contribution;
-                } else {
-                    let sky_contribution = sample_skybox(ray.direction);
-                    ray_color = sky_contribution;
+                var weight = 1.0;
+                if previous_pdf > 0.0 {
+                    weight = power_heuristic(previous_pdf, environment_pdf(ray.direction));
+                }
+                radiance += throughput * environment_radiance(ray.direction) * weight;
+                // The sun is a directional light, so only rays that could not sample it see it
+                if previous_pdf == 0.0 {
+                    radiance += throughput * sun_disk_radiance(ray.direction);
                 }
                 break;
             }
-    
-            // Calculate the hit point based on the ray and the closest hit distance
+
             let hit_point = ray_at(ray, closest_hit.distance);
+            if hit_rect != MAX_U32 {
+                let light = rect_lights[hit_rect];
+                if dot(ray.direction, cross(light.edge_u, light.edge_v)) < 0.0 {
+                    var weight = 1.0;
+                    if previous_pdf > 0.0 {
+                        weight = power_heuristic(previous_pdf, rect_light_pdf(light, previous_point, hit_point));
+                    }
+                    radiance += throughput * light.emission * weight;
+                }
+                break;
+            }
     
-            // Loop over lights to calculate direct illumination at the hit point
-            for (var l = 0u; l < arrayLength(&lights); l++) {
-                if lights[l].is_valid == 1 {
-                    let light = lights[l];
-                    let r_d = normalize(light.position - hit_point); // Direction to the light
-                    let shadow_origin = hit_point + closest_hit.normal * 0.001;
-                    let ray_2 = Ray(shadow_origin, r_d, 1.0 / r_d);
-                    let dist_to_light = length(light.position - hit_point);
+            let material = materials[material_index];
+            let albedo = material.albedo * tint;
+            // Triangle hits face the ray, refraction needs the side the stored normal points out of
+            var normal = closest_hit.normal;
+            if closest_hit.triangle != MAX_U32 {
+                normal = normalize(bvh_triangles[closest_hit.triangle].n);
+            }
+            let facing = select(-normal, normal, dot(ray.direction, normal) < 0.0);
+
+            // Emissive spheres were already sampled as lights from the previous point, emissive
+            // triangles are only found this way
+            var emission_weight = 1.0;
+            if hit_sphere != MAX_U32 && previous_pdf > 0.0 {
+                let sphere = spheres[hit_sphere];
+                let light_pdf = sphere_light_pdf(sphere.center, sphere.radius, previous_point);
+                emission_weight = power_heuristic(previous_pdf, light_pdf);
+            }
+            radiance += throughput * material.emission * emission_weight;
     
-                    // Check if light is visible by tracing a shadow ray
-                    var hit_found = false;
-                    for (var k = 0u; k < arrayLength(&spheres); k++) {
-                        let sphere = spheres[k];
-                        let hit_result = hit_sphere_result(ray_2, sphere.center, sphere.radius);
-                        if (hit_result.distance < dist_to_light) {
-                            hit_found = true;
-                            break;
-                        }
-                    }
-                    if (!hit_found && bvh_nodes_count.count > 0u) {
-                        let hit_bvh = traverse_bvh(ray_2);
-                        if (hit_bvh.distance < dist_to_light) {
-                            hit_found = true;
-                        }
-                    }
+            radiance += throughput * sample_lights(hit_point, facing, -ray.direction, material, albedo, hit_sphere, &rng);
     
-                    // If no objects obstruct the light, calculate lighting contribution
-                    if !hit_found {
-                        let dist = length(light.position - hit_point);
-                        let final_intensity = light.intensity / (dist * dist / 1000);
-                        let max_value = max(dot(closest_hit.normal, r_d), 0.);
-                        var diffuse = vec4<f32>(
-                            hit_sphere.color.xyz * max_value,
-                            hit_sphere.color.w
-                        );
-                        ray_color += light.color * final_intensity * diffuse;
-                    }
+            // Continue the path in a direction drawn from the material
+            let next = sample_bsdf(material, albedo, normal, ray.direction, &rng);
+            throughput *= next.weight;
+            previous_point = hit_point;
+            previous_pdf = next.pdf;
+
+            // Russian roulette: end dim paths early, boosting the survivors to stay unbiased
+            if bounce >= 3u {
+                let survival = clamp(max(throughput.x, max(throughput.y, throughput.z)), 0.05, 1.0);
+                if random_float(&rng) >= survival {
+                    break;
                 }
+                throughput /= survival;
             }
-    
-            // Handle different material types
-            if hit_sphere.material < 1.e-1 { // Diffuse material
-                ray_color += vec4<f32>(0.1, 0.1, 0.1, 1.0) * hit_sphere.color;
+            if all(throughput == vec3<f32>(0.0)) {
                 break;
-            } else if hit_sphere.material <= 1. { // Reflective material
-                let specular_ratio = smoothstep(0.0, 1.0, hit_sphere.material);
-                let reflected_dir = reflect(ray.direction, closest_hit.normal);
-                // let seed = vec2<f32>(in.vert_pos.xy) * f32(bounce + 1u) * 127.1;
-                let diffuse_dir = random_hemisphere_direction(closest_hit.normal, vec2<f32>(10.)); // Generate a random direction
-                ray.direction = mix(diffuse_dir, reflected_dir, specular_ratio);
-            } else { // Refractive material
-                let cos_theta = min(dot(-ray.direction, closest_hit.normal), 1.0);
-                let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
-                let refraction_ratio = 1.0 / hit_sphere.refactivity;  // Air to glass
-                if refraction_ratio * sin_theta > 1.0 { // Total internal reflection
-                    ray.direction = reflect(ray.direction, closest_hit.normal);
-                } else { // Calculate refraction direction
-                    ray.direction = refract_ray(ray.direction, closest_hit.normal, 1.0, 1.5);
-                }
             }
     
-            // Move the ray origin slightly along the normal to prevent self-intersection
-            ray.origin = hit_point + closest_hit.normal * 0.001;
+            // Move the ray origin slightly off the surface, on the side the path continues on
+            ray.origin = hit_point + facing * select(-0.001, 0.001, dot(next.direction, facing) > 0.0);
+            ray.direction = next.direction;
             ray.inv = 1.0 / ray.direction;
-            attenuation *= hit_sphere.material;
         }
 
-        final_color += ray_color;
+        final_color += vec4<f32>(radiance, 1.0);
     }
 
     final_color /= f32(offset_count);
 
-    // Return the calculated color after all bounces and lighting computations
-    return final_color;
+    // Blend this frame into the average of the previous ones
+    let previous = textureLoad(previous_frames, vec2<i32>(in.clip_position.xy), 0);
+    let average = mix(previous, final_color, 1.0 / f32(frame_info.frame + 1u));
+    return FragmentOutput(average, average);
 }
@@ -0,0 +1,63 @@
+// Line overlay drawn on top of the ray traced image, e.g. mesh cross-sections
+
+struct VertexInput {
+  @location(0) position: vec3<f32>, // World space position
+}
+
+struct VertexOutput {
+  @builtin(position) clip_position: vec4<f32>,
+}
+
+// Same camera uniform as main.wgsl
+struct CamInfos {
+  cam_info: mat4x4<f32>,
+  projection: vec4<f32>, // x: 1 for orthographic else 0, y: far, z: aperture, w: focus distance
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CamInfos;
+// [cam_pos[0], cam_pos[1], cam_pos[2], view_port_center[0]]
+// [view_port_center[1], view_port_center[2], pixel_width, pixel_height]
+// [half_width, half_height, x[0], x[1]]
+// [x[2], y[0], y[1], y[2]]
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let cam_info = camera.cam_info;
+    let cam_pos = vec3<f32>(cam_info[0].x, cam_info[0].y, cam_info[0].z);
+    let view_port_center = vec3<f32>(cam_info[0].w, cam_info[1].x, cam_info[1].y);
+    let x = vec3<f32>(cam_info[2].z, cam_info[2].w, cam_info[3].x);
+    let y = vec3<f32>(cam_info[3].y, cam_info[3].z, cam_info[3].w);
+
+    // inverse of the camera ray setup in main.wgsl: scale the point onto the viewport, which sits
+    // at the near distance, and divide by its half extents. Depth goes into w so lines behind the
+    // camera get clipped
+    let forward = view_port_center - cam_pos;
+    let near = length(forward);
+    let d = in.position - cam_pos;
+    let depth = dot(d, forward / near);
+
+    var out: VertexOutput;
+    // orthographic views skip the division, depth is kept in z over the far distance instead
+    if camera.projection.x > 0.5 {
+        out.clip_position = vec4<f32>(
+            dot(d, x) / cam_info[2].x,
+            -dot(d, y) / cam_info[2].y,
+            clamp(depth / camera.projection.y, 0.0, 1.0),
+            1.0
+        );
+        return out;
+    }
+    out.clip_position = vec4<f32>(
+        dot(d, x) * near / cam_info[2].x,
+        -dot(d, y) * near / cam_info[2].y,
+        0.0,
+        depth
+    );
+    return out;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.85, 0.1, 1.0);
+}
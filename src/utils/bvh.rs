@@ -1,11 +1,18 @@
+use std::ops::Range;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use crate::utils::distance::WindingNumbers;
+use crate::utils::matrix::Mat4;
 use crate::utils::mesh::Mesh;
+use crate::utils::polyline::{chain_segments, Polyline};
+use crate::utils::ray::Ray;
+use crate::utils::vector::Vec3;
 use crate::utils::MinHeap;
 use rayon::max_num_threads;
 use rayon::prelude::*;
 
+use super::bvh4::{collapse, Node4, BVH4};
 use super::EntityCount;
 
 #[repr(C)]
@@ -46,10 +53,105 @@ pub struct Node {
     pub padding_: [u32; 6],  // 24 bytes — keeps struct 64-byte total
 }
 
+// surface properties of a mesh triangle. The color multiplies the albedo of the material, so
+// per face colors like a deviation heatmap can share one material
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::NoUninit)]
+pub struct TriangleShading {
+    pub color: [f32; 4],
+    pub material: u32, // index into the material buffer
+    pub padding_: [u32; 3],
+}
+
+impl Default for TriangleShading {
+    fn default() -> Self {
+        Self {
+            color: [0.8, 0.8, 0.8, 1.],
+            material: 0,
+            padding_: [0; 3],
+        }
+    }
+}
+
 #[repr(C)]
 pub struct BVH {
     pub nodes: Vec<Node>,
     pub triangles: Vec<Triangle2>,
+    pub face_indices: Vec<u32>, // mesh face each entry of `triangles` was built from
+    pub node_areas: Vec<f32>,   // surface area of each node when it was last (re)built
+    pub depth: u8,
+}
+
+// a refitted node whose surface area grew past this factor gets its subtree rebuilt
+pub const REBUILD_THRESHOLD: f32 = 2.0;
+
+// element ranges of `nodes` and `triangles` that changed during a refit
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BvhUpdate {
+    pub nodes: Option<Range<usize>>,
+    pub triangles: Option<Range<usize>>,
+}
+
+fn merge_range(range: &mut Option<Range<usize>>, other: Range<usize>) {
+    *range = match range.take() {
+        Some(r) => Some(r.start.min(other.start)..r.end.max(other.end)),
+        None => Some(other),
+    };
+}
+
+pub fn surface_area(bounds: &[f32; 6]) -> f32 {
+    let x = (bounds[3] - bounds[0]).max(0.);
+    let y = (bounds[4] - bounds[1]).max(0.);
+    let z = (bounds[5] - bounds[2]).max(0.);
+    2. * (x * y + y * z + z * x)
+}
+
+pub fn is_leaf(node: &Node) -> bool {
+    node.left_node == 0 && node.right_node == 0
+}
+
+fn triangle_from_face(mesh: &Mesh, face_index: usize) -> Triangle2 {
+    let face = mesh.faces[face_index];
+    Triangle2 {
+        normal: mesh.normals[face_index],
+        v1: mesh.vertices[face[0]],
+        v2: mesh.vertices[face[1]],
+        v3: mesh.vertices[face[2]],
+        ..Default::default()
+    }
+}
+
+fn triangle_center(tri: &Triangle2) -> [f32; 3] {
+    [
+        (tri.v1[0] + tri.v2[0] + tri.v3[0]) / 3.0,
+        (tri.v1[1] + tri.v2[1] + tri.v3[1]) / 3.0,
+        (tri.v1[2] + tri.v2[2] + tri.v3[2]) / 3.0,
+    ]
+}
+
+// face normal from the winding of the (moved) vertices, the stored STL normal is stale once they move
+fn geometric_normal(tri: &Triangle2) -> [f32; 3] {
+    let e1 = [
+        tri.v2[0] - tri.v1[0],
+        tri.v2[1] - tri.v1[1],
+        tri.v2[2] - tri.v1[2],
+    ];
+    let e2 = [
+        tri.v3[0] - tri.v1[0],
+        tri.v3[1] - tri.v1[1],
+        tri.v3[2] - tri.v1[2],
+    ];
+    let n = [
+        e1[1] * e2[2] - e1[2] * e2[1],
+        e1[2] * e2[0] - e1[0] * e2[2],
+        e1[0] * e2[1] - e1[1] * e2[0],
+    ];
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if length > 0. {
+        [n[0] / length, n[1] / length, n[2] / length]
+    } else {
+        tri.normal
+    }
 }
 
 pub fn create_bvh(mesh: &Mesh, depth: u8) -> BVH {
@@ -139,9 +241,26 @@ pub fn create_bvh(mesh: &Mesh, depth: u8) -> BVH {
         padding_: [0, 0, 0, 0, 0, 0],
     };
     let mut nodes = vec![root_node];
-    let depth = depth - 1;
-    create_nodes(&mut triangle_centers, &mut triangles, &mut nodes, depth, 0);
-    BVH { nodes, triangles }
+    let mut face_indices: Vec<u32> = (0..triangles.len() as u32).collect();
+    create_nodes(
+        &mut triangle_centers,
+        &mut triangles,
+        &mut face_indices,
+        &mut nodes,
+        depth - 1,
+        0,
+    );
+    let node_areas = nodes
+        .iter()
+        .map(|node| surface_area(&node.bounds))
+        .collect();
+    BVH {
+        nodes,
+        triangles,
+        face_indices,
+        node_areas,
+        depth,
+    }
 }
 
 pub fn compute_bounds(triangles: &[Triangle2], start: usize, end: usize) -> [f32; 6] {
@@ -163,6 +282,7 @@ pub fn compute_bounds(triangles: &[Triangle2], start: usize, end: usize) -> [f32
 pub fn create_nodes(
     triangle_centers: &mut Vec<[f32; 3]>,
     triangles: &mut Vec<Triangle2>,
+    face_indices: &mut [u32],
     nodes: &mut Vec<Node>,
     depth: u8,
     parent_node_index: usize,
@@ -193,6 +313,7 @@ pub fn create_nodes(
                 let swap_index = right_indices.pop().unwrap();
                 triangles.swap(i, swap_index as usize);
                 triangle_centers.swap(i, swap_index as usize);
+                face_indices.swap(i, swap_index as usize);
                 last_left_triangle_index = swap_index;
             } else {
                 last_left_triangle_index = i as u32;
@@ -222,6 +343,7 @@ pub fn create_nodes(
         create_nodes(
             triangle_centers,
             triangles,
+            face_indices,
             nodes,
             current_dept,
             left_node_index,
@@ -247,6 +369,7 @@ pub fn create_nodes(
         create_nodes(
             triangle_centers,
             triangles,
+            face_indices,
             nodes,
             current_dept,
             right_node_index,
@@ -254,36 +377,430 @@ pub fn create_nodes(
     }
 }
 
+// largest triangle count the LBVH builder stops splitting at
+pub const LBVH_LEAF_SIZE: usize = 4;
+
+// spreads the low 10 bits of `v` so two zero bits sit between each of them
+fn expand_bits_10(v: u32) -> u32 {
+    let mut x = v & 0x3ff;
+    x = (x | (x << 16)) & 0x030000ff;
+    x = (x | (x << 8)) & 0x0300f00f;
+    x = (x | (x << 4)) & 0x030c30c3;
+    x = (x | (x << 2)) & 0x09249249;
+    x
+}
+
+// spreads the low 21 bits of `v` so two zero bits sit between each of them
+fn expand_bits_21(v: u64) -> u64 {
+    let mut x = v & 0x1fffff;
+    x = (x | (x << 32)) & 0x001f00000000ffff;
+    x = (x | (x << 16)) & 0x001f0000ff0000ff;
+    x = (x | (x << 8)) & 0x100f00f00f00f00f;
+    x = (x | (x << 4)) & 0x10c30c30c30c30c3;
+    x = (x | (x << 2)) & 0x1249249249249249;
+    x
+}
+
+// 30-bit morton code of a point normalized to the unit cube
+pub fn morton_code_30(p: [f32; 3]) -> u32 {
+    let scale = |c: f32| (c * 1024.).clamp(0., 1023.) as u32;
+    (expand_bits_10(scale(p[0])) << 2)
+        | (expand_bits_10(scale(p[1])) << 1)
+        | expand_bits_10(scale(p[2]))
+}
+
+// 63-bit morton code of a point normalized to the unit cube
+pub fn morton_code_63(p: [f32; 3]) -> u64 {
+    let scale = |c: f32| (c as f64 * 2097152.).clamp(0., 2097151.) as u64;
+    (expand_bits_21(scale(p[0])) << 2)
+        | (expand_bits_21(scale(p[1])) << 1)
+        | expand_bits_21(scale(p[2]))
+}
+
+struct ScatterTarget(*mut (u64, u32));
+unsafe impl Send for ScatterTarget {}
+unsafe impl Sync for ScatterTarget {}
+
+// parallel LSD radix sort of (key, value) pairs by key, one byte per pass. Every chunk counts its
+// digits, the counts are prefix-summed across chunks and each chunk scatters to its own slots
+pub fn radix_sort(pairs: &mut Vec<(u64, u32)>) {
+    if pairs.len() < 2 {
+        return;
+    }
+    let chunk_size = pairs.len().div_ceil(rayon::current_num_threads());
+    let mut scratch = vec![(0u64, 0u32); pairs.len()];
+    let highest_key = pairs.par_iter().map(|pair| pair.0).max().unwrap_or(0);
+    let passes = (64 - highest_key.leading_zeros()).div_ceil(8);
+
+    for pass in 0..passes {
+        let shift = pass * 8;
+        let histograms: Vec<[usize; 256]> = pairs
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let mut histogram = [0usize; 256];
+                for pair in chunk {
+                    histogram[((pair.0 >> shift) & 0xff) as usize] += 1;
+                }
+                histogram
+            })
+            .collect();
+
+        let mut offsets = vec![[0usize; 256]; histograms.len()];
+        let mut total = 0;
+        for digit in 0..256 {
+            for (chunk_idx, histogram) in histograms.iter().enumerate() {
+                offsets[chunk_idx][digit] = total;
+                total += histogram[digit];
+            }
+        }
+
+        let target = ScatterTarget(scratch.as_mut_ptr());
+        pairs
+            .par_chunks(chunk_size)
+            .zip(offsets.into_par_iter())
+            .for_each(|(chunk, mut offset)| {
+                let target = &target;
+                for pair in chunk {
+                    let digit = ((pair.0 >> shift) & 0xff) as usize;
+                    // every slot is handed to exactly one chunk by the prefix sum above
+                    unsafe { *target.0.add(offset[digit]) = *pair };
+                    offset[digit] += 1;
+                }
+            });
+        std::mem::swap(pairs, &mut scratch);
+    }
+}
+
+// index in `codes[first..=last]` after which the highest differing bit of the codes flips
+fn find_split(codes: &[(u64, u32)], first: usize, last: usize) -> usize {
+    let first_code = codes[first].0;
+    let common_prefix = (first_code ^ codes[last].0).leading_zeros();
+    let mut split = first;
+    let mut step = last - first;
+    loop {
+        step = step.div_ceil(2);
+        let new_split = split + step;
+        if new_split < last && (first_code ^ codes[new_split].0).leading_zeros() > common_prefix {
+            split = new_split;
+        }
+        if step <= 1 {
+            break;
+        }
+    }
+    split
+}
+
+fn emit_lbvh_nodes(
+    codes: &[(u64, u32)],
+    nodes: &mut Vec<Node>,
+    first: usize,
+    last: usize,
+    depth: u8,
+) -> usize {
+    nodes.push(Node {
+        bounds: [0.; 6],
+        start_triangle: first as u32,
+        triangle_count: (last - first + 1) as u32,
+        left_node: 0,
+        right_node: 0,
+        padding_: [0, 0, 0, 0, 0, 0],
+    });
+    let index = nodes.len() - 1;
+    if depth == 0 || last - first < LBVH_LEAF_SIZE {
+        return index;
+    }
+    let split = if codes[first].0 == codes[last].0 {
+        // duplicate codes carry no spatial order, halve the range instead
+        (first + last) / 2
+    } else {
+        find_split(codes, first, last)
+    };
+    let left = emit_lbvh_nodes(codes, nodes, first, split, depth - 1);
+    nodes[index].left_node = left as u32;
+    let right = emit_lbvh_nodes(codes, nodes, split + 1, last, depth - 1);
+    nodes[index].right_node = right as u32;
+    index
+}
+
+// linear BVH: triangles sorted along a 63-bit morton curve over their centroids, split on the
+// highest differing bit. Builds much faster than `create_bvh` at the cost of tree quality
+pub fn create_lbvh(mesh: &Mesh, depth: u8) -> BVH {
+    let triangles: Vec<Triangle2> = (0..mesh.faces.len())
+        .into_par_iter()
+        .map(|face_index| triangle_from_face(mesh, face_index))
+        .collect();
+    let centers: Vec<[f32; 3]> = triangles.par_iter().map(triangle_center).collect();
+    let (center_min, center_max) = centers
+        .par_iter()
+        .fold(
+            || ([f32::MAX; 3], [f32::MIN; 3]),
+            |(mut min, mut max), c| {
+                for k in 0..3 {
+                    min[k] = min[k].min(c[k]);
+                    max[k] = max[k].max(c[k]);
+                }
+                (min, max)
+            },
+        )
+        .reduce(
+            || ([f32::MAX; 3], [f32::MIN; 3]),
+            |a, b| {
+                (
+                    [a.0[0].min(b.0[0]), a.0[1].min(b.0[1]), a.0[2].min(b.0[2])],
+                    [a.1[0].max(b.1[0]), a.1[1].max(b.1[1]), a.1[2].max(b.1[2])],
+                )
+            },
+        );
+    let extent = [
+        (center_max[0] - center_min[0]).max(f32::EPSILON),
+        (center_max[1] - center_min[1]).max(f32::EPSILON),
+        (center_max[2] - center_min[2]).max(f32::EPSILON),
+    ];
+
+    let mut codes: Vec<(u64, u32)> = centers
+        .par_iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let normalized = [
+                (c[0] - center_min[0]) / extent[0],
+                (c[1] - center_min[1]) / extent[1],
+                (c[2] - center_min[2]) / extent[2],
+            ];
+            (morton_code_63(normalized), i as u32)
+        })
+        .collect();
+    radix_sort(&mut codes);
+
+    let face_indices: Vec<u32> = codes.iter().map(|&(_, i)| i).collect();
+    let triangles: Vec<Triangle2> = face_indices
+        .par_iter()
+        .map(|&i| triangles[i as usize])
+        .collect();
+
+    let mut nodes = vec![];
+    if !codes.is_empty() {
+        emit_lbvh_nodes(&codes, &mut nodes, 0, codes.len() - 1, depth - 1);
+    }
+    let mut bvh = BVH {
+        nodes,
+        triangles,
+        face_indices,
+        node_areas: vec![],
+        depth,
+    };
+    bvh.refit_bounds();
+    bvh.node_areas = bvh
+        .nodes
+        .iter()
+        .map(|node| surface_area(&node.bounds))
+        .collect();
+    bvh
+}
+
+impl BVH {
+    // node indices grouped by depth, root first. Nodes orphaned by a partial rebuild are not reachable
+    pub fn levels(&self) -> Vec<Vec<usize>> {
+        let mut levels: Vec<Vec<usize>> = vec![];
+        if self.nodes.is_empty() {
+            return levels;
+        }
+        let mut current = vec![0];
+        while !current.is_empty() {
+            let mut next = vec![];
+            for &index in current.iter() {
+                let node = &self.nodes[index];
+                if node.left_node != 0 {
+                    next.push(node.left_node as usize);
+                }
+                if node.right_node != 0 {
+                    next.push(node.right_node as usize);
+                }
+            }
+            levels.push(current);
+            current = next;
+        }
+        levels
+    }
+
+    fn node_bounds(&self, index: usize) -> [f32; 6] {
+        let node = &self.nodes[index];
+        if is_leaf(node) {
+            let start = node.start_triangle as usize;
+            return compute_bounds(&self.triangles, start, start + node.triangle_count as usize);
+        }
+        let mut bounds = [f32::MAX, f32::MAX, f32::MAX, f32::MIN, f32::MIN, f32::MIN];
+        for child in [node.left_node, node.right_node] {
+            if child != 0 {
+                let child_bounds = &self.nodes[child as usize].bounds;
+                for k in 0..3 {
+                    bounds[k] = bounds[k].min(child_bounds[k]);
+                    bounds[k + 3] = bounds[k + 3].max(child_bounds[k + 3]);
+                }
+            }
+        }
+        bounds
+    }
+
+    // recomputes every reachable node's bounds bottom-up from `triangles`, one level at a time
+    // in parallel. Returns the range of nodes whose bounds changed
+    pub fn refit_bounds(&mut self) -> Option<Range<usize>> {
+        let levels = self.levels();
+        let mut changed: Option<Range<usize>> = None;
+        for level in levels.iter().rev() {
+            let bvh = &*self;
+            let updated: Vec<(usize, [f32; 6])> = level
+                .par_iter()
+                .map(|&index| (index, bvh.node_bounds(index)))
+                .filter(|(index, bounds)| bvh.nodes[*index].bounds != *bounds)
+                .collect();
+            for (index, bounds) in updated {
+                self.nodes[index].bounds = bounds;
+                merge_range(&mut changed, index..index + 1);
+            }
+        }
+        changed
+    }
+
+    // pulls the moved vertices of `mesh` into `triangles` and refits the tree. The mesh must have the
+    // same faces the BVH was built from. Subtrees that degraded past REBUILD_THRESHOLD are rebuilt
+    pub fn refit(&mut self, mesh: &Mesh) -> BvhUpdate {
+        let mut update = BvhUpdate::default();
+        if mesh.faces.len() != self.triangles.len() {
+            *self = create_bvh(mesh, self.depth);
+            update.nodes = Some(0..self.nodes.len());
+            update.triangles = Some(0..self.triangles.len());
+            return update;
+        }
+
+        update.triangles = self
+            .triangles
+            .par_iter_mut()
+            .zip(self.face_indices.par_iter())
+            .enumerate()
+            .filter_map(|(i, (tri, &face_index))| {
+                let mut moved = triangle_from_face(mesh, face_index as usize);
+                if moved.v1 == tri.v1 && moved.v2 == tri.v2 && moved.v3 == tri.v3 {
+                    return None;
+                }
+                moved.normal = geometric_normal(&moved);
+                *tri = moved;
+                Some(i..i + 1)
+            })
+            .reduce_with(|a, b| a.start.min(b.start)..a.end.max(b.end));
+        if update.triangles.is_none() {
+            return update;
+        }
+
+        update.nodes = self.refit_bounds();
+        self.rebuild_degraded(&mut update);
+
+        // partial rebuilds leave their old subtrees behind, start over once those outweigh the live tree
+        let live_nodes: usize = self.levels().iter().map(|level| level.len()).sum();
+        if self.nodes.len() > 2 * live_nodes {
+            *self = create_bvh(mesh, self.depth);
+            update.nodes = Some(0..self.nodes.len());
+            update.triangles = Some(0..self.triangles.len());
+        }
+        update
+    }
+
+    // re-splits the topmost subtrees whose area grew past REBUILD_THRESHOLD times their build area.
+    // The new nodes are appended, the triangles are only reordered inside each subtree's range
+    fn rebuild_degraded(&mut self, update: &mut BvhUpdate) {
+        let mut degraded = vec![];
+        let mut stack = vec![(0usize, 0u8)];
+        while let Some((index, level)) = stack.pop() {
+            let node = self.nodes[index];
+            if is_leaf(&node) {
+                continue;
+            }
+            if surface_area(&node.bounds) > REBUILD_THRESHOLD * self.node_areas[index] {
+                degraded.push((index, level));
+                continue;
+            }
+            for child in [node.left_node, node.right_node] {
+                if child != 0 {
+                    stack.push((child as usize, level + 1));
+                }
+            }
+        }
+        if degraded.is_empty() {
+            return;
+        }
+
+        let mut triangle_centers: Vec<[f32; 3]> =
+            self.triangles.par_iter().map(triangle_center).collect();
+        for (index, level) in degraded {
+            let first_new_node = self.nodes.len();
+            let start = self.nodes[index].start_triangle as usize;
+            let end = start + self.nodes[index].triangle_count as usize;
+            self.nodes[index].left_node = 0;
+            self.nodes[index].right_node = 0;
+            create_nodes(
+                &mut triangle_centers,
+                &mut self.triangles,
+                &mut self.face_indices,
+                &mut self.nodes,
+                self.depth.saturating_sub(level + 1),
+                index,
+            );
+            self.node_areas[index] = surface_area(&self.nodes[index].bounds);
+            for node in self.nodes[first_new_node..].iter() {
+                self.node_areas.push(surface_area(&node.bounds));
+            }
+            merge_range(&mut update.nodes, index..index + 1);
+            merge_range(&mut update.nodes, first_new_node..self.nodes.len());
+            merge_range(&mut update.triangles, start..end);
+        }
+    }
+}
+
 pub struct BvhManager {
     pub nodes_buffer: wgpu::Buffer,
     pub triangles_buffer: wgpu::Buffer,
     pub nodes_count_buffer: wgpu::Buffer,
     pub triangles_count_buffer: wgpu::Buffer,
+    pub nodes4_buffer: wgpu::Buffer,
+    pub nodes4_count_buffer: wgpu::Buffer,
+    pub shading_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bvh: BVH,
+    pub bvh4: Option<BVH4>, // when set the shader traverses the 4-wide tree instead of `bvh`
+    pub face_shading: Vec<TriangleShading>, // per mesh face, faces past its end use the default
 }
 
 impl BvhManager {
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, mesh: &Mesh) -> Self {
+        Self::from_bvh(device, queue, create_bvh(mesh, 25))
+    }
+
+    pub fn from_bvh(device: &wgpu::Device, queue: &wgpu::Queue, bvh: BVH) -> Self {
         let (
             nodes_buffer,
             triangles_buffer,
             nodes_count_buffer,
             triangles_count_buffer,
+            nodes4_buffer,
+            nodes4_count_buffer,
+            shading_buffer,
             bind_group,
             bind_group_layout,
-            bvh,
-        ) = Self::create_buffers_and_bind_group(device, mesh);
+        ) = Self::create_buffers_and_bind_group(device, &bvh, None);
 
         let mut manager = Self {
             nodes_buffer,
             triangles_buffer,
             nodes_count_buffer,
             triangles_count_buffer,
+            nodes4_buffer,
+            nodes4_count_buffer,
+            shading_buffer,
             bind_group,
             bind_group_layout,
             bvh,
+            bvh4: None,
+            face_shading: vec![],
         };
 
         manager.update_buffers(queue);
@@ -292,17 +809,19 @@ impl BvhManager {
 
     fn create_buffers_and_bind_group(
         device: &wgpu::Device,
-        mesh: &Mesh,
+        bvh: &BVH,
+        bvh4: Option<&BVH4>,
     ) -> (
+        wgpu::Buffer,
+        wgpu::Buffer,
+        wgpu::Buffer,
         wgpu::Buffer,
         wgpu::Buffer,
         wgpu::Buffer,
         wgpu::Buffer,
         wgpu::BindGroup,
         wgpu::BindGroupLayout,
-        BVH,
     ) {
-        let bvh = create_bvh(mesh, 25);
         let nodes_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Nodes buffer"),
             size: (std::mem::size_of::<Node>() * bvh.nodes.len()) as wgpu::BufferAddress,
@@ -330,48 +849,60 @@ impl BvhManager {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
+
+        // storage bindings can't be empty, keep room for one node while the wide tree is off
+        let nodes4_len = bvh4.map_or(1, |bvh4| bvh4.nodes.len().max(1));
+        let nodes4_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Nodes4 buffer"),
+            size: (std::mem::size_of::<Node4>() * nodes4_len) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let nodes4_count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Nodes4 count buffer"),
+            size: std::mem::size_of::<EntityCount>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shading_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Triangle shading buffer"),
+            size: (std::mem::size_of::<TriangleShading>() * bvh.triangles.len().max(1))
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
+                storage_entry(0),
+                storage_entry(1),
+                uniform_entry(2),
+                uniform_entry(3),
+                storage_entry(4),
+                uniform_entry(5),
+                storage_entry(6),
             ],
             label: Some("Bvh Bind Group Layout"),
         });
@@ -395,6 +926,18 @@ impl BvhManager {
                     binding: 3,
                     resource: triangles_count_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: nodes4_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: nodes4_count_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: shading_buffer.as_entire_binding(),
+                },
             ],
             label: Some("Bvh Bind Group"),
         });
@@ -404,12 +947,37 @@ impl BvhManager {
             triangles_buffer,
             nodes_count_buffer,
             triangles_count_buffer,
+            nodes4_buffer,
+            nodes4_count_buffer,
+            shading_buffer,
             bind_group,
             bind_group_layout,
-            bvh,
         )
     }
 
+    fn recreate_buffers(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let (
+            nodes_buffer,
+            triangles_buffer,
+            nodes_count_buffer,
+            triangles_count_buffer,
+            nodes4_buffer,
+            nodes4_count_buffer,
+            shading_buffer,
+            bind_group,
+            _,
+        ) = Self::create_buffers_and_bind_group(device, &self.bvh, self.bvh4.as_ref());
+        self.nodes_buffer = nodes_buffer;
+        self.triangles_buffer = triangles_buffer;
+        self.nodes_count_buffer = nodes_count_buffer;
+        self.triangles_count_buffer = triangles_count_buffer;
+        self.nodes4_buffer = nodes4_buffer;
+        self.nodes4_count_buffer = nodes4_count_buffer;
+        self.shading_buffer = shading_buffer;
+        self.bind_group = bind_group;
+        self.update_buffers(queue);
+    }
+
     fn update_buffers(&mut self, queue: &wgpu::Queue) {
         queue.write_buffer(&self.nodes_buffer, 0, bytemuck::cast_slice(&self.bvh.nodes));
         queue.write_buffer(
@@ -435,133 +1003,531 @@ impl BvhManager {
             0,
             bytemuck::cast_slice(&[triangles_count]),
         );
+        self.update_shading_buffer(queue, 0..self.bvh.triangles.len());
+        self.update_wide_buffers(queue);
     }
+
+    // shading follows the BVH's triangle order, which changes on rebuilds
+    fn update_shading_buffer(&self, queue: &wgpu::Queue, range: Range<usize>) {
+        let shading: Vec<TriangleShading> = self.bvh.face_indices[range.clone()]
+            .iter()
+            .map(|&face| {
+                self.face_shading
+                    .get(face as usize)
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect();
+        queue.write_buffer(
+            &self.shading_buffer,
+            (range.start * std::mem::size_of::<TriangleShading>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&shading),
+        );
+    }
+
+    // per mesh face colors and materials, e.g. a deviation heatmap
+    pub fn set_face_shading(&mut self, queue: &wgpu::Queue, face_shading: Vec<TriangleShading>) {
+        self.face_shading = face_shading;
+        self.update_shading_buffer(queue, 0..self.bvh.triangles.len());
+    }
+
+    fn update_wide_buffers(&mut self, queue: &wgpu::Queue) {
+        let nodes4_count = EntityCount {
+            count: self.bvh4.as_ref().map_or(0, |bvh4| bvh4.nodes.len() as u32),
+        };
+        if let Some(bvh4) = &self.bvh4 {
+            queue.write_buffer(&self.nodes4_buffer, 0, bytemuck::cast_slice(&bvh4.nodes));
+        }
+        queue.write_buffer(
+            &self.nodes4_count_buffer,
+            0,
+            bytemuck::cast_slice(&[nodes4_count]),
+        );
+    }
+
+    // switches the shader between the binary tree and the BVH4 collapsed from it
+    pub fn set_wide(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, wide: bool) {
+        self.bvh4 = if wide {
+            Some(collapse(&self.bvh))
+        } else {
+            None
+        };
+        let required_size = self.bvh4.as_ref().map_or(0, |bvh4| {
+            std::mem::size_of_val(bvh4.nodes.as_slice()) as wgpu::BufferAddress
+        });
+        if required_size > self.nodes4_buffer.size() {
+            self.recreate_buffers(device, queue);
+        } else {
+            self.update_wide_buffers(queue);
+        }
+    }
+
+    // refits the BVH to the moved vertices of `mesh` and uploads only the node and triangle
+    // ranges that changed
+    pub fn refit(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mesh: &Mesh) {
+        let update = self.bvh.refit(mesh);
+        if self.bvh4.is_some() && update.nodes.is_some() {
+            self.bvh4 = Some(collapse(&self.bvh));
+        }
+        let required_nodes_size =
+            std::mem::size_of_val(self.bvh.nodes.as_slice()) as wgpu::BufferAddress;
+        let required_triangles_size =
+            std::mem::size_of_val(self.bvh.triangles.as_slice()) as wgpu::BufferAddress;
+        let required_nodes4_size = self.bvh4.as_ref().map_or(0, |bvh4| {
+            std::mem::size_of_val(bvh4.nodes.as_slice()) as wgpu::BufferAddress
+        });
+        if required_nodes_size > self.nodes_buffer.size()
+            || required_triangles_size > self.triangles_buffer.size()
+            || required_nodes4_size > self.nodes4_buffer.size()
+        {
+            self.recreate_buffers(device, queue);
+            return;
+        }
+
+        if let Some(range) = update.nodes {
+            queue.write_buffer(
+                &self.nodes_buffer,
+                (range.start * std::mem::size_of::<Node>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&self.bvh.nodes[range]),
+            );
+            let nodes_count = EntityCount {
+                count: self.bvh.nodes.len() as u32,
+            };
+            queue.write_buffer(
+                &self.nodes_count_buffer,
+                0,
+                bytemuck::cast_slice(&[nodes_count]),
+            );
+            self.update_wide_buffers(queue);
+        }
+        if let Some(range) = update.triangles {
+            queue.write_buffer(
+                &self.triangles_buffer,
+                (range.start * std::mem::size_of::<Triangle2>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&self.bvh.triangles[range.clone()]),
+            );
+            self.update_shading_buffer(queue, range);
+        }
+    }
+}
+
+// closest hit of a CPU ray query against a BVH
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub distance: f32,
+    pub triangle: u32,          // mesh face index
+    pub barycentrics: [f32; 3], // weights of v1, v2, v3
+    pub normal: Vec3<f32>,      // stored face normal, not flipped towards the ray
+}
+
+pub fn intersect_aabb(ray: &Ray, bounding_box: &[f32; 6]) -> Option<f32> {
+    let tx1 = (bounding_box[0] - ray.origin.v[0]) * ray.inv.v[0];
+    let tx2 = (bounding_box[3] - ray.origin.v[0]) * ray.inv.v[0];
+    let t1 = tx1.min(tx2);
+    let t2 = tx1.max(tx2);
+
+    let ty1 = (bounding_box[1] - ray.origin.v[1]) * ray.inv.v[1];
+    let ty2 = (bounding_box[4] - ray.origin.v[1]) * ray.inv.v[1];
+    let t3 = ty1.min(ty2);
+    let t4 = ty1.max(ty2);
+
+    let tz1 = (bounding_box[2] - ray.origin.v[2]) * ray.inv.v[2];
+    let tz2 = (bounding_box[5] - ray.origin.v[2]) * ray.inv.v[2];
+    let t5 = tz1.min(tz2);
+    let t6 = tz1.max(tz2);
+
+    let tmin = t1.min(t2).max(t3.min(t4)).max(t5.min(t6));
+    let tmax = t1.max(t2).min(t3.max(t4)).min(t5.max(t6));
+
+    if tmax >= tmin && tmax > 0.0 {
+        Some(tmin)
+    } else {
+        None
+    }
+}
+
+// distance and the (u, v) barycentrics of v2 and v3 where the ray crosses the triangle
+pub fn moller_trumbore_intersection(ray: &Ray, triangle: &Triangle2) -> Option<(f32, f32, f32)> {
+    let v1 = Vec3::new(triangle.v1[0], triangle.v1[1], triangle.v1[2]);
+    let e1 = Vec3::new(triangle.v2[0], triangle.v2[1], triangle.v2[2]) - v1;
+    let e2 = Vec3::new(triangle.v3[0], triangle.v3[1], triangle.v3[2]) - v1;
+
+    let ray_cross_e2 = ray.direction.cross(&e2);
+    let det = e1.dot(&ray_cross_e2);
+
+    if det > -f32::EPSILON && det < f32::EPSILON {
+        return None; // This ray is parallel to this triangle.
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - v1;
+    let u = inv_det * s.dot(&ray_cross_e2);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let s_cross_e1 = s.cross(&e1);
+    let v = inv_det * ray.direction.dot(&s_cross_e1);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    // At this stage we can compute t to find out where the intersection point is on the line.
+    let t = inv_det * e2.dot(&s_cross_e1);
+
+    if t > f32::EPSILON {
+        Some((t, u, v))
+    } else {
+        // This means that there is a line intersection but not a ray intersection.
+        None
+    }
+}
+
+impl BVH {
+    // walks the tree front to back and calls `test` on every leaf the ray reaches before
+    // `max_distance`. `test` returns the new maximum distance, or None to stop the walk
+    fn walk_leaves(
+        &self,
+        ray: &Ray,
+        mut max_distance: f32,
+        mut test: impl FnMut(usize, usize) -> Option<f32>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            match intersect_aabb(ray, &node.bounds) {
+                Some(t) if t < max_distance => {}
+                _ => continue,
+            }
+            if is_leaf(node) {
+                let start = node.start_triangle as usize;
+                match test(start, start + node.triangle_count as usize) {
+                    Some(distance) => max_distance = distance,
+                    None => return,
+                }
+                continue;
+            }
+            let mut hits: Vec<(f32, u32)> = [node.left_node, node.right_node]
+                .iter()
+                .filter(|&&child| child != 0)
+                .filter_map(|&child| {
+                    intersect_aabb(ray, &self.nodes[child as usize].bounds).map(|t| (t, child))
+                })
+                .filter(|&(t, _)| t < max_distance)
+                .collect();
+            // nearest child goes on top of the stack
+            hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+            stack.extend(hits.iter().map(|&(_, child)| child));
+        }
+    }
+
+    // closest triangle along the ray
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<(f32, f32, f32, usize)> = None;
+        self.walk_leaves(ray, f32::MAX, |start, end| {
+            for i in start..end {
+                if let Some((t, u, v)) = moller_trumbore_intersection(ray, &self.triangles[i]) {
+                    if closest.map_or(true, |c| t < c.0) {
+                        closest = Some((t, u, v, i));
+                    }
+                }
+            }
+            Some(closest.map_or(f32::MAX, |c| c.0))
+        });
+        closest.map(|(distance, u, v, i)| {
+            let n = self.triangles[i].normal;
+            Hit {
+                distance,
+                triangle: self.face_indices[i],
+                barycentrics: [1. - u - v, u, v],
+                normal: Vec3::new(n[0], n[1], n[2]).normalize(),
+            }
+        })
+    }
+
+    // whether any triangle lies along the ray closer than `tmax`, stops at the first one found
+    pub fn occluded(&self, ray: &Ray, tmax: f32) -> bool {
+        let mut found = false;
+        self.walk_leaves(ray, tmax, |start, end| {
+            found = self.triangles[start..end].iter().any(|tri| {
+                moller_trumbore_intersection(ray, tri).is_some_and(|(t, _, _)| t < tmax)
+            });
+            if found {
+                None
+            } else {
+                Some(tmax)
+            }
+        });
+        found
+    }
+}
+
+// result of checking two meshes against each other
+#[derive(Clone, Debug, Default)]
+pub struct Interference {
+    pub pairs: Vec<(u32, u32)>, // intersecting mesh faces, first mesh then second
+    pub curves: Vec<Polyline>,  // where the two surfaces cross
+    pub volume: f32,            // estimated volume inside both meshes
+}
+
+pub fn aabbs_overlap(a: &[f32; 6], b: &[f32; 6]) -> bool {
+    (0..3).all(|k| a[k] <= b[k + 3] && b[k] <= a[k + 3])
+}
+
+fn sub64(a: &[f32; 3], b: &[f32; 3]) -> [f64; 3] {
+    [
+        a[0] as f64 - b[0] as f64,
+        a[1] as f64 - b[1] as f64,
+        a[2] as f64 - b[2] as f64,
+    ]
+}
+
+fn cross64(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot64(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// point where the segment p-q passes through the triangle, None when it misses or lies in its plane
+pub fn segment_triangle_intersection(
+    p: &[f32; 3],
+    q: &[f32; 3],
+    tri: &Triangle2,
+) -> Option<[f32; 3]> {
+    let e1 = sub64(&tri.v2, &tri.v1);
+    let e2 = sub64(&tri.v3, &tri.v1);
+    let n = cross64(&e1, &e2);
+    let dp = dot64(&n, &sub64(p, &tri.v1));
+    let dq = dot64(&n, &sub64(q, &tri.v1));
+    if (dp > 0. && dq > 0.) || (dp < 0. && dq < 0.) || dp == dq {
+        return None;
+    }
+    let t = dp / (dp - dq);
+    let pq = sub64(q, p);
+    let x = [
+        p[0] as f64 + t * pq[0],
+        p[1] as f64 + t * pq[1],
+        p[2] as f64 + t * pq[2],
+    ];
+    // x has to be on the inner side of all three edges
+    let tolerance = -1e-9 * dot64(&n, &n);
+    for (a, b) in [(tri.v1, tri.v2), (tri.v2, tri.v3), (tri.v3, tri.v1)] {
+        let edge = sub64(&b, &a);
+        let to_x = [x[0] - a[0] as f64, x[1] - a[1] as f64, x[2] - a[2] as f64];
+        if dot64(&cross64(&edge, &to_x), &n) < tolerance {
+            return None;
+        }
+    }
+    Some([x[0] as f32, x[1] as f32, x[2] as f32])
 }
 
-//pub fn intersect_aabb(ray: &Ray, bounding_box: &[f32; 6]) -> Option<f32> {
-//    let tx1 = (bounding_box[0] - ray.origin.v[0]) * ray.inv.v[0];
-//    let tx2 = (bounding_box[3] - ray.origin.v[0]) * ray.inv.v[0];
-//    let t1 = tx1.min(tx2);
-//    let t2 = tx1.max(tx2);
-//
-//    let ty1 = (bounding_box[1] - ray.origin.v[1]) * ray.inv.v[1];
-//    let ty2 = (bounding_box[4] - ray.origin.v[1]) * ray.inv.v[1];
-//    let t3 = ty1.min(ty2);
-//    let t4 = ty1.max(ty2);
-//
-//    let tz1 = (bounding_box[2] - ray.origin.v[2]) * ray.inv.v[2];
-//    let tz2 = (bounding_box[5] - ray.origin.v[2]) * ray.inv.v[2];
-//    let t5 = tz1.min(tz2);
-//    let t6 = tz1.max(tz2);
-//
-//    let tmin = t1.min(t2).max(t3.min(t4)).max(t5.min(t6));
-//    let tmax = t1.max(t2).min(t3.max(t4)).min(t5.max(t6));
-//
-//    if tmax >= tmin && tmax > 0.0 {
-//        Some(tmin as f32)
-//    } else {
-//        None
-//    }
-//}
-//
-//pub fn moller_trumbore_intersection (origin: &Vec3<f32>, direction: &Vec3<f32>, triangle: &Triangle2) -> bool {
-//	let e1 = &triangle.vertices[1] - &triangle.vertices[0];
-//	let e2 = &triangle.vertices[2] - &triangle.vertices[0];
-//
-//	let ray_cross_e2 = direction.cross(&e2);
-//	let det = e1.dot(&ray_cross_e2);
-//
-//	if det > -f32::EPSILON && det < f32::EPSILON {
-//		return false; // This ray is parallel to this triangle.
-//	}
-//
-//	let inv_det = 1.0 / det;
-//    let v1 = &triangle.vertices[0];
-//	let s = origin - v1;
-//	let u = inv_det * s.dot(&ray_cross_e2);
-//	if u < 0.0 || u > 1.0 {
-//		return false;
-//	}
-//
-//	let s_cross_e1 = s.cross(&e1);
-//	let v = inv_det * direction.dot(&s_cross_e1);
-//	if v < 0.0 || u + v > 1.0 {
-//		return false;
-//	}
-//	// At this stage we can compute t to find out where the intersection point is on the line.
-//	let t = inv_det * e2.dot(&s_cross_e1);
-//
-//	if t > f32::EPSILON { // ray intersection
-//		let intersection_point = origin + direction * t;
-//		return true;
-//	}
-//	else { // This means that there is a line intersection but not a ray intersection.
-//		return false;
-//	}
-//}
-//
-//pub fn is_on_box_boundary(ray: &Ray, bounds: &[f32; 6],) -> bool {
-//    let inv_dir = Vec3 {
-//        v: [1.0 / ray.direction.v[0], 1.0 / ray.direction.v[1], 1.0 / ray.direction.v[2]]
-//    };
-//
-//    let min_bounds = Vec3::new(bounds[0], bounds[1],bounds[2]);
-//    let max_bounds = Vec3::new(bounds[3], bounds[4],bounds[5]);
-//
-//    let t1 = (min_bounds.v[0] - ray.origin.v[0]) * inv_dir.v[0];
-//    let t2 = (max_bounds.v[0] - ray.origin.v[0]) * inv_dir.v[0];
-//    let t3 = (min_bounds.v[1] - ray.origin.v[1]) * inv_dir.v[1];
-//    let t4 = (max_bounds.v[1] - ray.origin.v[1]) * inv_dir.v[1];
-//    let t5 = (min_bounds.v[2] - ray.origin.v[2]) * inv_dir.v[2];
-//    let t6 = (max_bounds.v[2] - ray.origin.v[2]) * inv_dir.v[2];
-//
-//    let txmin = t1.min(t2);
-//    let txmax = t1.max(t2);
-//    let tymin = t3.min(t4);
-//    let tymax = t3.max(t4);
-//    let tzmin = t5.min(t6);
-//    let tzmax = t5.max(t6);
-//
-//    let tmin = txmin.max(tymin).max(tzmin);
-//    let tmax = txmax.min(tymax).min(tzmax);
-//
-//    if tmax < tmin || tmax <= 0.0 {
-//        return false; // Ray doesn't intersect the box at all
-//    }
-//
-//    let intersection_point = ray.at(tmin);
-//
-//    let dist = ((&intersection_point - ray.origin).length() * 3.0_f64.sqrt()) as f32;
-//
-//    let mut min_subs = &intersection_point - &min_bounds;
-//    min_subs.v[0] = min_subs.v[0].abs();
-//    min_subs.v[1] = min_subs.v[1].abs();
-//    min_subs.v[2] = min_subs.v[2].abs();
-//
-//    let mut max_subs = &intersection_point - &max_bounds;
-//    max_subs.v[0] = max_subs.v[0].abs();
-//    max_subs.v[1] = max_subs.v[1].abs();
-//    max_subs.v[2] = max_subs.v[2].abs();
-//
-//    let x_thresh = ((max_bounds.v[0] - min_bounds.v[0]) * dist / 500.).min(0.052);
-//    let y_thresh = ((max_bounds.v[1] - min_bounds.v[1]) * dist / 500.).min(0.052);
-//    let z_thresh = ((max_bounds.v[2] - min_bounds.v[2]) * dist / 500.).min(0.052);
-//
-//    // Check if the intersection point is on an edge
-//    let on_x_edge = (min_subs.v[1] < x_thresh  || max_subs.v[1] < x_thresh ) && (min_subs.v[2] < x_thresh  || max_subs.v[2] < x_thresh );
-//    let on_y_edge = (min_subs.v[0] < y_thresh  || max_subs.v[0] < y_thresh ) && (min_subs.v[2] < y_thresh  || max_subs.v[2] < y_thresh );
-//    let on_z_edge = (min_subs.v[1] < z_thresh  || max_subs.v[1] < z_thresh ) && (min_subs.v[0] < z_thresh  || max_subs.v[0] < z_thresh );
-//
-//    on_x_edge || on_y_edge || on_z_edge
-//}
+// segment shared by two crossing triangles. Its endpoints are where the edges of one triangle pass
+// through the other; coplanar pairs are skipped
+pub fn triangle_intersection(a: &Triangle2, b: &Triangle2) -> Option<[[f32; 3]; 2]> {
+    let mut points = vec![];
+    for (tri, other) in [(a, b), (b, a)] {
+        for (p, q) in [(tri.v1, tri.v2), (tri.v2, tri.v3), (tri.v3, tri.v1)] {
+            points.extend(segment_triangle_intersection(&p, &q, other));
+        }
+    }
+    let mut best: Option<(f32, [[f32; 3]; 2])> = None;
+    for i in 0..points.len() {
+        for j in i + 1..points.len() {
+            let d = sub64(&points[i], &points[j]);
+            let distance = dot64(&d, &d) as f32;
+            if distance > 0. && best.map_or(true, |b| distance > b.0) {
+                best = Some((distance, [points[i], points[j]]));
+            }
+        }
+    }
+    best.map(|(_, segment)| segment)
+}
+
+impl BVH {
+    // copy of the tree with every triangle moved by `transform`, refitted rather than rebuilt.
+    // A mirroring transform reverses the winding, so v2 and v3 swap to keep the normals outward
+    pub fn transformed(&self, transform: &Mat4) -> BVH {
+        let mirrored = transform.determinant() < 0.;
+        let triangles = self
+            .triangles
+            .par_iter()
+            .map(|tri| {
+                let (v2, v3) = if mirrored {
+                    (tri.v3, tri.v2)
+                } else {
+                    (tri.v2, tri.v3)
+                };
+                let mut moved = Triangle2 {
+                    v1: transform.transform_point(&Vec3::from(tri.v1)).to_array(),
+                    v2: transform.transform_point(&Vec3::from(v2)).to_array(),
+                    v3: transform.transform_point(&Vec3::from(v3)).to_array(),
+                    ..*tri
+                };
+                moved.normal = geometric_normal(&moved);
+                moved
+            })
+            .collect();
+        let mut bvh = BVH {
+            nodes: self.nodes.clone(),
+            triangles,
+            face_indices: self.face_indices.clone(),
+            node_areas: self.node_areas.clone(),
+            depth: self.depth,
+        };
+        bvh.refit_bounds();
+        bvh
+    }
+
+    // pairs of triangle indices (into `triangles` of each tree) whose triangles cross, found by
+    // walking both trees together and only descending into overlapping boxes
+    pub fn overlapping_triangles(&self, other: &BVH) -> Vec<(usize, usize)> {
+        if self.nodes.is_empty() || other.nodes.is_empty() {
+            return vec![];
+        }
+        let mut leaf_pairs = vec![];
+        let mut stack = vec![(0usize, 0usize)];
+        while let Some((a, b)) = stack.pop() {
+            let (node_a, node_b) = (&self.nodes[a], &other.nodes[b]);
+            if !aabbs_overlap(&node_a.bounds, &node_b.bounds) {
+                continue;
+            }
+            let (leaf_a, leaf_b) = (is_leaf(node_a), is_leaf(node_b));
+            if leaf_a && leaf_b {
+                leaf_pairs.push((a, b));
+            } else if leaf_b
+                || (!leaf_a && surface_area(&node_a.bounds) >= surface_area(&node_b.bounds))
+            {
+                // split the larger box
+                for child in [node_a.left_node, node_a.right_node] {
+                    if child != 0 {
+                        stack.push((child as usize, b));
+                    }
+                }
+            } else {
+                for child in [node_b.left_node, node_b.right_node] {
+                    if child != 0 {
+                        stack.push((a, child as usize));
+                    }
+                }
+            }
+        }
+
+        leaf_pairs
+            .par_iter()
+            .flat_map_iter(|&(a, b)| {
+                let (node_a, node_b) = (&self.nodes[a], &other.nodes[b]);
+                let range_a = node_a.start_triangle as usize
+                    ..(node_a.start_triangle + node_a.triangle_count) as usize;
+                let range_b = node_b.start_triangle as usize
+                    ..(node_b.start_triangle + node_b.triangle_count) as usize;
+                range_a.flat_map(move |i| range_b.clone().map(move |j| (i, j)))
+            })
+            .filter(|&(i, j)| {
+                let bounds_a = compute_bounds(&self.triangles, i, i + 1);
+                let bounds_b = compute_bounds(&other.triangles, j, j + 1);
+                aabbs_overlap(&bounds_a, &bounds_b)
+                    && triangle_intersection(&self.triangles[i], &other.triangles[j]).is_some()
+            })
+            .collect()
+    }
+
+    // checks this mesh against `other`, each optionally placed by a transform.
+    // The penetration volume is sampled on a `resolution`^3 grid over the overlap of both bounds
+    // and is only meaningful for closed meshes
+    pub fn interference(
+        &self,
+        transform: Option<&Mat4>,
+        other: &BVH,
+        other_transform: Option<&Mat4>,
+        resolution: usize,
+    ) -> Interference {
+        let moved_a = transform.map(|m| self.transformed(m));
+        let moved_b = other_transform.map(|m| other.transformed(m));
+        let a = moved_a.as_ref().unwrap_or(self);
+        let b = moved_b.as_ref().unwrap_or(other);
+
+        let overlapping = a.overlapping_triangles(b);
+        let segments: Vec<[[f32; 3]; 2]> = overlapping
+            .par_iter()
+            .filter_map(|&(i, j)| triangle_intersection(&a.triangles[i], &b.triangles[j]))
+            .collect();
+        let mut interference = Interference {
+            pairs: overlapping
+                .iter()
+                .map(|&(i, j)| (a.face_indices[i], b.face_indices[j]))
+                .collect(),
+            ..Default::default()
+        };
+        if a.nodes.is_empty() || b.nodes.is_empty() {
+            return interference;
+        }
+
+        let (bounds_a, bounds_b) = (a.nodes[0].bounds, b.nodes[0].bounds);
+        let mut overlap = [0.; 6];
+        for k in 0..3 {
+            overlap[k] = bounds_a[k].max(bounds_b[k]);
+            overlap[k + 3] = bounds_a[k + 3].min(bounds_b[k + 3]);
+        }
+        let diagonal = (0..3)
+            .map(|k| (overlap[k + 3] - overlap[k]).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        interference.curves = chain_segments(&segments, diagonal.max(1.) * 1e-5);
+        if !aabbs_overlap(&bounds_a, &bounds_b) || resolution == 0 {
+            return interference;
+        }
+
+        let winding_a = WindingNumbers::new(a);
+        let winding_b = WindingNumbers::new(b);
+        let step: Vec<f32> = (0..3)
+            .map(|k| (overlap[k + 3] - overlap[k]) / resolution as f32)
+            .collect();
+        let inside = (0..resolution.pow(3))
+            .into_par_iter()
+            .filter(|&i| {
+                let cell = [
+                    i % resolution,
+                    (i / resolution) % resolution,
+                    i / resolution.pow(2),
+                ];
+                let p = Vec3::new(
+                    overlap[0] + (cell[0] as f32 + 0.5) * step[0],
+                    overlap[1] + (cell[1] as f32 + 0.5) * step[1],
+                    overlap[2] + (cell[2] as f32 + 0.5) * step[2],
+                );
+                winding_a.at(&p) > 0.5 && winding_b.at(&p) > 0.5
+            })
+            .count();
+        interference.volume = inside as f32 * step[0] * step[1] * step[2];
+        interference
+    }
+}
 
 #[cfg(test)]
 mod test {
     use std::{path::PathBuf, str::FromStr, time::Instant};
 
-    use crate::utils::mesh::load_mesh;
+    use crate::utils::{
+        matrix::Mat4,
+        mesh::{load_mesh, Mesh},
+        ray::Ray,
+        vector::Vec3,
+    };
 
-    use super::create_bvh;
+    use rand::Rng;
+
+    use super::{
+        create_bvh, create_lbvh, is_leaf, moller_trumbore_intersection, morton_code_30,
+        morton_code_63, radix_sort, surface_area, triangle_intersection,
+    };
 
     #[test]
     fn bvh_test() {
@@ -570,4 +1536,205 @@ mod test {
         let _bvh = create_bvh(&mesh, 20);
         println!("BVH time: {:?} milliseconds", start.elapsed().as_millis());
     }
+
+    #[test]
+    fn refit_test() {
+        let mut mesh = load_mesh(&PathBuf::from_str("assets/monkey.stl").unwrap()).unwrap();
+        let mut bvh = create_bvh(&mesh, 20);
+        let original_bounds = bvh.nodes[0].bounds;
+
+        let untouched = bvh.refit(&mesh);
+        assert_eq!(untouched.nodes, None);
+        assert_eq!(untouched.triangles, None);
+
+        for vertex in mesh.vertices.iter_mut() {
+            vertex[0] += 5.;
+        }
+        let start = Instant::now();
+        let update = bvh.refit(&mesh);
+        println!("Refit time: {:?} microseconds", start.elapsed().as_micros());
+        assert_eq!(update.triangles, Some(0..bvh.triangles.len()));
+        assert!((bvh.nodes[0].bounds[0] - original_bounds[0] - 5.).abs() < 1e-4);
+        assert!((bvh.nodes[0].bounds[3] - original_bounds[3] - 5.).abs() < 1e-4);
+        assert_eq!(bvh.nodes[0].bounds, create_bvh(&mesh, 20).nodes[0].bounds);
+    }
+
+    #[test]
+    fn refit_rebuilds_degraded_subtrees() {
+        let mut mesh = load_mesh(&PathBuf::from_str("assets/monkey.stl").unwrap()).unwrap();
+        let mut bvh = create_bvh(&mesh, 20);
+        let node_count = bvh.nodes.len();
+
+        // stretch one half of the model far away so the nodes holding it blow up
+        for vertex in mesh.vertices.iter_mut() {
+            if vertex[0] > 0. {
+                vertex[1] *= 10.;
+            }
+        }
+        let update = bvh.refit(&mesh);
+        assert!(update.nodes.is_some());
+        for level in bvh.levels() {
+            for index in level {
+                let node = &bvh.nodes[index];
+                assert!(surface_area(&node.bounds) <= 2. * bvh.node_areas[index] + 1e-3);
+                let start = node.start_triangle as usize;
+                for tri in &bvh.triangles[start..start + node.triangle_count as usize] {
+                    for v in [tri.v1, tri.v2, tri.v3] {
+                        for (k, value) in v.iter().enumerate() {
+                            assert!(*value >= node.bounds[k] && *value <= node.bounds[k + 3]);
+                        }
+                    }
+                }
+            }
+        }
+        println!("Nodes before: {}, after: {}", node_count, bvh.nodes.len());
+    }
+
+    #[test]
+    fn morton_code_test() {
+        assert_eq!(morton_code_30([0., 0., 0.]), 0);
+        assert_eq!(morton_code_30([1., 1., 1.]), (1 << 30) - 1);
+        assert_eq!(morton_code_63([1., 1., 1.]), (1 << 63) - 1);
+        // x is the most significant axis of every bit triple
+        assert_eq!(morton_code_30([1. / 1024., 0., 0.]), 0b100);
+        assert_eq!(morton_code_63([0., 0., 1. / 2097152.]), 0b001);
+    }
+
+    #[test]
+    fn radix_sort_test() {
+        let mut rng = rand::thread_rng();
+        let mut pairs: Vec<(u64, u32)> = (0..100_000)
+            .map(|i| (rng.gen::<u64>() >> rng.gen_range(0..64), i))
+            .collect();
+        let mut expected = pairs.clone();
+        expected.sort_by_key(|pair| pair.0);
+        radix_sort(&mut pairs);
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn lbvh_test() {
+        let mesh = load_mesh(&PathBuf::from_str("assets/monkey.stl").unwrap()).unwrap();
+        let start = Instant::now();
+        let bvh = create_lbvh(&mesh, 20);
+        println!("LBVH time: {:?} microseconds", start.elapsed().as_micros());
+
+        assert_eq!(bvh.nodes[0].bounds, create_bvh(&mesh, 20).nodes[0].bounds);
+        let mut covered = vec![0; bvh.triangles.len()];
+        for level in bvh.levels() {
+            for index in level {
+                let node = &bvh.nodes[index];
+                let start = node.start_triangle as usize;
+                let end = start + node.triangle_count as usize;
+                for tri in &bvh.triangles[start..end] {
+                    for v in [tri.v1, tri.v2, tri.v3] {
+                        for (k, value) in v.iter().enumerate() {
+                            assert!(*value >= node.bounds[k] && *value <= node.bounds[k + 3]);
+                        }
+                    }
+                }
+                if is_leaf(node) {
+                    covered[start..end].iter_mut().for_each(|count| *count += 1);
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+        let mut faces = bvh.face_indices.clone();
+        faces.sort();
+        assert!(faces.iter().enumerate().all(|(i, &face)| i as u32 == face));
+    }
+
+    #[test]
+    fn ray_query_test() {
+        let mesh = load_mesh(&PathBuf::from_str("assets/monkey.stl").unwrap()).unwrap();
+        let bvh = create_bvh(&mesh, 20);
+        let mut rng = rand::thread_rng();
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let origin = Vec3::new(
+                rng.gen_range(-5.0..5.0),
+                rng.gen_range(-5.0..5.0),
+                rng.gen_range(-5.0..5.0),
+            );
+            let target = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let ray = Ray::new(origin, target - origin);
+
+            let brute_force = bvh
+                .triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(i, tri)| moller_trumbore_intersection(&ray, tri).map(|h| (h.0, i)))
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+            let hit = bvh.intersect(&ray);
+            assert_eq!(hit.as_ref().map(|h| h.distance), brute_force.map(|b| b.0));
+            let Some(hit) = hit else {
+                assert!(!bvh.occluded(&ray, f32::MAX));
+                continue;
+            };
+            hits += 1;
+
+            let face = mesh.faces[hit.triangle as usize];
+            let mut point = Vec3::new(0., 0., 0.);
+            for (weight, vertex) in hit.barycentrics.iter().zip(face.iter()) {
+                let v = mesh.vertices[*vertex];
+                point += Vec3::new(v[0], v[1], v[2]) * *weight;
+            }
+            assert!((point - ray.at(hit.distance)).length() < 1e-4);
+            assert!(bvh.occluded(&ray, hit.distance + 1e-3));
+            assert!(!bvh.occluded(&ray, hit.distance - 1e-3));
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn interference_test() {
+        let a = create_bvh(&Mesh::cuboid([0., 0., 0.], [1., 1., 1.]), 10);
+        let b = create_bvh(&Mesh::cuboid([0., 0., 0.], [1., 1., 1.]), 10);
+        // second box moved to [0.3, 1.3] x [0.4, 1.4] x [0.45, 1.45]
+        let translation = Mat4::from_translation(&Vec3::new(0.3, 0.4, 0.45));
+
+        let interference = a.interference(None, &b, Some(&translation), 10);
+        assert!(!interference.pairs.is_empty());
+        // a single loop along six edges of the overlap box
+        assert_eq!(interference.curves.len(), 1);
+        assert!(interference.curves[0].closed);
+        assert!((interference.curves[0].length() - 3.7).abs() < 1e-4);
+        assert!((interference.volume - 0.7 * 0.6 * 0.55).abs() < 0.01);
+
+        // the same box mirrored in x keeps its normals outward and overlaps just the same
+        let mirror = Mat4::from_translation(&Vec3::new(1.3, 0.4, 0.45))
+            * Mat4::from_scale(&Vec3::new(-1., 1., 1.));
+        let mirrored = b.transformed(&mirror);
+        for tri in &mirrored.triangles {
+            let centroid = (Vec3::from(tri.v1) + Vec3::from(tri.v2) + Vec3::from(tri.v3)) / 3.;
+            assert!(Vec3::from(tri.normal).dot(&(centroid - Vec3::new(0.8, 0.9, 0.95))) > 0.);
+        }
+        let interference = a.interference(None, &b, Some(&mirror), 10);
+        assert!((interference.volume - 0.7 * 0.6 * 0.55).abs() < 0.01);
+
+        let translation = Mat4::from_translation(&Vec3::new(2., 0., 0.));
+        let apart = a.interference(None, &b, Some(&translation), 10);
+        assert!(apart.pairs.is_empty() && apart.curves.is_empty());
+        assert_eq!(apart.volume, 0.);
+
+        // a row of boxes crossing the unit box finds the same pairs as testing every triangle
+        let boxes: Vec<Mesh> = (0..4)
+            .map(|i| {
+                let x = 0.7 * i as f32 - 0.4;
+                Mesh::cuboid([x, 0.2, 0.3], [x + 0.5, 0.8, 1.3])
+            })
+            .collect();
+        let boxes = create_bvh(&Mesh::merge(&boxes), 10);
+        let pairs = boxes.overlapping_triangles(&a);
+        let brute_force = (0..boxes.triangles.len())
+            .flat_map(|i| (0..a.triangles.len()).map(move |j| (i, j)))
+            .filter(|&(i, j)| triangle_intersection(&boxes.triangles[i], &a.triangles[j]).is_some())
+            .count();
+        assert!(brute_force > 0);
+        assert_eq!(pairs.len(), brute_force);
+    }
 }
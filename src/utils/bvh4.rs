@@ -0,0 +1,333 @@
+use crate::utils::bvh::{
+    intersect_aabb, is_leaf, moller_trumbore_intersection, surface_area, Triangle2, BVH,
+};
+use crate::utils::ray::Ray;
+use crate::utils::simd::intersect_aabb4;
+
+// marks an unused child slot of a Node4
+pub const INVALID_CHILD: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::NoUninit)]
+pub struct Node4 {
+    pub min_x: [f32; 4], // child aabbs, one lane per child
+    pub min_y: [f32; 4],
+    pub min_z: [f32; 4],
+    pub max_x: [f32; 4],
+    pub max_y: [f32; 4],
+    pub max_z: [f32; 4],
+    pub children: [u32; 4], // Node4 index for inner children, first triangle for leaves
+    pub triangle_counts: [u32; 4], // 0 for inner children and unused slots
+}
+
+impl Default for Node4 {
+    fn default() -> Self {
+        Self {
+            min_x: [f32::MAX; 4],
+            min_y: [f32::MAX; 4],
+            min_z: [f32::MAX; 4],
+            max_x: [f32::MIN; 4],
+            max_y: [f32::MIN; 4],
+            max_z: [f32::MIN; 4],
+            children: [INVALID_CHILD; 4],
+            triangle_counts: [0; 4],
+        }
+    }
+}
+
+// 4-wide tree over the triangles of the binary BVH it was collapsed from
+pub struct BVH4 {
+    pub nodes: Vec<Node4>,
+}
+
+// work done by a single ray traversal, used to compare tree layouts
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraversalStats {
+    pub distance: f32,
+    pub nodes_visited: u32,
+    pub box_tests: u32,
+    pub triangle_tests: u32,
+}
+
+// collapses `bvh` into a BVH4: every wide node takes the children of a binary node and keeps
+// opening its largest inner child until it holds four
+pub fn collapse(bvh: &BVH) -> BVH4 {
+    let mut wide = BVH4 { nodes: vec![] };
+    if !bvh.nodes.is_empty() {
+        if is_leaf(&bvh.nodes[0]) {
+            let mut root = Node4::default();
+            set_child(&mut root, 0, bvh, 0, 0);
+            wide.nodes.push(root);
+        } else {
+            collapse_node(bvh, 0, &mut wide.nodes);
+        }
+    }
+    wide
+}
+
+fn binary_children(bvh: &BVH, index: usize) -> Vec<usize> {
+    let node = &bvh.nodes[index];
+    [node.left_node, node.right_node]
+        .iter()
+        .filter(|&&child| child != 0)
+        .map(|&child| child as usize)
+        .collect()
+}
+
+fn set_child(wide_node: &mut Node4, slot: usize, bvh: &BVH, binary_index: usize, child: u32) {
+    let node = &bvh.nodes[binary_index];
+    wide_node.min_x[slot] = node.bounds[0];
+    wide_node.min_y[slot] = node.bounds[1];
+    wide_node.min_z[slot] = node.bounds[2];
+    wide_node.max_x[slot] = node.bounds[3];
+    wide_node.max_y[slot] = node.bounds[4];
+    wide_node.max_z[slot] = node.bounds[5];
+    if is_leaf(node) {
+        wide_node.children[slot] = node.start_triangle;
+        wide_node.triangle_counts[slot] = node.triangle_count;
+    } else {
+        wide_node.children[slot] = child;
+        wide_node.triangle_counts[slot] = 0;
+    }
+}
+
+fn collapse_node(bvh: &BVH, binary_index: usize, nodes: &mut Vec<Node4>) -> u32 {
+    let mut children = binary_children(bvh, binary_index);
+    loop {
+        let largest_inner = children
+            .iter()
+            .enumerate()
+            .filter(|(_, &child)| !is_leaf(&bvh.nodes[child]))
+            .max_by(|a, b| {
+                surface_area(&bvh.nodes[*a.1].bounds)
+                    .total_cmp(&surface_area(&bvh.nodes[*b.1].bounds))
+            })
+            .map(|(slot, _)| slot);
+        let Some(slot) = largest_inner else {
+            break;
+        };
+        let grandchildren = binary_children(bvh, children[slot]);
+        if children.len() - 1 + grandchildren.len() > 4 {
+            break;
+        }
+        children.remove(slot);
+        children.extend(grandchildren);
+    }
+
+    nodes.push(Node4::default());
+    let index = nodes.len() - 1;
+    for (slot, &child) in children.iter().enumerate() {
+        let wide_child = if is_leaf(&bvh.nodes[child]) {
+            0
+        } else {
+            collapse_node(bvh, child, nodes)
+        };
+        set_child(&mut nodes[index], slot, bvh, child, wide_child);
+    }
+    index as u32
+}
+
+fn triangle_distance(ray: &Ray, tri: &Triangle2) -> f32 {
+    moller_trumbore_intersection(ray, tri).map_or(f32::MAX, |(t, _, _)| t)
+}
+
+// closest hit of a ray against the binary tree, mirrors `traverse_bvh` in main.wgsl
+pub fn traverse_binary(bvh: &BVH, ray: &Ray) -> TraversalStats {
+    let mut stats = TraversalStats {
+        distance: f32::MAX,
+        ..Default::default()
+    };
+    if bvh.nodes.is_empty() {
+        return stats;
+    }
+    let box_distance =
+        |index: u32| intersect_aabb(ray, &bvh.nodes[index as usize].bounds).unwrap_or(f32::MAX);
+    let mut stack = vec![0u32];
+    while let Some(index) = stack.pop() {
+        stats.nodes_visited += 1;
+        stats.box_tests += 1;
+        if box_distance(index) >= stats.distance {
+            continue;
+        }
+        let node = &bvh.nodes[index as usize];
+        if is_leaf(node) {
+            let start = node.start_triangle as usize;
+            for tri in &bvh.triangles[start..start + node.triangle_count as usize] {
+                stats.triangle_tests += 1;
+                stats.distance = stats.distance.min(triangle_distance(ray, tri));
+            }
+            continue;
+        }
+        let mut hits = vec![];
+        for child in [node.left_node, node.right_node] {
+            if child != 0 {
+                stats.box_tests += 1;
+                let t = box_distance(child);
+                if t < stats.distance {
+                    hits.push((t, child));
+                }
+            }
+        }
+        // nearest child goes on top of the stack
+        hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+        stack.extend(hits.iter().map(|&(_, child)| child));
+    }
+    stats
+}
+
+impl BVH4 {
+    // closest hit of a ray against `triangles`, which must be the triangles of the BVH this tree
+    // was collapsed from. Mirrors `traverse_bvh4` in main.wgsl
+    pub fn traverse(&self, triangles: &[Triangle2], ray: &Ray) -> TraversalStats {
+        let mut stats = TraversalStats {
+            distance: f32::MAX,
+            ..Default::default()
+        };
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0u32]
+        };
+        while let Some(index) = stack.pop() {
+            stats.nodes_visited += 1;
+            let node = &self.nodes[index as usize];
+            let mut hits = vec![];
+            let distances = intersect_aabb4(ray, node);
+            for (slot, &t) in distances.iter().enumerate() {
+                if node.children[slot] == INVALID_CHILD {
+                    continue;
+                }
+                stats.box_tests += 1;
+                if t >= stats.distance {
+                    continue;
+                }
+                if node.triangle_counts[slot] > 0 {
+                    let start = node.children[slot] as usize;
+                    let end = start + node.triangle_counts[slot] as usize;
+                    for tri in &triangles[start..end] {
+                        stats.triangle_tests += 1;
+                        stats.distance = stats.distance.min(triangle_distance(ray, tri));
+                    }
+                } else {
+                    hits.push((t, node.children[slot]));
+                }
+            }
+            hits.retain(|&(t, _)| t < stats.distance);
+            hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+            stack.extend(hits.iter().map(|&(_, child)| child));
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, str::FromStr, time::Instant};
+
+    use rand::Rng;
+
+    use crate::utils::{
+        bvh::{create_bvh, is_leaf},
+        mesh::{load_mesh, Mesh},
+        ray::Ray,
+        vector::Vec3,
+    };
+
+    use super::{collapse, traverse_binary};
+
+    #[test]
+    fn leaf_test() {
+        // a row of boxes, so the tree has to split
+        let boxes: Vec<Mesh> = (0..8)
+            .map(|i| {
+                let x = 3. * i as f32;
+                Mesh::cuboid([x, 0., 0.], [x + 1., 1., 1.])
+            })
+            .collect();
+        let mesh = Mesh::merge(&boxes);
+        let bvh = create_bvh(&mesh, 10);
+
+        // inner nodes keep the triangle range of their subtree, so a triangle count does not
+        // mark a leaf, which is why traverse_bvh in main.wgsl tests for missing children
+        let root = &bvh.nodes[0];
+        assert!(!is_leaf(root));
+        assert_eq!(root.triangle_count as usize, mesh.faces.len());
+
+        // a ray down onto one box only tests the triangles of the leaves it reaches
+        let ray = Ray::new(Vec3::new(9.5, 5., 0.5), Vec3::new(0., -1., 0.));
+        let stats = traverse_binary(&bvh, &ray);
+        assert!((stats.distance - 4.).abs() < 1e-5);
+        assert!(stats.triangle_tests < mesh.faces.len() as u32 / 4);
+    }
+
+    #[test]
+    fn bvh4_benchmark() {
+        let mesh = load_mesh(&PathBuf::from_str("assets/monkey.stl").unwrap()).unwrap();
+        let bvh = create_bvh(&mesh, 25);
+        let start = Instant::now();
+        let wide = collapse(&bvh);
+        println!(
+            "Collapse time: {:?} microseconds, {} binary nodes -> {} wide nodes",
+            start.elapsed().as_micros(),
+            bvh.nodes.len(),
+            wide.nodes.len()
+        );
+
+        let bounds = bvh.nodes[0].bounds;
+        let center = [
+            (bounds[0] + bounds[3]) / 2.,
+            (bounds[1] + bounds[4]) / 2.,
+            (bounds[2] + bounds[5]) / 2.,
+        ];
+        let mut rng = rand::thread_rng();
+        let ray_count = 10_000;
+        let (mut binary_visits, mut wide_visits) = (0, 0);
+        let (mut binary_boxes, mut wide_boxes) = (0, 0);
+        let (mut binary_time, mut wide_time) = (0, 0);
+        let mut hits = 0;
+        for _ in 0..ray_count {
+            // rays from a sphere around the model aimed at jittered points inside its bounds
+            let mut origin = [0.; 3];
+            let mut direction = [0.; 3];
+            for k in 0..3 {
+                origin[k] = center[k] + rng.gen_range(-1.0..1.0) * 10.;
+                direction[k] =
+                    center[k] + rng.gen_range(-0.5..0.5) * (bounds[k + 3] - bounds[k]) - origin[k];
+            }
+            let ray = Ray::new(
+                Vec3::new(origin[0], origin[1], origin[2]),
+                Vec3::new(direction[0], direction[1], direction[2]),
+            );
+
+            let start = Instant::now();
+            let binary = traverse_binary(&bvh, &ray);
+            binary_time += start.elapsed().as_nanos();
+            let start = Instant::now();
+            let wide = wide.traverse(&bvh.triangles, &ray);
+            wide_time += start.elapsed().as_nanos();
+
+            assert_eq!(binary.distance, wide.distance);
+            if binary.distance < f32::MAX {
+                hits += 1;
+            }
+            binary_visits += binary.nodes_visited;
+            wide_visits += wide.nodes_visited;
+            binary_boxes += binary.box_tests;
+            wide_boxes += wide.box_tests;
+        }
+        println!("{} of {} rays hit", hits, ray_count);
+        println!(
+            "binary: {:.2} nodes / {:.2} boxes per ray, {} ns per ray",
+            binary_visits as f32 / ray_count as f32,
+            binary_boxes as f32 / ray_count as f32,
+            binary_time / ray_count as u128
+        );
+        println!(
+            "bvh4:   {:.2} nodes / {:.2} boxes per ray, {} ns per ray",
+            wide_visits as f32 / ray_count as f32,
+            wide_boxes as f32 / ray_count as f32,
+            wide_time / ray_count as u128
+        );
+        assert!(wide_visits < binary_visits);
+    }
+}
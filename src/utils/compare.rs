@@ -0,0 +1,205 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::utils::bvh::{Triangle2, TriangleShading, BVH};
+use crate::utils::vector::Vec3;
+
+// distances from sampled points of one surface to the closest points of another
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deviation {
+    pub hausdorff: f32, // largest distance
+    pub mean: f32,
+    pub rms: f32,
+    pub samples: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Comparison {
+    pub forward: Deviation,       // mesh to reference
+    pub backward: Deviation,      // reference to mesh
+    pub hausdorff: f32,           // symmetric, the larger of both directions
+    pub rms: f32,                 // over the samples of both directions
+    pub face_deviation: Vec<f32>, // per face of the compared mesh, largest distance of its corners and center
+}
+
+fn triangle_area(tri: &Triangle2) -> f32 {
+    let a = Vec3::new(tri.v1[0], tri.v1[1], tri.v1[2]);
+    let b = Vec3::new(tri.v2[0], tri.v2[1], tri.v2[2]);
+    let c = Vec3::new(tri.v3[0], tri.v3[1], tri.v3[2]);
+    (b - a).cross(&(c - a)).length() * 0.5
+}
+
+// `count` points spread over the surface proportionally to triangle area, reproducible for a seed
+pub fn sample_surface(bvh: &BVH, count: usize, seed: u64) -> Vec<Vec3<f32>> {
+    let mut cdf: Vec<f32> = Vec::with_capacity(bvh.triangles.len());
+    let mut total = 0.;
+    for tri in bvh.triangles.iter() {
+        total += triangle_area(tri);
+        cdf.push(total);
+    }
+    if total <= 0. {
+        return vec![];
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| {
+            let target = rng.gen_range(0.0..total);
+            let i = cdf.partition_point(|&c| c <= target).min(cdf.len() - 1);
+            let tri = &bvh.triangles[i];
+            let (mut u, mut v): (f32, f32) = (rng.gen(), rng.gen());
+            if u + v > 1. {
+                (u, v) = (1. - u, 1. - v);
+            }
+            let p =
+                |k: usize| tri.v1[k] + (tri.v2[k] - tri.v1[k]) * u + (tri.v3[k] - tri.v1[k]) * v;
+            Vec3::new(p(0), p(1), p(2))
+        })
+        .collect()
+}
+
+fn distances(points: &[Vec3<f32>], to: &BVH) -> Vec<f32> {
+    points
+        .par_iter()
+        .map(|p| to.closest_point(p).map_or(f32::MAX, |c| c.distance))
+        .collect()
+}
+
+fn summarize(distances: &[f32]) -> Deviation {
+    if distances.is_empty() {
+        return Deviation::default();
+    }
+    let n = distances.len() as f64;
+    Deviation {
+        hausdorff: distances.iter().copied().fold(0., f32::max),
+        mean: (distances.iter().map(|&d| d as f64).sum::<f64>() / n) as f32,
+        rms: (distances.iter().map(|&d| (d as f64).powi(2)).sum::<f64>() / n).sqrt() as f32,
+        samples: distances.len(),
+    }
+}
+
+// one-sided deviation of `from` against `to`
+pub fn one_sided(from: &BVH, to: &BVH, samples: usize) -> Deviation {
+    summarize(&distances(&sample_surface(from, samples, 0), to))
+}
+
+// compares `mesh` against `reference` with `samples` surface points in each direction
+pub fn compare(mesh: &BVH, reference: &BVH, samples: usize) -> Comparison {
+    let forward = distances(&sample_surface(mesh, samples, 0), reference);
+    let backward = distances(&sample_surface(reference, samples, 1), mesh);
+    let both: Vec<f32> = forward.iter().chain(backward.iter()).copied().collect();
+    let (forward, backward, both) = (summarize(&forward), summarize(&backward), summarize(&both));
+
+    let mut face_deviation = vec![0.; mesh.face_indices.len()];
+    let per_triangle: Vec<f32> = mesh
+        .triangles
+        .par_iter()
+        .map(|tri| {
+            let center = [0, 1, 2].map(|k| (tri.v1[k] + tri.v2[k] + tri.v3[k]) / 3.);
+            [tri.v1, tri.v2, tri.v3, center]
+                .iter()
+                .map(|v| {
+                    reference
+                        .closest_point(&Vec3::new(v[0], v[1], v[2]))
+                        .map_or(f32::MAX, |c| c.distance)
+                })
+                .fold(0., f32::max)
+        })
+        .collect();
+    for (&face, deviation) in mesh.face_indices.iter().zip(per_triangle) {
+        if let Some(d) = face_deviation.get_mut(face as usize) {
+            *d = deviation;
+        }
+    }
+
+    Comparison {
+        forward,
+        backward,
+        hausdorff: forward.hausdorff.max(backward.hausdorff),
+        rms: both.rms,
+        face_deviation,
+    }
+}
+
+// blue at 0 through green and yellow to red at 1
+pub fn heatmap_color(t: f32) -> [f32; 4] {
+    let stops = [
+        [0.1, 0.2, 0.9],
+        [0.1, 0.8, 0.3],
+        [0.95, 0.9, 0.1],
+        [0.9, 0.1, 0.1],
+    ];
+    let scaled = t.clamp(0., 1.) * (stops.len() - 1) as f32;
+    let i = (scaled as usize).min(stops.len() - 2);
+    let f = scaled - i as f32;
+    let c = [0, 1, 2].map(|k| stops[i][k] + (stops[i + 1][k] - stops[i][k]) * f);
+    [c[0], c[1], c[2], 1.]
+}
+
+// per face colors for `face_deviation`, saturating at `max_deviation`
+pub fn heatmap_shading(face_deviation: &[f32], max_deviation: f32) -> Vec<TriangleShading> {
+    let scale = if max_deviation > 0. {
+        1. / max_deviation
+    } else {
+        0.
+    };
+    face_deviation
+        .iter()
+        .map(|&d| TriangleShading {
+            color: heatmap_color(d * scale),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::{bvh::create_bvh, mesh::Mesh};
+
+    use super::{compare, heatmap_shading, one_sided};
+
+    #[test]
+    fn cube_comparison_test() {
+        let inner = create_bvh(&Mesh::cuboid([0., 0., 0.], [1., 1., 1.]), 10);
+        let outer = create_bvh(&Mesh::cuboid([-0.05, -0.05, -0.05], [1.05, 1.05, 1.05]), 10);
+
+        let same = compare(&inner, &inner, 1000);
+        assert!(same.hausdorff < 1e-6);
+        assert!(same.face_deviation.iter().all(|&d| d < 1e-6));
+
+        // every point of the inner box is 0.05 from the outer one, the outer corners are further out
+        let forward = one_sided(&inner, &outer, 2000);
+        assert!((forward.hausdorff - 0.05).abs() < 1e-5);
+        assert!((forward.rms - 0.05).abs() < 1e-5);
+        let comparison = compare(&inner, &outer, 2000);
+        assert!(comparison.backward.hausdorff > 0.05);
+        assert!(comparison.hausdorff <= 0.05 * 3f32.sqrt() + 1e-5);
+        assert_eq!(comparison.face_deviation.len(), 12);
+        assert!(comparison
+            .face_deviation
+            .iter()
+            .all(|&d| (d - 0.05).abs() < 1e-5));
+        assert_eq!(heatmap_shading(&comparison.face_deviation, 0.1).len(), 12);
+    }
+
+    #[test]
+    fn stretched_comparison_test() {
+        // stretching a box by 5% along x moves its x faces by 0.05 and the rim of the others by
+        // up to 0.05, so the deviation varies over the surface
+        let reference = create_bvh(&Mesh::cuboid([-1., -1., -1.], [1., 1., 1.]), 10);
+        let stretched = create_bvh(&Mesh::cuboid([-1.05, -1., -1.], [1.05, 1., 1.]), 10);
+        let comparison = compare(&stretched, &reference, 4000);
+        assert!((comparison.hausdorff - 0.05).abs() < 1e-5);
+        assert!((comparison.backward.hausdorff - 0.05).abs() < 1e-5);
+
+        // an area of 8 at 0.05 and a 0.1 / 2.1 share of the other 16.8 spread evenly up to 0.05
+        let (x_faces, rim) = (8., 16.8 * 0.1 / 2.1);
+        let mean = (x_faces * 0.05 + rim * 0.025) / 24.8;
+        let rms = ((x_faces * 0.05 * 0.05 + rim * 0.05 * 0.05 / 3.) / 24.8f32).sqrt();
+        assert!((comparison.forward.mean - mean).abs() < 0.05 * mean);
+        assert!((comparison.forward.rms - rms).abs() < 0.05 * rms);
+        assert!(comparison
+            .face_deviation
+            .iter()
+            .all(|&d| (d - 0.05).abs() < 1e-5));
+    }
+}
@@ -0,0 +1,365 @@
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+
+use crate::utils::bvh::{is_leaf, Triangle2, BVH};
+use crate::utils::vector::Vec3;
+use crate::utils::MinHeap;
+
+// nodes further away than this many times their radius use the dipole approximation
+pub const WINDING_ACCURACY: f32 = 2.0;
+
+#[derive(Clone, Debug)]
+pub struct ClosestPoint {
+    pub point: Vec3<f32>,
+    pub triangle: u32, // mesh face index
+    pub distance: f32,
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3<f32> {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+// nearest point of a triangle, Ericson's Real-Time Collision Detection 5.1.5
+pub fn closest_point_on_triangle(p: &Vec3<f32>, tri: &Triangle2) -> Vec3<f32> {
+    let a = to_vec3(tri.v1);
+    let b = to_vec3(tri.v2);
+    let c = to_vec3(tri.v3);
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0. && d2 <= 0. {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0. && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        let v = d1 / (d1 - d3);
+        return a + ab * v;
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0. && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        let w = d2 / (d2 - d6);
+        return a + ac * w;
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && (d4 - d3) >= 0. && (d5 - d6) >= 0. {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return b + (c - b) * w;
+    }
+
+    let denom = 1. / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    a + ab * v + ac * w
+}
+
+pub fn aabb_distance_squared(p: &Vec3<f32>, bounds: &[f32; 6]) -> f32 {
+    let mut distance = 0.;
+    for k in 0..3 {
+        let d = (bounds[k] - p.v[k]).max(0.).max(p.v[k] - bounds[k + 3]);
+        distance += d * d;
+    }
+    distance
+}
+
+// signed solid angle of the triangle seen from `p` over 4 pi (Van Oosterom and Strackee),
+// positive when `p` is behind a counter-clockwise triangle
+pub fn triangle_winding_number(p: &Vec3<f32>, tri: &Triangle2) -> f64 {
+    let rel = |v: [f32; 3]| {
+        Vec3::new(
+            v[0] as f64 - p.v[0] as f64,
+            v[1] as f64 - p.v[1] as f64,
+            v[2] as f64 - p.v[2] as f64,
+        )
+    };
+    let a = rel(tri.v1);
+    let b = rel(tri.v2);
+    let c = rel(tri.v3);
+    let (la, lb, lc) = (a.length(), b.length(), c.length());
+    let det = a.dot(&b.cross(&c));
+    let div = la * lb * lc + a.dot(&b) * lc + b.dot(&c) * la + c.dot(&a) * lb;
+    det.atan2(div) / (2. * PI)
+}
+
+impl BVH {
+    // nearest surface point, searching the nodes best-first by their distance to `p`
+    pub fn closest_point(&self, p: &Vec3<f32>) -> Option<ClosestPoint> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best: Option<(f32, Vec3<f32>, usize)> = None;
+        // squared distances are never negative, so their bit patterns sort like the floats
+        let mut heap = MinHeap::<(u32, u32)>::new();
+        heap.push((aabb_distance_squared(p, &self.nodes[0].bounds).to_bits(), 0));
+        while let Some((distance_bits, index)) = heap.pop() {
+            if best
+                .as_ref()
+                .is_some_and(|b| f32::from_bits(distance_bits) >= b.0)
+            {
+                break;
+            }
+            let node = &self.nodes[index as usize];
+            if is_leaf(node) {
+                let start = node.start_triangle as usize;
+                for i in start..start + node.triangle_count as usize {
+                    let point = closest_point_on_triangle(p, &self.triangles[i]);
+                    let distance = (point - p).squared_length();
+                    if best.as_ref().map_or(true, |b| distance < b.0) {
+                        best = Some((distance, point, i));
+                    }
+                }
+                continue;
+            }
+            for child in [node.left_node, node.right_node] {
+                if child != 0 {
+                    let distance = aabb_distance_squared(p, &self.nodes[child as usize].bounds);
+                    heap.push((distance.to_bits(), child));
+                }
+            }
+        }
+        best.map(|(distance, point, i)| ClosestPoint {
+            point,
+            triangle: self.face_indices[i],
+            distance: distance.sqrt(),
+        })
+    }
+
+    // exact generalized winding number: ~1 inside a closed outward facing mesh, ~0 outside.
+    // Visits every triangle, use `WindingNumbers` for many queries
+    pub fn winding_number(&self, p: &Vec3<f32>) -> f32 {
+        self.triangles
+            .par_iter()
+            .map(|tri| triangle_winding_number(p, tri))
+            .sum::<f64>() as f32
+    }
+
+    // distance to the surface, negative inside. Only meaningful for watertight meshes
+    pub fn signed_distance(&self, p: &Vec3<f32>) -> Option<f32> {
+        let closest = self.closest_point(p)?;
+        if self.winding_number(p) > 0.5 {
+            Some(-closest.distance)
+        } else {
+            Some(closest.distance)
+        }
+    }
+}
+
+// area weighted normal and centroid of a subtree, enough for the far field of its winding number
+#[derive(Clone, Copy, Debug)]
+struct WindingNode {
+    center: [f64; 3],
+    normal: [f64; 3],
+    radius: f64,
+}
+
+// fast winding numbers (Barill et al. 2018): subtrees far from the query point are replaced by a
+// single dipole instead of summing all of their triangles
+pub struct WindingNumbers<'a> {
+    pub bvh: &'a BVH,
+    nodes: Vec<WindingNode>,
+}
+
+impl<'a> WindingNumbers<'a> {
+    pub fn new(bvh: &'a BVH) -> Self {
+        // inner nodes keep the triangle range of their subtree, so every node is independent
+        let nodes = (0..bvh.nodes.len())
+            .into_par_iter()
+            .map(|index| {
+                let node = &bvh.nodes[index];
+                let start = node.start_triangle as usize;
+                let mut area_sum = 0.;
+                let mut center = [0.; 3];
+                let mut normal = [0.; 3];
+                for tri in &bvh.triangles[start..start + node.triangle_count as usize] {
+                    let a = to_vec3(tri.v1).convert::<f64>();
+                    let b = to_vec3(tri.v2).convert::<f64>();
+                    let c = to_vec3(tri.v3).convert::<f64>();
+                    let n = (b - a).cross(&(c - a)) * 0.5;
+                    let area = n.length();
+                    let centroid = (a + b + c) / 3.;
+                    for (sum, c) in center.iter_mut().zip(centroid.v) {
+                        *sum += c * area;
+                    }
+                    for (sum, n) in normal.iter_mut().zip(n.v) {
+                        *sum += n;
+                    }
+                    area_sum += area;
+                }
+                if area_sum > 0. {
+                    center.iter_mut().for_each(|c| *c /= area_sum);
+                }
+                let mut radius: f64 = 0.;
+                for corner in 0..8 {
+                    let distance: f64 = (0..3)
+                        .map(|k| {
+                            let bound = node.bounds[if corner & (1 << k) == 0 { k } else { k + 3 }];
+                            (bound as f64 - center[k]).powi(2)
+                        })
+                        .sum();
+                    radius = radius.max(distance.sqrt());
+                }
+                WindingNode {
+                    center,
+                    normal,
+                    radius,
+                }
+            })
+            .collect();
+        Self { bvh, nodes }
+    }
+
+    pub fn at(&self, p: &Vec3<f32>) -> f32 {
+        if self.bvh.nodes.is_empty() {
+            return 0.;
+        }
+        let q = [p.v[0] as f64, p.v[1] as f64, p.v[2] as f64];
+        let mut winding = 0.;
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.bvh.nodes[index as usize];
+            let far = &self.nodes[index as usize];
+            let d = [
+                far.center[0] - q[0],
+                far.center[1] - q[1],
+                far.center[2] - q[2],
+            ];
+            let distance = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+            if distance > WINDING_ACCURACY as f64 * far.radius {
+                let dot = d[0] * far.normal[0] + d[1] * far.normal[1] + d[2] * far.normal[2];
+                winding += dot / (4. * PI * distance.powi(3));
+            } else if is_leaf(node) {
+                let start = node.start_triangle as usize;
+                for tri in &self.bvh.triangles[start..start + node.triangle_count as usize] {
+                    winding += triangle_winding_number(p, tri);
+                }
+            } else {
+                stack.extend(
+                    [node.left_node, node.right_node]
+                        .iter()
+                        .filter(|&&c| c != 0),
+                );
+            }
+        }
+        winding as f32
+    }
+
+    pub fn signed_distance(&self, p: &Vec3<f32>) -> Option<f32> {
+        let closest = self.bvh.closest_point(p)?;
+        if self.at(p) > 0.5 {
+            Some(-closest.distance)
+        } else {
+            Some(closest.distance)
+        }
+    }
+}
+
+// samples the signed distance at the centers of a `resolution` grid spanning `bounds`,
+// x varies fastest
+pub fn signed_distance_grid(bvh: &BVH, bounds: &[f32; 6], resolution: [usize; 3]) -> Vec<f32> {
+    let winding = WindingNumbers::new(bvh);
+    let step = [
+        (bounds[3] - bounds[0]) / resolution[0] as f32,
+        (bounds[4] - bounds[1]) / resolution[1] as f32,
+        (bounds[5] - bounds[2]) / resolution[2] as f32,
+    ];
+    (0..resolution[0] * resolution[1] * resolution[2])
+        .into_par_iter()
+        .map(|i| {
+            let x = i % resolution[0];
+            let y = (i / resolution[0]) % resolution[1];
+            let z = i / (resolution[0] * resolution[1]);
+            let p = Vec3::new(
+                bounds[0] + (x as f32 + 0.5) * step[0],
+                bounds[1] + (y as f32 + 0.5) * step[1],
+                bounds[2] + (z as f32 + 0.5) * step[2],
+            );
+            winding.signed_distance(&p).unwrap_or(f32::MAX)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use crate::utils::{bvh::create_bvh, mesh::Mesh, vector::Vec3};
+
+    use super::{closest_point_on_triangle, signed_distance_grid, WindingNumbers};
+
+    #[test]
+    fn cube_distance_test() {
+        let bvh = create_bvh(&Mesh::cuboid([0., 0., 0.], [1., 1., 1.]), 10);
+
+        let closest = bvh.closest_point(&Vec3::new(2., 0.5, 0.5)).unwrap();
+        assert!((closest.distance - 1.).abs() < 1e-6);
+        assert!((closest.point - Vec3::new(1., 0.5, 0.5)).length() < 1e-6);
+
+        assert!((bvh.winding_number(&Vec3::new(0.5, 0.5, 0.5)) - 1.).abs() < 1e-4);
+        assert!(bvh.winding_number(&Vec3::new(1.5, 0.5, 0.5)).abs() < 1e-4);
+        assert!((bvh.signed_distance(&Vec3::new(0.5, 0.5, 0.5)).unwrap() + 0.5).abs() < 1e-6);
+        assert!((bvh.signed_distance(&Vec3::new(0.5, 0.5, 3.)).unwrap() - 2.).abs() < 1e-6);
+
+        let grid = signed_distance_grid(&bvh, &[-1., -1., -1., 2., 2., 2.], [3, 3, 3]);
+        assert!((grid[13] + 0.5).abs() < 1e-6);
+        assert!(grid.iter().enumerate().all(|(i, &d)| (i == 13) == (d < 0.)));
+    }
+
+    #[test]
+    fn closest_point_test() {
+        // a flat box, where the distance to the surface is known everywhere
+        let (min, max) = ([0., 0., 0.], [2., 1., 0.5]);
+        let bvh = create_bvh(&Mesh::cuboid(min, max), 10);
+        let winding = WindingNumbers::new(&bvh);
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let p = Vec3::new(
+                rng.gen_range(-1.0..3.0),
+                rng.gen_range(-1.0..2.0),
+                rng.gen_range(-1.0..1.5),
+            );
+            let inside = (0..3).all(|k| p.v[k] > min[k] && p.v[k] < max[k]);
+            let expected = if inside {
+                (0..3)
+                    .map(|k| (p.v[k] - min[k]).min(max[k] - p.v[k]))
+                    .fold(f32::MAX, f32::min)
+            } else {
+                let outside = Vec3::new(
+                    (min[0] - p.v[0]).max(0.).max(p.v[0] - max[0]),
+                    (min[1] - p.v[1]).max(0.).max(p.v[1] - max[1]),
+                    (min[2] - p.v[2]).max(0.).max(p.v[2] - max[2]),
+                );
+                outside.length()
+            };
+            let brute_force = bvh
+                .triangles
+                .iter()
+                .map(|tri| (closest_point_on_triangle(&p, tri) - p).length())
+                .fold(f32::MAX, f32::min);
+            let closest = bvh.closest_point(&p).unwrap();
+            assert!((closest.distance - expected).abs() < 1e-5);
+            assert!((closest.distance - brute_force).abs() < 1e-5);
+            let sign = if inside { -1. } else { 1. };
+            assert!((bvh.signed_distance(&p).unwrap() - sign * expected).abs() < 1e-5);
+            assert!((winding.at(&p) - bvh.winding_number(&p)).abs() < 0.05);
+        }
+    }
+}
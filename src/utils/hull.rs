@@ -0,0 +1,520 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::utils::mesh::Mesh;
+
+// hull face during construction, counter-clockwise seen from outside
+struct HullFace {
+    vertices: [usize; 3],
+    normal: [f64; 3],
+    offset: f64,         // plane: dot(normal, p) == offset
+    outside: Vec<usize>, // points above this face and no earlier one
+    alive: bool,
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: &[f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+impl HullFace {
+    fn new(vertices: [usize; 3], points: &[[f64; 3]]) -> Self {
+        let [a, b, c] = vertices.map(|v| points[v]);
+        let n = cross(&sub(&b, &a), &sub(&c, &a));
+        let l = length(&n);
+        let normal = if l > 0. {
+            [n[0] / l, n[1] / l, n[2] / l]
+        } else {
+            n
+        };
+        Self {
+            vertices,
+            normal,
+            offset: dot(&normal, &a),
+            outside: vec![],
+            alive: true,
+        }
+    }
+
+    fn distance(&self, p: &[f64; 3]) -> f64 {
+        dot(&self.normal, p) - self.offset
+    }
+
+    fn edges(&self) -> [(usize, usize); 3] {
+        let [a, b, c] = self.vertices;
+        [(a, b), (b, c), (c, a)]
+    }
+}
+
+// four points spanning a tetrahedron, Err when all points are (nearly) collinear or coplanar
+fn initial_simplex(points: &[[f64; 3]], epsilon: f64) -> Result<[usize; 4], String> {
+    // the most distant pair among the axis extremes
+    let mut extremes = vec![];
+    for k in 0..3 {
+        let by_axis = |a: &&[f64; 3], b: &&[f64; 3]| a[k].total_cmp(&b[k]);
+        let min = points.iter().enumerate().min_by(|a, b| by_axis(&a.1, &b.1));
+        let max = points.iter().enumerate().max_by(|a, b| by_axis(&a.1, &b.1));
+        extremes.extend(min.map(|m| m.0));
+        extremes.extend(max.map(|m| m.0));
+    }
+    let mut best = (0., 0, 0);
+    for &i in extremes.iter() {
+        for &j in extremes.iter() {
+            let d = length(&sub(&points[i], &points[j]));
+            if d > best.0 {
+                best = (d, i, j);
+            }
+        }
+    }
+    let (span, a, b) = best;
+    if span <= epsilon {
+        return Err("Convex hull needs at least two distinct points".to_string());
+    }
+
+    // farthest from the line a-b
+    let ab = sub(&points[b], &points[a]);
+    let (line_distance, c) = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (length(&cross(&ab, &sub(p, &points[a]))) / span, i))
+        .max_by(|x, y| x.0.total_cmp(&y.0))
+        .unwrap();
+    if line_distance <= epsilon {
+        return Err("Convex hull input is collinear".to_string());
+    }
+
+    // farthest from the plane a-b-c
+    let plane = HullFace::new([a, b, c], points);
+    let (plane_distance, d) = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (plane.distance(p).abs(), i))
+        .max_by(|x, y| x.0.total_cmp(&y.0))
+        .unwrap();
+    if plane_distance <= epsilon {
+        return Err("Convex hull input is coplanar".to_string());
+    }
+    Ok([a, b, c, d])
+}
+
+// the face across the edge u-v, Err when the hull stopped being a closed surface, which tolerance
+// problems on nearly degenerate input can cause
+fn face_across(
+    edge_faces: &HashMap<(usize, usize), usize>,
+    u: usize,
+    v: usize,
+) -> Result<usize, String> {
+    edge_faces.get(&(v, u)).copied().ok_or_else(|| {
+        "Convex hull lost its closed surface, the input is too degenerate".to_string()
+    })
+}
+
+// vertex indices and outward normal of a finished hull triangle
+type HullTriangle = ([usize; 3], [f64; 3]);
+
+fn find(parent: &mut [usize], mut f: usize) -> usize {
+    while parent[f] != f {
+        parent[f] = parent[parent[f]];
+        f = parent[f];
+    }
+    f
+}
+
+// a point that became a hull vertex early can end up in the middle of a flat region or edge once
+// later points are added. Joins neighbouring faces lying in one plane into polygons, drops the
+// vertices that are no corner of any polygon and triangulates the polygons again as fans
+fn merge_coplanar(
+    faces: &[HullFace],
+    edge_faces: &HashMap<(usize, usize), usize>,
+    points: &[[f64; 3]],
+    epsilon: f64,
+) -> Result<Vec<HullTriangle>, String> {
+    let mut parent: Vec<usize> = (0..faces.len()).collect();
+    for (f, face) in faces.iter().enumerate().filter(|(_, face)| face.alive) {
+        for (u, v) in face.edges() {
+            let g = face_across(edge_faces, u, v)?;
+            let (root_f, root_g) = (find(&mut parent, f), find(&mut parent, g));
+            if root_f == root_g {
+                continue;
+            }
+            // compare against the first face of each group so flat regions cannot drift
+            let flat = |plane: &HullFace, other: &HullFace| {
+                other
+                    .vertices
+                    .iter()
+                    .all(|&p| plane.distance(&points[p]).abs() <= epsilon)
+            };
+            let (a, b) = (&faces[root_f], &faces[root_g]);
+            if dot(&a.normal, &b.normal) > 0. && flat(a, b) && flat(b, a) {
+                parent[root_g] = root_f;
+            }
+        }
+    }
+
+    // boundary of every group, as the next vertex along the counter-clockwise outline
+    let mut outlines: HashMap<usize, HashMap<usize, usize>> = HashMap::new();
+    for (f, face) in faces.iter().enumerate().filter(|(_, face)| face.alive) {
+        let root = find(&mut parent, f);
+        for (u, v) in face.edges() {
+            let g = face_across(edge_faces, u, v)?;
+            if find(&mut parent, g) != root {
+                outlines.entry(root).or_default().insert(u, v);
+            }
+        }
+    }
+    let mut polygons: Vec<(usize, Vec<usize>)> = vec![];
+    for (&root, next) in outlines.iter() {
+        let start = *next.keys().min().unwrap();
+        let mut polygon = vec![start];
+        let mut current = next.get(&start).copied();
+        while let Some(vertex) = current.filter(|&v| v != start && polygon.len() <= next.len()) {
+            polygon.push(vertex);
+            current = next.get(&vertex).copied();
+        }
+        if polygon.len() != next.len() {
+            // not a simple outline, keep the group's triangles as they are
+            for (f, face) in faces.iter().enumerate().filter(|(_, face)| face.alive) {
+                if find(&mut parent, f) == root {
+                    polygons.push((f, face.vertices.to_vec()));
+                }
+            }
+            continue;
+        }
+        polygons.push((root, polygon));
+    }
+
+    // a vertex is kept when it is a proper corner of at least one polygon
+    let mut corners: HashMap<usize, bool> = HashMap::new();
+    for (_, polygon) in polygons.iter() {
+        for (i, &q) in polygon.iter().enumerate() {
+            let p = points[polygon[(i + polygon.len() - 1) % polygon.len()]];
+            let r = points[polygon[(i + 1) % polygon.len()]];
+            let pr = sub(&r, &p);
+            let line_distance =
+                length(&cross(&pr, &sub(&points[q], &p))) / length(&pr).max(f64::MIN_POSITIVE);
+            *corners.entry(q).or_default() |= line_distance > epsilon;
+        }
+    }
+    polygons.sort_by_key(|(root, _)| *root);
+    let mut triangles = vec![];
+    for (root, mut polygon) in polygons {
+        polygon.retain(|q| corners[q]);
+        for i in 1..polygon.len().saturating_sub(1) {
+            triangles.push(([polygon[0], polygon[i], polygon[i + 1]], faces[root].normal));
+        }
+    }
+    Ok(triangles)
+}
+
+// quickhull over a point set. Computed in f64 with a tolerance scaled to the input, points within
+// it of a hull plane count as inside, so coplanar and duplicate points never create sliver faces.
+// Returns an indexed mesh of outward facing triangles holding only the hull's vertices
+pub fn convex_hull(input: &[[f32; 3]]) -> Result<Mesh, String> {
+    if input.len() < 4 {
+        return Err(format!(
+            "Convex hull needs at least 4 points, got {}",
+            input.len()
+        ));
+    }
+    let points: Vec<[f64; 3]> = input
+        .iter()
+        .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+        .collect();
+    if points.iter().flatten().any(|c| !c.is_finite()) {
+        return Err("Convex hull input contains non-finite coordinates".to_string());
+    }
+    let mut max_abs = [0f64; 3];
+    for p in points.iter() {
+        for k in 0..3 {
+            max_abs[k] = max_abs[k].max(p[k].abs());
+        }
+    }
+    // round off of the f32 input dominates, tolerate a few of its ulps
+    let epsilon = (max_abs[0] + max_abs[1] + max_abs[2]) * 4. * f32::EPSILON as f64;
+
+    let simplex = initial_simplex(&points, epsilon)?;
+    let mut faces: Vec<HullFace> = vec![];
+    let [a, b, c, d] = simplex;
+    let base = HullFace::new([a, b, c], &points);
+    // orient the tetrahedron so its faces point away from the fourth vertex
+    let tetrahedron = if base.distance(&points[d]) < 0. {
+        [[a, b, c], [a, d, b], [b, d, c], [c, d, a]]
+    } else {
+        [[a, c, b], [a, b, d], [b, c, d], [c, a, d]]
+    };
+    for vertices in tetrahedron {
+        faces.push(HullFace::new(vertices, &points));
+    }
+    let mut edge_faces: HashMap<(usize, usize), usize> = HashMap::new();
+    for (f, face) in faces.iter().enumerate() {
+        for edge in face.edges() {
+            edge_faces.insert(edge, f);
+        }
+    }
+
+    let assign =
+        |faces: &mut Vec<HullFace>, candidates: &[usize], range: std::ops::Range<usize>| {
+            for &p in candidates {
+                if let Some(face) = faces[range.clone()]
+                    .iter_mut()
+                    .find(|face| face.alive && face.distance(&points[p]) > epsilon)
+                {
+                    face.outside.push(p);
+                }
+            }
+        };
+    let candidates: Vec<usize> = (0..points.len()).filter(|p| !simplex.contains(p)).collect();
+    assign(&mut faces, &candidates, 0..4);
+
+    while let Some(start) = faces
+        .iter()
+        .position(|face| face.alive && !face.outside.is_empty())
+    {
+        let eye = *faces[start]
+            .outside
+            .iter()
+            .max_by(|&&x, &&y| {
+                faces[start]
+                    .distance(&points[x])
+                    .total_cmp(&faces[start].distance(&points[y]))
+            })
+            .unwrap();
+
+        // faces seen from the eye, grown from `start` across shared edges so the set stays connected
+        let mut visible = vec![start];
+        let mut queued = HashSet::from([start]);
+        let mut is_visible: HashMap<usize, bool> = HashMap::from([(start, true)]);
+        let mut horizon = vec![];
+        let mut i = 0;
+        while i < visible.len() {
+            let f = visible[i];
+            i += 1;
+            for (u, v) in faces[f].edges() {
+                let neighbour = face_across(&edge_faces, u, v)?;
+                let seen = *is_visible
+                    .entry(neighbour)
+                    .or_insert_with(|| faces[neighbour].distance(&points[eye]) > epsilon);
+                if seen {
+                    if queued.insert(neighbour) {
+                        visible.push(neighbour);
+                    }
+                } else {
+                    horizon.push((u, v));
+                }
+            }
+        }
+
+        let mut orphans = vec![];
+        for &f in visible.iter() {
+            faces[f].alive = false;
+            orphans.append(&mut faces[f].outside);
+            for edge in faces[f].edges() {
+                edge_faces.remove(&edge);
+            }
+        }
+        orphans.retain(|&p| p != eye);
+
+        let first_new = faces.len();
+        for (u, v) in horizon {
+            faces.push(HullFace::new([u, v, eye], &points));
+            for edge in faces[faces.len() - 1].edges() {
+                edge_faces.insert(edge, faces.len() - 1);
+            }
+        }
+        let end = faces.len();
+        assign(&mut faces, &orphans, first_new..end);
+    }
+
+    // compact to the vertices the hull uses
+    let mut remap: HashMap<usize, usize> = HashMap::new();
+    let mut mesh = Mesh::e_new();
+    for (vertices, normal) in merge_coplanar(&faces, &edge_faces, &points, epsilon)? {
+        let indices = vertices.map(|v| {
+            *remap.entry(v).or_insert_with(|| {
+                mesh.vertices.push(input[v]);
+                mesh.vertices.len() - 1
+            })
+        });
+        mesh.faces.push(indices);
+        mesh.normals.push(normal.map(|n| n as f32));
+    }
+    mesh.num_faces = mesh.faces.len() as u32;
+    mesh.loaded = true;
+    mesh.processed = true;
+    Ok(mesh)
+}
+
+// hull of a mesh's vertices
+pub fn mesh_convex_hull(mesh: &Mesh) -> Result<Mesh, String> {
+    convex_hull(&mesh.vertices)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use crate::utils::mesh::Mesh;
+
+    use super::{convex_hull, mesh_convex_hull};
+
+    // volume and area of a closed, outward facing mesh
+    fn volume_and_area(mesh: &Mesh) -> (f32, f32) {
+        let (mut volume, mut area) = (0., 0.);
+        for face in mesh.faces.iter() {
+            let [a, b, c] = face.map(|v| mesh.vertices[v]);
+            let e1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let e2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+            let n = [
+                e1[1] * e2[2] - e1[2] * e2[1],
+                e1[2] * e2[0] - e1[0] * e2[2],
+                e1[0] * e2[1] - e1[1] * e2[0],
+            ];
+            volume += (a[0] * n[0] + a[1] * n[1] + a[2] * n[2]) / 6.;
+            area += (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.;
+        }
+        (volume, area)
+    }
+
+    // every point on or below every face, and each edge shared by exactly two faces
+    fn assert_valid_hull(hull: &Mesh, points: &[[f32; 3]]) {
+        for (face, normal) in hull.faces.iter().zip(hull.normals.iter()) {
+            let a = hull.vertices[face[0]];
+            for p in points {
+                let d = (p[0] - a[0]) * normal[0]
+                    + (p[1] - a[1]) * normal[1]
+                    + (p[2] - a[2]) * normal[2];
+                assert!(d < 1e-4, "point {:?} is {} above a hull face", p, d);
+            }
+        }
+        let mut edges = std::collections::HashMap::new();
+        for face in hull.faces.iter() {
+            for (u, v) in [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])] {
+                *edges.entry((u, v)).or_insert(0) += 1;
+            }
+        }
+        for (&(u, v), &count) in edges.iter() {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(v, u)), Some(&1));
+        }
+        // Euler characteristic of a closed triangulated sphere
+        assert_eq!(hull.vertices.len() + hull.faces.len() - edges.len() / 2, 2);
+    }
+
+    #[test]
+    fn known_solids_test() {
+        let tetrahedron = [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+        let hull = convex_hull(&tetrahedron).unwrap();
+        assert_eq!(hull.faces.len(), 4);
+        assert!((volume_and_area(&hull).0 - 1. / 6.).abs() < 1e-6);
+        assert_valid_hull(&hull, &tetrahedron);
+
+        let octahedron = [
+            [1., 0., 0.],
+            [-1., 0., 0.],
+            [0., 1., 0.],
+            [0., -1., 0.],
+            [0., 0., 1.],
+            [0., 0., -1.],
+        ];
+        let hull = convex_hull(&octahedron).unwrap();
+        assert_eq!(hull.faces.len(), 8);
+        assert!((volume_and_area(&hull).0 - 4. / 3.).abs() < 1e-6);
+        assert_valid_hull(&hull, &octahedron);
+
+        // cube corners plus duplicates, face centers, edge midpoints and inner points
+        let mut rng = rand::thread_rng();
+        let mut cube = vec![];
+        for i in 0..8 {
+            cube.push([(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32]);
+        }
+        cube.extend_from_slice(&cube.clone());
+        for k in 0..3 {
+            for side in [0., 1.] {
+                let mut center = [0.5; 3];
+                center[k] = side;
+                cube.push(center);
+                let mut midpoint = [side; 3];
+                midpoint[k] = 0.5;
+                cube.push(midpoint);
+            }
+        }
+        for _ in 0..100 {
+            cube.push([rng.gen(), rng.gen(), rng.gen()]);
+        }
+        let hull = convex_hull(&cube).unwrap();
+        assert_eq!(hull.vertices.len(), 8);
+        assert_eq!(hull.faces.len(), 12);
+        let (volume, area) = volume_and_area(&hull);
+        assert!((volume - 1.).abs() < 1e-6);
+        assert!((area - 6.).abs() < 1e-5);
+        assert_valid_hull(&hull, &cube);
+    }
+
+    #[test]
+    fn degenerate_input_test() {
+        assert!(convex_hull(&[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]).is_err());
+        assert!(convex_hull(&[[1., 1., 1.]; 10]).is_err());
+        let collinear: Vec<[f32; 3]> = (0..10).map(|i| [i as f32, 2. * i as f32, 0.]).collect();
+        assert!(convex_hull(&collinear).is_err());
+        let coplanar: Vec<[f32; 3]> = (0..20)
+            .map(|i| [(i % 5) as f32, (i / 5) as f32, 3.])
+            .collect();
+        assert!(convex_hull(&coplanar).is_err());
+        assert!(
+            convex_hull(&[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.], [0., 0., f32::NAN]]).is_err()
+        );
+        // a lattice with every point twice has many coplanar and duplicate points, its hull is
+        // still the cube around it
+        let lattice: Vec<[f32; 3]> = (0..2000)
+            .map(|i| [(i % 10) as f32, (i / 10 % 10) as f32, (i / 100 % 10) as f32])
+            .collect();
+        let hull = convex_hull(&lattice).unwrap();
+        assert_valid_hull(&hull, &lattice);
+        assert!((volume_and_area(&hull).0 - 729.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn point_cloud_hull_test() {
+        // points on a sphere are (almost) all on the hull, only near coplanar ones may be dropped
+        let mut rng = rand::thread_rng();
+        let sphere: Vec<[f32; 3]> = (0..500)
+            .map(|_| {
+                let p: [f32; 3] = [
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                ];
+                let l = (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+                [p[0] / l, p[1] / l, p[2] / l]
+            })
+            .collect();
+        let hull = convex_hull(&sphere).unwrap();
+        assert!(hull.vertices.len() > sphere.len() * 9 / 10);
+        assert_valid_hull(&hull, &sphere);
+
+        // two boxes apart are wrapped by the box spanning both
+        let mesh = Mesh::merge(&[
+            Mesh::cuboid([0., 0., 0.], [1., 1., 1.]),
+            Mesh::cuboid([2., 0., 0.], [3., 1., 1.]),
+        ]);
+        let hull = mesh_convex_hull(&mesh).unwrap();
+        assert_valid_hull(&hull, &mesh.vertices);
+        let (volume, area) = volume_and_area(&hull);
+        assert!((volume - 3.).abs() < 1e-5);
+        assert!((area - 14.).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// linear RGB pixels, top row first
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    pub fn get(&self, x: u32, y: u32) -> [f32; 3] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+// writes tightly packed 8 bit RGBA pixels, top row first, as a binary PPM. Alpha is dropped
+pub fn write_ppm(rgba: &[u8], width: u32, height: u32, file_path: &str) -> io::Result<()> {
+    if rgba.len() != 4 * width as usize * height as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "pixel data does not match the image size",
+        ));
+    }
+    let mut file = BufWriter::new(File::create(file_path)?);
+    write!(file, "P6\n{} {}\n255\n", width, height)?;
+    for pixel in rgba.chunks_exact(4) {
+        file.write_all(&pixel[..3])?;
+    }
+    file.flush()
+}
+
+// reads a Radiance .hdr, or an OpenEXR file when built with the exr feature
+pub fn read_environment(file_path: &str) -> Result<HdrImage, String> {
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("hdr") | Some("pic") => read_hdr(file_path),
+        #[cfg(feature = "exr")]
+        Some("exr") => read_exr(file_path),
+        _ => Err(format!("{}: unsupported environment format", file_path)),
+    }
+}
+
+// shared exponent color of a Radiance pixel
+fn rgbe_to_rgb(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0., 0., 0.];
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    [0, 1, 2].map(|k| (rgbe[k] as f32 + 0.5) * scale)
+}
+
+fn rgb_to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // max = m * 2^exponent with m in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    if max / 2f32.powi(exponent) >= 1. {
+        exponent += 1;
+    }
+    let scale = 256. / 2f32.powi(exponent);
+    let [r, g, b] = rgb.map(|c| (c * scale).clamp(0., 255.) as u8);
+    [r, g, b, (exponent + 128) as u8]
+}
+
+// larger than any texture a GPU can bind
+pub const MAX_HDR_SIZE: u32 = 1 << 15;
+
+// Radiance RGBE image, flat or with run length encoded scanlines, in the usual -Y +X orientation
+pub fn read_hdr(file_path: &str) -> Result<HdrImage, String> {
+    let bytes = std::fs::read(file_path).map_err(|e| e.to_string())?;
+    let error = |what: &str| format!("{}: {}", file_path, what);
+    if !bytes.starts_with(b"#?") {
+        return Err(error("not a Radiance file"));
+    }
+
+    // header lines up to an empty one, then the resolution line
+    let mut position = 0;
+    let next_line = |position: &mut usize| -> Result<String, String> {
+        let end = bytes[*position..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| error("truncated header"))?;
+        let line = String::from_utf8_lossy(&bytes[*position..*position + end]).to_string();
+        *position += end + 1;
+        Ok(line)
+    };
+    loop {
+        let line = next_line(&mut position)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(error("only RGBE pixels are supported"));
+            }
+        }
+    }
+    let resolution = next_line(&mut position)?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match fields[..] {
+        ["-Y", height, "+X", width] => (
+            height.parse::<u32>().map_err(|_| error("invalid height"))?,
+            width.parse::<u32>().map_err(|_| error("invalid width"))?,
+        ),
+        _ => return Err(error("unsupported orientation")),
+    };
+
+    if width == 0 || height == 0 || width > MAX_HDR_SIZE || height > MAX_HDR_SIZE {
+        return Err(error("unsupported image size"));
+    }
+
+    // a flat pixel takes four bytes, an encoded scanline at least two per run of 127 bytes in each
+    // channel, so the file bounds the size before anything is allocated
+    let data = &bytes[position..];
+    let runs = width.div_ceil(127) as usize;
+    let min_scanline = (4 * width as usize).min(4 + 8 * runs);
+    if data.len() < min_scanline * height as usize {
+        return Err(error("truncated pixel data"));
+    }
+    let mut read = 0;
+    let mut take = |count: usize| -> Result<&[u8], String> {
+        let slice = data
+            .get(read..read + count)
+            .ok_or_else(|| error("truncated pixel data"))?;
+        read += count;
+        Ok(slice)
+    };
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        let start = take(4)?;
+        let encoded = (8..0x8000).contains(&width)
+            && start[0] == 2
+            && start[1] == 2
+            && ((start[2] as u32) << 8 | start[3] as u32) == width;
+        if encoded {
+            // each channel in turn, as runs of one repeated byte or of literal bytes
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width as usize {
+                    let count = take(1)?[0] as usize;
+                    let (run, count) = if count > 128 {
+                        (true, count - 128)
+                    } else {
+                        (false, count)
+                    };
+                    if count == 0 || x + count > width as usize {
+                        return Err(error("bad scanline run"));
+                    }
+                    if run {
+                        let value = take(1)?[0];
+                        for pixel in &mut scanline[x..x + count] {
+                            pixel[channel] = value;
+                        }
+                    } else {
+                        for (pixel, &value) in scanline[x..x + count].iter_mut().zip(take(count)?) {
+                            pixel[channel] = value;
+                        }
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            scanline[0].copy_from_slice(start);
+            for pixel in scanline.iter_mut().skip(1) {
+                pixel.copy_from_slice(take(4)?);
+            }
+        }
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_rgb(rgbe)));
+    }
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// flat Radiance RGBE image, readable by `read_hdr` and other tools
+pub fn write_hdr(image: &HdrImage, file_path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_path)?);
+    write!(
+        file,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        image.height, image.width
+    )?;
+    for &pixel in &image.pixels {
+        file.write_all(&rgb_to_rgbe(pixel))?;
+    }
+    file.flush()
+}
+
+#[cfg(feature = "exr")]
+pub fn read_exr(file_path: &str) -> Result<HdrImage, String> {
+    use exr::prelude::*;
+
+    let image = read_first_rgba_layer_from_file(
+        file_path,
+        |resolution, _| HdrImage {
+            width: resolution.width() as u32,
+            height: resolution.height() as u32,
+            pixels: vec![[0.; 3]; resolution.width() * resolution.height()],
+        },
+        |image: &mut HdrImage, position, (r, g, b, _): (f32, f32, f32, f32)| {
+            let index = position.y() * image.width as usize + position.x();
+            image.pixels[index] = [r, g, b];
+        },
+    )
+    .map_err(|e| format!("{}: {}", file_path, e))?;
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_hdr, rgb_to_rgbe, rgbe_to_rgb, write_hdr, write_ppm, HdrImage};
+
+    #[test]
+    fn ppm_test() {
+        let file_path = std::env::temp_dir().join("ppm_test.ppm");
+        let file_path = file_path.to_str().unwrap();
+        let rgba = [
+            255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 10, 20, 30, 0,
+        ];
+        write_ppm(&rgba, 2, 2, file_path).unwrap();
+        let bytes = std::fs::read(file_path).unwrap();
+        assert_eq!(&bytes[..11], b"P6\n2 2\n255\n");
+        assert_eq!(&bytes[11..], &[255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 20, 30]);
+        assert!(write_ppm(&rgba, 3, 2, file_path).is_err());
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn hdr_test() {
+        // shared exponents keep about 1% precision over a huge range
+        for value in [1e-6, 0.01, 0.5, 1., 3.7, 1000., 65_000.] {
+            let rgb = rgbe_to_rgb(rgb_to_rgbe([value, value / 2., value / 10.]));
+            assert!(
+                (rgb[0] - value).abs() <= 0.01 * value,
+                "{} gives {:?}",
+                value,
+                rgb
+            );
+            assert!((rgb[1] - value / 2.).abs() <= 0.01 * value);
+        }
+        assert_eq!(rgbe_to_rgb(rgb_to_rgbe([0., 0., 0.])), [0., 0., 0.]);
+
+        let file_path = std::env::temp_dir().join("hdr_test.hdr");
+        let file_path = file_path.to_str().unwrap();
+        let image = HdrImage {
+            width: 3,
+            height: 2,
+            pixels: vec![
+                [1., 2., 4.],
+                [0., 0., 0.],
+                [0.25, 0.5, 0.125],
+                [10., 0., 1.],
+                [7., 7., 7.],
+                [0.5, 0.5, 0.5],
+            ],
+        };
+        write_hdr(&image, file_path).unwrap();
+        let read = read_hdr(file_path).unwrap();
+        assert_eq!((read.width, read.height), (3, 2));
+        for (a, b) in read.pixels.iter().zip(&image.pixels) {
+            for k in 0..3 {
+                assert!((a[k] - b[k]).abs() <= 0.01 * b[0].max(b[1]).max(b[2]));
+            }
+        }
+
+        // run length encoded scanlines: 8 pixels, red runs, green literals, blue and exponent runs
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        bytes.extend([2, 2, 0, 8]);
+        bytes.extend([128 + 8, 128]);
+        bytes.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        bytes.extend([128 + 4, 64, 128 + 4, 0]);
+        bytes.extend([128 + 8, 129]);
+        std::fs::write(file_path, &bytes).unwrap();
+        let read = read_hdr(file_path).unwrap();
+        assert_eq!(read.get(0, 0), rgbe_to_rgb([128, 0, 64, 129]));
+        assert_eq!(read.get(7, 0), rgbe_to_rgb([128, 112, 0, 129]));
+
+        std::fs::write(file_path, b"#?RADIANCE\n\n-Y 4 +X 4\n").unwrap();
+        assert!(read_hdr(file_path).is_err());
+        // empty and huge sizes fail before any pixel is read
+        for resolution in [
+            "-Y 1 +X 0",
+            "-Y 0 +X 1",
+            "-Y 4000000 +X 4000000",
+            "-Y 30000 +X 30000",
+        ] {
+            let header = format!("#?RADIANCE\n\n{}\n", resolution);
+            std::fs::write(file_path, [header.as_bytes(), &[2, 2, 0, 1]].concat()).unwrap();
+            assert!(read_hdr(file_path).is_err());
+        }
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[cfg(feature = "exr")]
+    #[test]
+    fn exr_test() {
+        let file_path = std::env::temp_dir().join("exr_test.exr");
+        let file_path = file_path.to_str().unwrap();
+        exr::prelude::write_rgb_file(file_path, 4, 2, |x, y| (x as f32, y as f32, 0.5)).unwrap();
+        let image = super::read_environment(file_path).unwrap();
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(image.get(3, 1), [3., 1., 0.5]);
+        std::fs::remove_file(file_path).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+use std::ops::Mul;
+
+use crate::utils::quaternion::Quat;
+use crate::utils::vector::Vec3;
+
+// column-major like wgsl, `cols[c][r]` is row r of column c
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    pub cols: [[f32; 3]; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub cols: [[f32; 4]; 4],
+}
+
+impl Default for Mat3 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mat3 {
+    pub const IDENTITY: Mat3 = Mat3 {
+        cols: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+    };
+
+    pub fn from_cols(x: &Vec3<f32>, y: &Vec3<f32>, z: &Vec3<f32>) -> Self {
+        Self {
+            cols: [x.to_array(), y.to_array(), z.to_array()],
+        }
+    }
+
+    pub fn from_scale(scale: &Vec3<f32>) -> Self {
+        let mut m = Self::IDENTITY;
+        for k in 0..3 {
+            m.cols[k][k] = scale.v[k];
+        }
+        m
+    }
+
+    pub fn from_quat(q: &Quat) -> Self {
+        let [x, y, z, w] = q.normalize().to_array();
+        Self {
+            cols: [
+                [
+                    1. - 2. * (y * y + z * z),
+                    2. * (x * y + w * z),
+                    2. * (x * z - w * y),
+                ],
+                [
+                    2. * (x * y - w * z),
+                    1. - 2. * (x * x + z * z),
+                    2. * (y * z + w * x),
+                ],
+                [
+                    2. * (x * z + w * y),
+                    2. * (y * z - w * x),
+                    1. - 2. * (x * x + y * y),
+                ],
+            ],
+        }
+    }
+
+    // upper left 3x3 block, the linear part of an affine transform
+    pub fn from_mat4(m: &Mat4) -> Self {
+        Self {
+            cols: [0, 1, 2].map(|c| [m.cols[c][0], m.cols[c][1], m.cols[c][2]]),
+        }
+    }
+
+    pub fn row(&self, r: usize) -> [f32; 3] {
+        [self.cols[0][r], self.cols[1][r], self.cols[2][r]]
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self {
+            cols: [0, 1, 2].map(|r| self.row(r)),
+        }
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let [a, b, c] = self.cols;
+        a[0] * (b[1] * c[2] - b[2] * c[1]) - b[0] * (a[1] * c[2] - a[2] * c[1])
+            + c[0] * (a[1] * b[2] - a[2] * b[1])
+    }
+
+    // None for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det == 0. || !det.is_finite() {
+            return None;
+        }
+        let [a, b, c] = self.cols.map(|col| Vec3::new(col[0], col[1], col[2]));
+        // rows of the inverse are the cross products of the columns
+        let rows = [b.cross(&c), c.cross(&a), a.cross(&b)];
+        let mut inverse = Self::from_cols(&rows[0], &rows[1], &rows[2]).transpose();
+        for col in inverse.cols.iter_mut() {
+            for value in col.iter_mut() {
+                *value /= det;
+            }
+        }
+        Some(inverse)
+    }
+
+    pub fn mul_vec(&self, v: &Vec3<f32>) -> Vec3<f32> {
+        let [x, y, z] = [0, 1, 2].map(|r| {
+            self.cols[0][r] * v.v[0] + self.cols[1][r] * v.v[1] + self.cols[2][r] * v.v[2]
+        });
+        Vec3::new(x, y, z)
+    }
+
+    // wgsl mat3x3 columns are padded to 16 bytes
+    pub fn to_gpu(&self) -> [[f32; 4]; 3] {
+        self.cols.map(|c| [c[0], c[1], c[2], 0.])
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Mat3;
+    fn mul(self, rhs: Mat3) -> Mat3 {
+        Mat3 {
+            cols: rhs
+                .cols
+                .map(|c| self.mul_vec(&Vec3::new(c[0], c[1], c[2])).to_array()),
+        }
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        cols: [
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ],
+    };
+
+    pub fn from_cols_array(cols: [[f32; 4]; 4]) -> Self {
+        Self { cols }
+    }
+
+    pub fn from_translation(t: &Vec3<f32>) -> Self {
+        let mut m = Self::IDENTITY;
+        m.cols[3] = [t.v[0], t.v[1], t.v[2], 1.];
+        m
+    }
+
+    pub fn from_scale(scale: &Vec3<f32>) -> Self {
+        Self::from_mat3(&Mat3::from_scale(scale))
+    }
+
+    pub fn from_quat(q: &Quat) -> Self {
+        Self::from_mat3(&Mat3::from_quat(q))
+    }
+
+    pub fn from_mat3(m: &Mat3) -> Self {
+        let mut out = Self::IDENTITY;
+        for c in 0..3 {
+            out.cols[c][..3].copy_from_slice(&m.cols[c]);
+        }
+        out
+    }
+
+    // translation * rotation * scale
+    pub fn from_scale_rotation_translation(
+        scale: &Vec3<f32>,
+        rotation: &Quat,
+        translation: &Vec3<f32>,
+    ) -> Self {
+        let mut m = Self::from_mat3(&(Mat3::from_quat(rotation) * Mat3::from_scale(scale)));
+        m.cols[3] = [translation.v[0], translation.v[1], translation.v[2], 1.];
+        m
+    }
+
+    // right handed view matrix looking from `eye` at `target`, the camera looks down -z
+    pub fn look_at(eye: &Vec3<f32>, target: &Vec3<f32>, up: &Vec3<f32>) -> Self {
+        let f = (target - eye).normalize();
+        let s = f.cross(up).normalize();
+        let u = s.cross(&f);
+        Self {
+            cols: [
+                [s.v[0], u.v[0], -f.v[0], 0.],
+                [s.v[1], u.v[1], -f.v[1], 0.],
+                [s.v[2], u.v[2], -f.v[2], 0.],
+                [-s.dot(eye), -u.dot(eye), f.dot(eye), 1.],
+            ],
+        }
+    }
+
+    // right handed perspective projection to wgpu's 0..1 depth range, `fov_y` in radians
+    pub fn perspective(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Self {
+        let f = 1. / (fov_y * 0.5).tan();
+        let range = far / (near - far);
+        Self {
+            cols: [
+                [f / aspect_ratio, 0., 0., 0.],
+                [0., f, 0., 0.],
+                [0., 0., range, -1.],
+                [0., 0., range * near, 0.],
+            ],
+        }
+    }
+
+    pub fn row(&self, r: usize) -> [f32; 4] {
+        [
+            self.cols[0][r],
+            self.cols[1][r],
+            self.cols[2][r],
+            self.cols[3][r],
+        ]
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self {
+            cols: [0, 1, 2, 3].map(|r| self.row(r)),
+        }
+    }
+
+    pub fn translation(&self) -> Vec3<f32> {
+        Vec3::new(self.cols[3][0], self.cols[3][1], self.cols[3][2])
+    }
+
+    // 2x2 sub determinants of the first two and last two columns, shared by the determinant and
+    // the inverse
+    fn minors(&self) -> ([f32; 6], [f32; 6]) {
+        let [a, b, c, d] = self.cols;
+        (
+            [
+                a[0] * b[1] - a[1] * b[0],
+                a[0] * b[2] - a[2] * b[0],
+                a[0] * b[3] - a[3] * b[0],
+                a[1] * b[2] - a[2] * b[1],
+                a[1] * b[3] - a[3] * b[1],
+                a[2] * b[3] - a[3] * b[2],
+            ],
+            [
+                c[0] * d[1] - c[1] * d[0],
+                c[0] * d[2] - c[2] * d[0],
+                c[0] * d[3] - c[3] * d[0],
+                c[1] * d[2] - c[2] * d[1],
+                c[1] * d[3] - c[3] * d[1],
+                c[2] * d[3] - c[3] * d[2],
+            ],
+        )
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let (s, t) = self.minors();
+        s[0] * t[5] - s[1] * t[4] + s[2] * t[3] + s[3] * t[2] - s[4] * t[1] + s[5] * t[0]
+    }
+
+    // None for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let (s, t) = self.minors();
+        let det = s[0] * t[5] - s[1] * t[4] + s[2] * t[3] + s[3] * t[2] - s[4] * t[1] + s[5] * t[0];
+        if det == 0. || !det.is_finite() {
+            return None;
+        }
+        let [a, b, c, d] = self.cols;
+        // adjugate by the Laplace expansion over the column pairs
+        let rows = [
+            [
+                b[1] * t[5] - b[2] * t[4] + b[3] * t[3],
+                -a[1] * t[5] + a[2] * t[4] - a[3] * t[3],
+                d[1] * s[5] - d[2] * s[4] + d[3] * s[3],
+                -c[1] * s[5] + c[2] * s[4] - c[3] * s[3],
+            ],
+            [
+                -b[0] * t[5] + b[2] * t[2] - b[3] * t[1],
+                a[0] * t[5] - a[2] * t[2] + a[3] * t[1],
+                -d[0] * s[5] + d[2] * s[2] - d[3] * s[1],
+                c[0] * s[5] - c[2] * s[2] + c[3] * s[1],
+            ],
+            [
+                b[0] * t[4] - b[1] * t[2] + b[3] * t[0],
+                -a[0] * t[4] + a[1] * t[2] - a[3] * t[0],
+                d[0] * s[4] - d[1] * s[2] + d[3] * s[0],
+                -c[0] * s[4] + c[1] * s[2] - c[3] * s[0],
+            ],
+            [
+                -b[0] * t[3] + b[1] * t[1] - b[2] * t[0],
+                a[0] * t[3] - a[1] * t[1] + a[2] * t[0],
+                -d[0] * s[3] + d[1] * s[1] - d[2] * s[0],
+                c[0] * s[3] - c[1] * s[1] + c[2] * s[0],
+            ],
+        ];
+        // rows[c] holds the inverse's column c
+        Some(Self {
+            cols: rows.map(|col| col.map(|value| value / det)),
+        })
+    }
+
+    pub fn mul_vec4(&self, v: [f32; 4]) -> [f32; 4] {
+        [0, 1, 2, 3].map(|r| {
+            self.cols[0][r] * v[0]
+                + self.cols[1][r] * v[1]
+                + self.cols[2][r] * v[2]
+                + self.cols[3][r] * v[3]
+        })
+    }
+
+    // affine point transform, the projective row is ignored
+    pub fn transform_point(&self, p: &Vec3<f32>) -> Vec3<f32> {
+        let [x, y, z, _] = self.mul_vec4([p.v[0], p.v[1], p.v[2], 1.]);
+        Vec3::new(x, y, z)
+    }
+
+    pub fn transform_vector(&self, v: &Vec3<f32>) -> Vec3<f32> {
+        let [x, y, z, _] = self.mul_vec4([v.v[0], v.v[1], v.v[2], 0.]);
+        Vec3::new(x, y, z)
+    }
+
+    // point transform with the perspective divide
+    pub fn project_point(&self, p: &Vec3<f32>) -> Vec3<f32> {
+        let [x, y, z, w] = self.mul_vec4([p.v[0], p.v[1], p.v[2], 1.]);
+        Vec3::new(x / w, y / w, z / w)
+    }
+
+    // normals go through the inverse transpose so they stay perpendicular under non-uniform scale
+    pub fn transform_normal(&self, n: &Vec3<f32>) -> Vec3<f32> {
+        let linear = Mat3::from_mat4(self);
+        match linear.inverse() {
+            Some(inverse) => inverse.transpose().mul_vec(n).normalize(),
+            None => *n,
+        }
+    }
+
+    // bounds in the repo's [min x, min y, min z, max x, max y, max z] layout, enclosing the
+    // transformed box
+    pub fn transform_aabb(&self, bounds: &[f32; 6]) -> [f32; 6] {
+        // Arvo: per axis, the extremes come from the smaller and larger product of each entry
+        let mut out = [
+            self.cols[3][0],
+            self.cols[3][1],
+            self.cols[3][2],
+            self.cols[3][0],
+            self.cols[3][1],
+            self.cols[3][2],
+        ];
+        for r in 0..3 {
+            for c in 0..3 {
+                let a = self.cols[c][r] * bounds[c];
+                let b = self.cols[c][r] * bounds[c + 3];
+                out[r] += a.min(b);
+                out[r + 3] += a.max(b);
+            }
+        }
+        out
+    }
+
+    pub fn to_cols_array(&self) -> [[f32; 4]; 4] {
+        self.cols
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        Mat4 {
+            cols: rhs.cols.map(|c| self.mul_vec4(c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use crate::utils::{quaternion::Quat, vector::Vec3};
+
+    use super::{Mat3, Mat4};
+
+    fn close(a: &Mat4, b: &Mat4) -> bool {
+        a.cols
+            .iter()
+            .flatten()
+            .zip(b.cols.iter().flatten())
+            .all(|(x, y)| (x - y).abs() < 1e-5)
+    }
+
+    fn close_vec(a: &Vec3<f32>, b: &Vec3<f32>) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn matrix_test() {
+        let rotation = Quat::from_axis_angle(&Vec3::new(1., 2., 3.), 0.7);
+        let m = Mat4::from_scale_rotation_translation(
+            &Vec3::new(2., 0.5, 3.),
+            &rotation,
+            &Vec3::new(1., -2., 4.),
+        );
+        let inverse = m.inverse().unwrap();
+        assert!(close(&(m * inverse), &Mat4::IDENTITY));
+        assert!(close(&(inverse * m), &Mat4::IDENTITY));
+        assert!((m.determinant() - 3.).abs() < 1e-5);
+        assert!(close(&m.transpose().transpose(), &m));
+        assert!(Mat4::from_scale(&Vec3::new(1., 0., 1.)).inverse().is_none());
+
+        let linear = Mat3::from_mat4(&m);
+        assert!((linear.determinant() - 3.).abs() < 1e-5);
+        let product = linear * linear.inverse().unwrap();
+        for c in 0..3 {
+            for r in 0..3 {
+                let expected = if c == r { 1. } else { 0. };
+                assert!((product.cols[c][r] - expected).abs() < 1e-5);
+            }
+        }
+
+        let p = Vec3::new(0.3, -0.2, 1.5);
+        assert!(close_vec(
+            &inverse.transform_point(&m.transform_point(&p)),
+            &p
+        ));
+        assert!(close_vec(
+            &Mat4::from_translation(&Vec3::new(1., 2., 3.)).transform_vector(&p),
+            &p
+        ));
+
+        // a normal stays perpendicular to a transformed tangent
+        let tangent = Vec3::new(1., 1., 0.);
+        let normal = Vec3::new(1., -1., 0.5);
+        let moved = m.transform_normal(&normal);
+        assert!(moved.dot(&m.transform_vector(&tangent)).abs() < 1e-5);
+
+        // quarter turn about z maps x to y
+        let turn = Mat4::from_quat(&Quat::from_axis_angle(&Vec3::new(0., 0., 1.), PI / 2.));
+        assert!(close_vec(
+            &turn.transform_point(&Vec3::new(1., 0., 0.)),
+            &Vec3::new(0., 1., 0.)
+        ));
+        let bounds = turn.transform_aabb(&[0., 0., 0., 2., 1., 1.]);
+        let expected = [-1., 0., 0., 0., 2., 1.];
+        assert!(bounds
+            .iter()
+            .zip(expected)
+            .all(|(a, b)| (a - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn camera_matrix_test() {
+        let eye = Vec3::new(0., 0., 5.);
+        let view = Mat4::look_at(&eye, &Vec3::new(0., 0., 0.), &Vec3::new(0., 1., 0.));
+        assert!(close_vec(
+            &view.transform_point(&Vec3::new(0., 0., 0.)),
+            &Vec3::new(0., 0., -5.)
+        ));
+        let projection = Mat4::perspective(PI / 2., 2., 1., 100.);
+        let near = projection.project_point(&Vec3::new(0., 0., -1.));
+        let far = projection.project_point(&Vec3::new(0., 0., -100.));
+        assert!(near.v[2].abs() < 1e-5 && (far.v[2] - 1.).abs() < 1e-5);
+        // the top edge of a 90 degree view
+        let top = projection.project_point(&Vec3::new(0., 1., -1.));
+        assert!((top.v[1] - 1.).abs() < 1e-5);
+    }
+}
@@ -105,6 +105,101 @@ impl Mesh {
         }
     }
 
+    // unindexed mesh in the same layout `load_mesh` produces, normals follow the winding
+    pub fn from_triangles(triangles: &[[[f32; 3]; 3]]) -> Self {
+        let mut mesh = Mesh::new(triangles.len());
+        for (i, tri) in triangles.iter().enumerate() {
+            let e1 = [
+                tri[1][0] - tri[0][0],
+                tri[1][1] - tri[0][1],
+                tri[1][2] - tri[0][2],
+            ];
+            let e2 = [
+                tri[2][0] - tri[0][0],
+                tri[2][1] - tri[0][1],
+                tri[2][2] - tri[0][2],
+            ];
+            let n = [
+                e1[1] * e2[2] - e1[2] * e2[1],
+                e1[2] * e2[0] - e1[0] * e2[2],
+                e1[0] * e2[1] - e1[1] * e2[0],
+            ];
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt().max(f32::MIN_POSITIVE);
+            mesh.normals[i] = [n[0] / length, n[1] / length, n[2] / length];
+            mesh.vertices[3 * i] = tri[0];
+            mesh.vertices[3 * i + 1] = tri[1];
+            mesh.vertices[3 * i + 2] = tri[2];
+            mesh.faces[i] = [3 * i, 3 * i + 1, 3 * i + 2];
+        }
+        mesh.loaded = true;
+        mesh
+    }
+
+    // axis aligned box with outward facing triangles
+    pub fn cuboid(min: [f32; 3], max: [f32; 3]) -> Self {
+        let corner = |i: usize| {
+            [
+                if i & 1 == 0 { min[0] } else { max[0] },
+                if i & 2 == 0 { min[1] } else { max[1] },
+                if i & 4 == 0 { min[2] } else { max[2] },
+            ]
+        };
+        // counter-clockwise quads seen from outside
+        let quads = [
+            [0, 4, 6, 2], // -x
+            [1, 3, 7, 5], // +x
+            [0, 1, 5, 4], // -y
+            [2, 6, 7, 3], // +y
+            [0, 2, 3, 1], // -z
+            [4, 5, 7, 6], // +z
+        ];
+        let triangles: Vec<[[f32; 3]; 3]> = quads
+            .iter()
+            .flat_map(|q| {
+                [
+                    [corner(q[0]), corner(q[1]), corner(q[2])],
+                    [corner(q[0]), corner(q[2]), corner(q[3])],
+                ]
+            })
+            .collect();
+        Self::from_triangles(&triangles)
+    }
+
+    // eight outward facing triangles with their corners `radius` along the axes from `center`
+    pub fn octahedron(center: [f32; 3], radius: f32) -> Self {
+        let mut triangles = vec![];
+        for signs in 0..8 {
+            let s = [0, 1, 2].map(|k| if signs & (1 << k) == 0 { 1. } else { -1. });
+            let [mut x, mut y, mut z] = [center; 3];
+            x[0] += s[0] * radius;
+            y[1] += s[1] * radius;
+            z[2] += s[2] * radius;
+            // an odd number of mirrored axes reverses the winding
+            if s[0] * s[1] * s[2] > 0. {
+                triangles.push([x, y, z]);
+            } else {
+                triangles.push([x, z, y]);
+            }
+        }
+        Self::from_triangles(&triangles)
+    }
+
+    // one mesh holding the faces of all `meshes`, e.g. a scene of several boxes
+    pub fn merge(meshes: &[Mesh]) -> Self {
+        let mut merged = Mesh::e_new();
+        for mesh in meshes {
+            let offset = merged.vertices.len();
+            merged.vertices.extend(&mesh.vertices);
+            merged.normals.extend(&mesh.normals);
+            merged
+                .faces
+                .extend(mesh.faces.iter().map(|f| f.map(|v| v + offset)));
+        }
+        merged.num_faces = merged.faces.len() as u32;
+        merged.loaded = true;
+        merged
+    }
+
     pub fn write_stl_file(&self, file_path: &str) -> io::Result<()> {
         let mut file = File::create(file_path)?;
 
@@ -1,6 +1,20 @@
 pub mod bvh;
+pub mod bvh4;
+pub mod compare;
+pub mod distance;
+pub mod hull;
+pub mod image;
+pub mod matrix;
 pub mod mesh;
+pub mod polyline;
+pub mod quaternion;
+pub mod random;
+pub mod ray;
+pub mod simd;
+pub mod slice;
+pub mod transform;
 pub mod vector;
+pub mod voxel;
 
 use rand::Rng;
 use std::mem;
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct Polyline {
+    pub points: Vec<[f32; 3]>,
+    pub closed: bool, // the last point connects back to the first
+}
+
+impl Polyline {
+    pub fn length(&self) -> f32 {
+        let mut length: f32 = self.points.windows(2).map(|w| distance(&w[0], &w[1])).sum();
+        if self.closed && self.points.len() > 2 {
+            length += distance(&self.points[self.points.len() - 1], &self.points[0]);
+        }
+        length
+    }
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn cell(p: &[f32; 3], tolerance: f32) -> [i64; 3] {
+    [
+        (p[0] / tolerance).floor() as i64,
+        (p[1] / tolerance).floor() as i64,
+        (p[2] / tolerance).floor() as i64,
+    ]
+}
+
+// merges endpoints closer than `tolerance` and returns the vertex id of every endpoint
+fn weld(segments: &[[[f32; 3]; 2]], tolerance: f32) -> (Vec<[f32; 3]>, Vec<[usize; 2]>) {
+    let mut vertices: Vec<[f32; 3]> = vec![];
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut ids = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let mut pair = [0; 2];
+        for (id, p) in pair.iter_mut().zip(segment) {
+            let c = cell(p, tolerance);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let Some(candidates) = grid.get(&[c[0] + dx, c[1] + dy, c[2] + dz]) else {
+                            continue;
+                        };
+                        if let Some(&v) = candidates
+                            .iter()
+                            .find(|&&v| distance(&vertices[v], p) <= tolerance)
+                        {
+                            found = Some(v);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            *id = found.unwrap_or_else(|| {
+                vertices.push(*p);
+                grid.entry(c).or_default().push(vertices.len() - 1);
+                vertices.len() - 1
+            });
+        }
+        ids.push(pair);
+    }
+    (vertices, ids)
+}
+
+// joins unordered segments that share endpoints (within `tolerance`) into polylines. Chains
+// start at open ends or branch points, whatever is left over forms closed loops
+pub fn chain_segments(segments: &[[[f32; 3]; 2]], tolerance: f32) -> Vec<Polyline> {
+    let (vertices, ids) = weld(segments, tolerance);
+    let mut edges: Vec<[usize; 2]> = ids
+        .into_iter()
+        .filter(|e| e[0] != e[1])
+        .map(|e| [e[0].min(e[1]), e[0].max(e[1])])
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+
+    let mut adjacency: Vec<Vec<usize>> = vec![vec![]; vertices.len()];
+    for (i, e) in edges.iter().enumerate() {
+        adjacency[e[0]].push(i);
+        adjacency[e[1]].push(i);
+    }
+    let mut used = vec![false; edges.len()];
+    let walk = |start: usize, used: &mut Vec<bool>| {
+        let mut points = vec![vertices[start]];
+        let mut current = start;
+        while let Some(&edge) = adjacency[current].iter().find(|&&e| !used[e]) {
+            used[edge] = true;
+            current = if edges[edge][0] == current {
+                edges[edge][1]
+            } else {
+                edges[edge][0]
+            };
+            points.push(vertices[current]);
+            if current == start {
+                break;
+            }
+        }
+        let closed = points.len() > 3 && current == start;
+        if closed {
+            points.pop();
+        }
+        Polyline { points, closed }
+    };
+
+    let mut polylines = vec![];
+    let open_ends = (0..vertices.len()).filter(|&v| adjacency[v].len() != 2);
+    for v in open_ends.chain(0..vertices.len()) {
+        while adjacency[v].iter().any(|&e| !used[e]) {
+            polylines.push(walk(v, &mut used));
+        }
+    }
+    polylines
+}
+
+#[cfg(test)]
+mod test {
+    use super::chain_segments;
+
+    #[test]
+    fn chain_segments_test() {
+        // a unit square given out of order with flipped segments, plus a separate open line
+        let segments = [
+            [[1., 0., 0.], [1., 1., 0.]],
+            [[0., 0., 0.], [1., 0., 0.]],
+            [[0., 1., 0.], [1., 1.000001, 0.]],
+            [[0., 1., 0.], [0., 0., 0.]],
+            [[5., 0., 0.], [6., 0., 0.]],
+            [[6., 0., 0.], [6., 2., 0.]],
+        ];
+        let mut polylines = chain_segments(&segments, 1e-4);
+        polylines.sort_by_key(|p| p.closed);
+        assert_eq!(polylines.len(), 2);
+        assert!(!polylines[0].closed);
+        assert_eq!(polylines[0].points.len(), 3);
+        assert!((polylines[0].length() - 3.).abs() < 1e-5);
+        assert!(polylines[1].closed);
+        assert_eq!(polylines[1].points.len(), 4);
+        assert!((polylines[1].length() - 4.).abs() < 1e-5);
+    }
+}
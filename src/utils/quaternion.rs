@@ -0,0 +1,237 @@
+use std::ops::Mul;
+
+use crate::utils::matrix::Mat3;
+use crate::utils::vector::Vec3;
+
+// rotation quaternion x i + y j + z k + w
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+        w: 1.,
+    };
+
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+
+    // counter-clockwise rotation by `angle` radians around `axis`, which does not need to be unit
+    pub fn from_axis_angle(axis: &Vec3<f32>, angle: f32) -> Self {
+        let axis = axis.normalize();
+        let (sin, cos) = (angle * 0.5).sin_cos();
+        Self::new(axis.v[0] * sin, axis.v[1] * sin, axis.v[2] * sin, cos)
+    }
+
+    // rotation about x, then y, then z, in radians
+    pub fn from_euler(x: f32, y: f32, z: f32) -> Self {
+        Self::from_axis_angle(&Vec3::new(0., 0., 1.), z)
+            * Self::from_axis_angle(&Vec3::new(0., 1., 0.), y)
+            * Self::from_axis_angle(&Vec3::new(1., 0., 0.), x)
+    }
+
+    // shortest rotation taking direction `from` to direction `to`
+    pub fn from_rotation_arc(from: &Vec3<f32>, to: &Vec3<f32>) -> Self {
+        let (from, to) = (from.normalize(), to.normalize());
+        let d = from.dot(&to);
+        if d < -1. + 1e-6 {
+            // opposite directions, turn half way around any perpendicular axis
+            let mut axis = Vec3::new(1., 0., 0.).cross(&from);
+            if axis.squared_length() < 1e-6 {
+                axis = Vec3::new(0., 1., 0.).cross(&from);
+            }
+            return Self::from_axis_angle(&axis, std::f32::consts::PI);
+        }
+        let c = from.cross(&to);
+        Self::new(c.v[0], c.v[1], c.v[2], 1. + d).normalize()
+    }
+
+    // assumes `m` is a pure rotation
+    pub fn from_mat3(m: &Mat3) -> Self {
+        let [[m00, m10, m20], [m01, m11, m21], [m02, m12, m22]] = m.cols;
+        let trace = m00 + m11 + m22;
+        let q = if trace > 0. {
+            let s = (trace + 1.).sqrt() * 2.;
+            Self::new((m21 - m12) / s, (m02 - m20) / s, (m10 - m01) / s, 0.25 * s)
+        } else if m00 > m11 && m00 > m22 {
+            let s = (1. + m00 - m11 - m22).sqrt() * 2.;
+            Self::new(0.25 * s, (m01 + m10) / s, (m02 + m20) / s, (m21 - m12) / s)
+        } else if m11 > m22 {
+            let s = (1. + m11 - m00 - m22).sqrt() * 2.;
+            Self::new((m01 + m10) / s, 0.25 * s, (m12 + m21) / s, (m02 - m20) / s)
+        } else {
+            let s = (1. + m22 - m00 - m11).sqrt() * 2.;
+            Self::new((m02 + m20) / s, (m12 + m21) / s, 0.25 * s, (m10 - m01) / s)
+        };
+        q.normalize()
+    }
+
+    pub fn dot(&self, other: &Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Self {
+        let length = self.length();
+        if length == 0. {
+            return Self::IDENTITY;
+        }
+        Self::new(
+            self.x / length,
+            self.y / length,
+            self.z / length,
+            self.w / length,
+        )
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    pub fn inverse(&self) -> Self {
+        let squared = self.dot(self);
+        let c = self.conjugate();
+        Self::new(c.x / squared, c.y / squared, c.z / squared, c.w / squared)
+    }
+
+    pub fn rotate(&self, v: &Vec3<f32>) -> Vec3<f32> {
+        // v + 2 q x (q x v + w v), with q the vector part
+        let q = Vec3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.;
+        v + t * self.w + q.cross(&t)
+    }
+
+    // (axis, angle in radians), the axis is x when there is no rotation
+    pub fn to_axis_angle(&self) -> (Vec3<f32>, f32) {
+        let q = self.normalize();
+        let sin = (1. - q.w * q.w).max(0.).sqrt();
+        if sin < 1e-6 {
+            return (Vec3::new(1., 0., 0.), 0.);
+        }
+        (
+            Vec3::new(q.x / sin, q.y / sin, q.z / sin),
+            2. * q.w.clamp(-1., 1.).acos(),
+        )
+    }
+
+    // spherical interpolation along the shorter arc
+    pub fn slerp(&self, other: &Self, t: f32) -> Self {
+        let mut end = *other;
+        let mut d = self.dot(other);
+        if d < 0. {
+            end = Self::new(-end.x, -end.y, -end.z, -end.w);
+            d = -d;
+        }
+        let (a, b) = if d > 0.9995 {
+            // nearly the same rotation, a normalized lerp avoids dividing by a tiny sine
+            (1. - t, t)
+        } else {
+            let theta = d.acos();
+            let sin = theta.sin();
+            (((1. - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Self::new(
+            self.x * a + end.x * b,
+            self.y * a + end.y * b,
+            self.z * a + end.z * b,
+            self.w * a + end.w * b,
+        )
+        .normalize()
+    }
+
+    pub fn to_array(&self) -> [f32; 4] {
+        [self.x, self.y, self.z, self.w]
+    }
+}
+
+// applies rhs first, then self
+impl Mul for Quat {
+    type Output = Quat;
+    fn mul(self, rhs: Quat) -> Quat {
+        Quat::new(
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use crate::utils::{matrix::Mat3, vector::Vec3};
+
+    use super::Quat;
+
+    fn close_vec(a: &Vec3<f32>, b: &Vec3<f32>) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn quaternion_test() {
+        let q = Quat::from_axis_angle(&Vec3::new(0., 1., 0.), PI / 2.);
+        let v = Vec3::new(1., 0., 0.);
+        assert!(close_vec(&q.rotate(&v), &Vec3::new(0., 0., -1.)));
+        assert!(close_vec(&q.inverse().rotate(&q.rotate(&v)), &v));
+        assert!(close_vec(&Mat3::from_quat(&q).mul_vec(&v), &q.rotate(&v)));
+
+        // composition applies the right hand side first
+        let r = Quat::from_axis_angle(&Vec3::new(1., 0., 0.), 0.3);
+        let p = Vec3::new(0.2, -0.7, 1.1);
+        assert!(close_vec(&(q * r).rotate(&p), &q.rotate(&r.rotate(&p))));
+
+        let euler = Quat::from_euler(0.1, 0.2, 0.3);
+        let steps = Quat::from_axis_angle(&Vec3::new(0., 0., 1.), 0.3)
+            * Quat::from_axis_angle(&Vec3::new(0., 1., 0.), 0.2)
+            * Quat::from_axis_angle(&Vec3::new(1., 0., 0.), 0.1);
+        assert!(close_vec(&euler.rotate(&p), &steps.rotate(&p)));
+
+        // back and forth through matrices, q and -q are the same rotation
+        let m = Quat::from_mat3(&Mat3::from_quat(&euler));
+        assert!((m.dot(&euler).abs() - 1.).abs() < 1e-5);
+
+        let (axis, angle) = q.to_axis_angle();
+        assert!(close_vec(&axis, &Vec3::new(0., 1., 0.)));
+        assert!((angle - PI / 2.).abs() < 1e-5);
+
+        let arc = Quat::from_rotation_arc(&Vec3::new(0., 0., 2.), &Vec3::new(0., 3., 0.));
+        assert!(close_vec(
+            &arc.rotate(&Vec3::new(0., 0., 1.)),
+            &Vec3::new(0., 1., 0.)
+        ));
+        let flip = Quat::from_rotation_arc(&Vec3::new(1., 0., 0.), &Vec3::new(-1., 0., 0.));
+        assert!(close_vec(
+            &flip.rotate(&Vec3::new(1., 0., 0.)),
+            &Vec3::new(-1., 0., 0.)
+        ));
+
+        let half = Quat::IDENTITY.slerp(&q, 0.5);
+        assert!(close_vec(
+            &half.rotate(&v),
+            &Vec3::new((PI / 4.).cos(), 0., -(PI / 4.).sin())
+        ));
+        assert!(close_vec(
+            &Quat::IDENTITY.slerp(&q, 1.).rotate(&v),
+            &q.rotate(&v)
+        ));
+    }
+}
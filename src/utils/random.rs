@@ -0,0 +1,85 @@
+// PCG hash of a single word (Jarzynski and Olano, "Hash Functions for GPU Rendering"), the same
+// as `pcg_hash` in main.wgsl so CPU and GPU samples line up
+pub fn pcg_hash(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+// random number state for one sample of one pixel in one frame, see `rng_seed` in main.wgsl
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pcg {
+    pub state: u32,
+}
+
+impl Pcg {
+    pub fn new(pixel: [u32; 2], frame: u32, sample: u32) -> Self {
+        Self {
+            state: pcg_hash(pixel[0] ^ pcg_hash(pixel[1] ^ pcg_hash(frame ^ pcg_hash(sample)))),
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state = self.state.wrapping_mul(747796405).wrapping_add(2891336453);
+        let word = ((self.state >> ((self.state >> 28) + 4)) ^ self.state).wrapping_mul(277803737);
+        (word >> 22) ^ word
+    }
+
+    // uniform in [0, 1) from the top 24 bits, which an f32 holds exactly
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1. / 16777216.)
+    }
+
+    pub fn next_vec2(&mut self) -> [f32; 2] {
+        let a = self.next_f32();
+        [a, self.next_f32()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::{pcg_hash, Pcg};
+
+    #[test]
+    fn pcg_test() {
+        // reference values of the published hash
+        assert_eq!(pcg_hash(0), 129708002);
+        assert_eq!(pcg_hash(1), 2831084092);
+        assert_eq!(pcg_hash(12345), 4099845390);
+        let mut rng = Pcg::new([3, 7], 11, 2);
+        assert_eq!(rng.state, 229403909);
+        assert_eq!(rng.next_u32(), 3340069121);
+        assert_eq!(rng.next_u32(), 4074663564);
+
+        // neighbouring pixels, frames and samples get different streams
+        let first = |pixel, frame, sample| Pcg::new(pixel, frame, sample).next_u32();
+        assert_ne!(first([0, 0], 0, 0), first([1, 0], 0, 0));
+        assert_ne!(first([0, 0], 0, 0), first([0, 1], 0, 0));
+        assert_ne!(first([0, 0], 0, 0), first([0, 0], 1, 0));
+        assert_ne!(first([0, 0], 0, 0), first([0, 0], 0, 1));
+        assert_ne!(first([1, 2], 0, 0), first([2, 1], 0, 0));
+
+        // uniform floats: in range, mean and variance of U(0, 1), even histogram
+        let start = Instant::now();
+        let count = 1_000_000;
+        let mut buckets = [0u32; 16];
+        let (mut sum, mut squares) = (0f64, 0f64);
+        for i in 0..count {
+            let mut rng = Pcg::new([i % 1024, i / 1024], 5, 0);
+            let x = rng.next_f32();
+            assert!((0. ..1.).contains(&x));
+            sum += x as f64;
+            squares += (x * x) as f64;
+            buckets[(x * 16.) as usize] += 1;
+        }
+        println!("{} samples in {:?}", count, start.elapsed());
+        let mean = sum / count as f64;
+        assert!((mean - 0.5).abs() < 1e-3);
+        assert!((squares / count as f64 - mean * mean - 1. / 12.).abs() < 1e-3);
+        for bucket in buckets {
+            assert!((bucket as f64 / count as f64 - 1. / 16.).abs() < 2e-3);
+        }
+    }
+}
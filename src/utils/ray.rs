@@ -1,24 +1,29 @@
-use crate::vector::Vec3;
+use crate::utils::vector::Vec3;
 
 #[derive(Clone)]
-pub struct Ray<'a> {
-    pub origin: &'a Vec3<f32>,
+pub struct Ray {
+    pub origin: Vec3<f32>,
     pub direction: Vec3<f32>,
     pub inv: Vec3<f32>,
 }
 
-
-impl<'a> Ray<'a>{
-    pub fn new(origin: &'a Vec3<f32>, direction: Vec3<f32>, inv: Vec3<f32>) -> Self{
-        Ray{
+impl Ray {
+    // `direction` is normalized so hit distances come out in world units
+    pub fn new(origin: Vec3<f32>, direction: Vec3<f32>) -> Self {
+        let direction = direction.normalize();
+        let inv = Vec3::new(
+            1. / direction.v[0],
+            1. / direction.v[1],
+            1. / direction.v[2],
+        );
+        Ray {
             origin,
             direction,
-            inv
+            inv,
         }
     }
-    
+
     pub fn at(&self, t: f32) -> Vec3<f32> {
-        self.origin + &self.direction * t
+        self.origin + self.direction * t
     }
 }
-
@@ -0,0 +1,122 @@
+use crate::utils::bvh4::Node4;
+use crate::utils::ray::Ray;
+
+// entry distance of the ray into each of the four child boxes of `node`, f32::MAX where it
+// misses. Gives the same lanes as `intersect_aabb` on every child
+pub fn intersect_aabb4(ray: &Ray, node: &Node4) -> [f32; 4] {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        intersect_aabb4_sse(ray, node)
+    }
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    {
+        intersect_aabb4_scalar(ray, node)
+    }
+}
+
+pub fn intersect_aabb4_scalar(ray: &Ray, node: &Node4) -> [f32; 4] {
+    let slab = |min: &[f32; 4], max: &[f32; 4], k: usize, lane: usize| {
+        let t1 = (min[lane] - ray.origin.v[k]) * ray.inv.v[k];
+        let t2 = (max[lane] - ray.origin.v[k]) * ray.inv.v[k];
+        (t1.min(t2), t1.max(t2))
+    };
+    std::array::from_fn(|lane| {
+        let (x_near, x_far) = slab(&node.min_x, &node.max_x, 0, lane);
+        let (y_near, y_far) = slab(&node.min_y, &node.max_y, 1, lane);
+        let (z_near, z_far) = slab(&node.min_z, &node.max_z, 2, lane);
+        let tmin = x_near.max(y_near).max(z_near);
+        let tmax = x_far.min(y_far).min(z_far);
+        if tmax >= tmin && tmax > 0. {
+            tmin
+        } else {
+            f32::MAX
+        }
+    })
+}
+
+// sse is part of the x86_64 baseline, so no runtime detection is needed
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+pub fn intersect_aabb4_sse(ray: &Ray, node: &Node4) -> [f32; 4] {
+    use std::arch::x86_64::*;
+
+    let mut out = [0f32; 4];
+    // SAFETY: every load and store goes through 4 element arrays with unaligned intrinsics
+    unsafe {
+        let slab = |min: &[f32; 4], max: &[f32; 4], k: usize| {
+            let origin = _mm_set1_ps(ray.origin.v[k]);
+            let inv = _mm_set1_ps(ray.inv.v[k]);
+            let t1 = _mm_mul_ps(_mm_sub_ps(_mm_loadu_ps(min.as_ptr()), origin), inv);
+            let t2 = _mm_mul_ps(_mm_sub_ps(_mm_loadu_ps(max.as_ptr()), origin), inv);
+            (_mm_min_ps(t1, t2), _mm_max_ps(t1, t2))
+        };
+        let (x_near, x_far) = slab(&node.min_x, &node.max_x, 0);
+        let (y_near, y_far) = slab(&node.min_y, &node.max_y, 1);
+        let (z_near, z_far) = slab(&node.min_z, &node.max_z, 2);
+        let tmin = _mm_max_ps(_mm_max_ps(x_near, y_near), z_near);
+        let tmax = _mm_min_ps(_mm_min_ps(x_far, y_far), z_far);
+        let hit = _mm_and_ps(
+            _mm_cmpge_ps(tmax, tmin),
+            _mm_cmpgt_ps(tmax, _mm_setzero_ps()),
+        );
+        let result = _mm_or_ps(
+            _mm_and_ps(hit, tmin),
+            _mm_andnot_ps(hit, _mm_set1_ps(f32::MAX)),
+        );
+        _mm_storeu_ps(out.as_mut_ptr(), result);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use crate::utils::{bvh::intersect_aabb, bvh4::Node4, ray::Ray, vector::Vec3};
+
+    use super::{intersect_aabb4, intersect_aabb4_scalar};
+
+    #[test]
+    fn aabb4_test() {
+        let mut rng = rand::thread_rng();
+        let mut node = Node4::default();
+        for lane in 0..4 {
+            let min: [f32; 3] = std::array::from_fn(|_| rng.gen_range(-2.0..1.0));
+            let max: [f32; 3] = std::array::from_fn(|k| min[k] + rng.gen_range(0.1..1.5));
+            node.min_x[lane] = min[0];
+            node.min_y[lane] = min[1];
+            node.min_z[lane] = min[2];
+            node.max_x[lane] = max[0];
+            node.max_y[lane] = max[1];
+            node.max_z[lane] = max[2];
+        }
+        let mut hits = 0;
+        for _ in 0..10_000 {
+            let origin = Vec3::new(
+                rng.gen_range(-4.0..4.0),
+                rng.gen_range(-4.0..4.0),
+                rng.gen_range(-4.0..4.0),
+            );
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let ray = Ray::new(origin, direction);
+            let lanes = intersect_aabb4(&ray, &node);
+            assert_eq!(lanes, intersect_aabb4_scalar(&ray, &node));
+            for (lane, &t) in lanes.iter().enumerate() {
+                let bounds = [
+                    node.min_x[lane],
+                    node.min_y[lane],
+                    node.min_z[lane],
+                    node.max_x[lane],
+                    node.max_y[lane],
+                    node.max_z[lane],
+                ];
+                assert_eq!(t, intersect_aabb(&ray, &bounds).unwrap_or(f32::MAX));
+                hits += (t < f32::MAX) as u32;
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -0,0 +1,280 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use rayon::prelude::*;
+
+use crate::utils::bvh::{create_bvh, is_leaf, Triangle2, BVH};
+use crate::utils::mesh::Mesh;
+use crate::utils::polyline::{chain_segments, Polyline};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub point: [f32; 3],
+    pub normal: [f32; 3], // does not need to be normalized
+}
+
+impl Plane {
+    pub fn new(point: [f32; 3], normal: [f32; 3]) -> Self {
+        Self { point, normal }
+    }
+
+    pub fn signed_distance(&self, p: &[f32; 3]) -> f32 {
+        (p[0] - self.point[0]) * self.normal[0]
+            + (p[1] - self.point[1]) * self.normal[1]
+            + (p[2] - self.point[2]) * self.normal[2]
+    }
+
+    // two unit vectors spanning the plane, used as the 2D axes of exported slices
+    pub fn basis(&self) -> ([f32; 3], [f32; 3]) {
+        let n = normalize(self.normal);
+        // any world axis that is not close to parallel with the normal
+        let helper = if n[0].abs() < 0.9 {
+            [1., 0., 0.]
+        } else {
+            [0., 1., 0.]
+        };
+        let v = normalize(cross(&n, &helper));
+        let u = cross(&v, &n);
+        (u, v)
+    }
+
+    pub fn project(&self, p: &[f32; 3]) -> [f32; 2] {
+        let (u, v) = self.basis();
+        let d = [
+            p[0] - self.point[0],
+            p[1] - self.point[1],
+            p[2] - self.point[2],
+        ];
+        [
+            d[0] * u[0] + d[1] * u[1] + d[2] * u[2],
+            d[0] * v[0] + d[1] * v[1] + d[2] * v[2],
+        ]
+    }
+}
+
+fn cross(a: &[f32; 3], b: &[f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+// segment where the plane cuts the triangle. Vertices exactly on the plane count as above it so
+// that neighbouring triangles agree on every crossing point and the contours stay closed
+pub fn slice_triangle(plane: &Plane, tri: &Triangle2) -> Option<[[f32; 3]; 2]> {
+    let vertices = [tri.v1, tri.v2, tri.v3];
+    let distances = vertices.map(|v| plane.signed_distance(&v));
+    let mut points = vec![];
+    for i in 0..3 {
+        let j = (i + 1) % 3;
+        if (distances[i] >= 0.) != (distances[j] >= 0.) {
+            let t = distances[i] / (distances[i] - distances[j]);
+            let (a, b) = (vertices[i], vertices[j]);
+            points.push([
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]);
+        }
+    }
+    (points.len() == 2).then(|| [points[0], points[1]])
+}
+
+impl BVH {
+    // contours of the mesh in `plane`, only visiting the nodes whose boxes straddle it
+    pub fn slice(&self, plane: &Plane) -> Vec<Polyline> {
+        if self.nodes.is_empty() {
+            return vec![];
+        }
+        let mut leaves = vec![];
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            let (mut below, mut above) = (false, false);
+            for corner in 0..8 {
+                let p = [
+                    node.bounds[if corner & 1 == 0 { 0 } else { 3 }],
+                    node.bounds[if corner & 2 == 0 { 1 } else { 4 }],
+                    node.bounds[if corner & 4 == 0 { 2 } else { 5 }],
+                ];
+                if plane.signed_distance(&p) >= 0. {
+                    above = true;
+                } else {
+                    below = true;
+                }
+            }
+            if !(below && above) {
+                continue;
+            }
+            if is_leaf(node) {
+                leaves.push(index as usize);
+                continue;
+            }
+            stack.extend(
+                [node.left_node, node.right_node]
+                    .iter()
+                    .filter(|&&c| c != 0),
+            );
+        }
+
+        let segments: Vec<[[f32; 3]; 2]> = leaves
+            .par_iter()
+            .flat_map_iter(|&index| {
+                let node = &self.nodes[index];
+                let start = node.start_triangle as usize;
+                self.triangles[start..start + node.triangle_count as usize]
+                    .iter()
+                    .filter_map(|tri| slice_triangle(plane, tri))
+            })
+            .collect();
+        let bounds = self.nodes[0].bounds;
+        let diagonal = (0..3)
+            .map(|k| (bounds[k + 3] - bounds[k]).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        chain_segments(&segments, diagonal.max(1.) * 1e-6)
+    }
+}
+
+pub fn slice_mesh(mesh: &Mesh, plane: &Plane) -> Vec<Polyline> {
+    create_bvh(mesh, 20).slice(plane)
+}
+
+// 2D bounds of the contours in plane coordinates: min x, min y, max x, max y
+fn plane_bounds(polylines: &[Polyline], plane: &Plane) -> [f32; 4] {
+    let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+    for p in polylines.iter().flat_map(|p| p.points.iter()) {
+        let q = plane.project(p);
+        bounds[0] = bounds[0].min(q[0]);
+        bounds[1] = bounds[1].min(q[1]);
+        bounds[2] = bounds[2].max(q[0]);
+        bounds[3] = bounds[3].max(q[1]);
+    }
+    if polylines.iter().all(|p| p.points.is_empty()) {
+        bounds = [0., 0., 1., 1.];
+    }
+    bounds
+}
+
+// writes the contours as SVG paths in the plane's 2D coordinates, in mesh units with y up
+pub fn write_svg(polylines: &[Polyline], plane: &Plane, file_path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_path)?);
+    let bounds = plane_bounds(polylines, plane);
+    let (width, height) = (bounds[2] - bounds[0], bounds[3] - bounds[1]);
+    let margin = 0.02 * width.max(height).max(f32::MIN_POSITIVE);
+    writeln!(
+        file,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+        bounds[0] - margin,
+        -bounds[3] - margin,
+        width + 2. * margin,
+        height + 2. * margin
+    )?;
+    for polyline in polylines {
+        if polyline.points.is_empty() {
+            continue;
+        }
+        // svg y points down, flip it so the drawing matches the plane's orientation
+        let mut path = String::new();
+        for (i, p) in polyline.points.iter().enumerate() {
+            let q = plane.project(p);
+            path += &format!("{}{} {} ", if i == 0 { "M" } else { "L" }, q[0], -q[1]);
+        }
+        if polyline.closed {
+            path += "Z";
+        }
+        writeln!(
+            file,
+            "  <path d=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"{}\"/>",
+            path.trim_end(),
+            margin / 4.
+        )?;
+    }
+    writeln!(file, "</svg>")?;
+    file.flush()
+}
+
+// writes the contours as R12 DXF polylines in the plane's 2D coordinates
+pub fn write_dxf(polylines: &[Polyline], plane: &Plane, file_path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_path)?);
+    writeln!(file, "0\nSECTION\n2\nENTITIES")?;
+    for polyline in polylines {
+        if polyline.points.is_empty() {
+            continue;
+        }
+        writeln!(
+            file,
+            "0\nPOLYLINE\n8\n0\n66\n1\n70\n{}",
+            if polyline.closed { 1 } else { 0 }
+        )?;
+        for p in polyline.points.iter() {
+            let q = plane.project(p);
+            writeln!(file, "0\nVERTEX\n8\n0\n10\n{}\n20\n{}\n30\n0", q[0], q[1])?;
+        }
+        writeln!(file, "0\nSEQEND")?;
+    }
+    writeln!(file, "0\nENDSEC\n0\nEOF")?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::{bvh::create_bvh, mesh::Mesh};
+
+    use super::{slice_mesh, write_dxf, write_svg, Plane};
+
+    #[test]
+    fn cube_slice_test() {
+        let mesh = Mesh::cuboid([0., 0., 0.], [1., 1., 1.]);
+        let plane = Plane::new([0., 0., 0.5], [0., 0., 1.]);
+        let contours = slice_mesh(&mesh, &plane);
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].closed);
+        assert!((contours[0].length() - 4.).abs() < 1e-5);
+
+        // a plane through the top face vertices still gives one closed square
+        let contours = slice_mesh(&mesh, &Plane::new([0., 0., 1.], [0., 0., 1.]));
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].closed);
+
+        let dir = std::env::temp_dir();
+        let svg = dir.join("cube_slice.svg");
+        let dxf = dir.join("cube_slice.dxf");
+        write_svg(&contours, &plane, svg.to_str().unwrap()).unwrap();
+        write_dxf(&contours, &plane, dxf.to_str().unwrap()).unwrap();
+        let svg = std::fs::read_to_string(svg).unwrap();
+        assert_eq!(svg.matches("<path").count(), 1);
+        assert!(svg.contains('Z'));
+        let dxf = std::fs::read_to_string(dxf).unwrap();
+        assert_eq!(dxf.matches("VERTEX").count(), contours[0].points.len());
+        assert!(dxf.ends_with("EOF\n"));
+    }
+
+    #[test]
+    fn mesh_slice_test() {
+        // an octahedron cut at height h across any axis gives a square with corners 1 - h from it
+        let mesh = Mesh::octahedron([1., 2., 3.], 1.);
+        let bvh = create_bvh(&mesh, 10);
+        for k in 0..3 {
+            for h in [0., 0.3, -0.6] {
+                let mut point = [1., 2., 3.];
+                let mut normal = [0.; 3];
+                point[k] += h;
+                normal[k] = 1.;
+                let plane = Plane::new(point, normal);
+                let contours = bvh.slice(&plane);
+                assert_eq!(contours.len(), 1);
+                assert!(contours[0].closed);
+                let expected = 4. * 2f32.sqrt() * (1. - f32::abs(h));
+                assert!((contours[0].length() - expected).abs() < 1e-4);
+                assert_eq!(slice_mesh(&mesh, &plane)[0].points.len(), 4);
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::ops::Mul;
+
+use crate::utils::matrix::{Mat3, Mat4};
+use crate::utils::quaternion::Quat;
+use crate::utils::vector::Vec3;
+
+// scales, then rotates, then translates
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3<f32>,
+    pub rotation: Quat,
+    pub scale: Vec3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::new(0., 0., 0.),
+            rotation: Quat::IDENTITY,
+            scale: Vec3::new(1., 1., 1.),
+        }
+    }
+}
+
+impl Transform {
+    pub fn new(translation: Vec3<f32>, rotation: Quat, scale: Vec3<f32>) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn from_translation(translation: Vec3<f32>) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_scale(scale: Vec3<f32>) -> Self {
+        Self {
+            scale,
+            ..Default::default()
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(&self.scale, &self.rotation, &self.translation)
+    }
+
+    // splits an affine matrix without shear back into its parts
+    pub fn from_matrix(m: &Mat4) -> Self {
+        let linear = Mat3::from_mat4(m);
+        let mut scale = Vec3::new(0., 0., 0.);
+        let mut rotation = Mat3::IDENTITY;
+        for c in 0..3 {
+            let column = linear.cols[c];
+            let length = Vec3::from(column).length();
+            scale.v[c] = length;
+            if length > 0. {
+                rotation.cols[c] = column.map(|value| value / length);
+            }
+        }
+        // a mirroring matrix keeps a proper rotation and a negative scale
+        if linear.determinant() < 0. {
+            scale.v[0] = -scale.v[0];
+            rotation.cols[0] = rotation.cols[0].map(|value| -value);
+        }
+        Self {
+            translation: m.translation(),
+            rotation: Quat::from_mat3(&rotation),
+            scale,
+        }
+    }
+
+    pub fn transform_point(&self, p: &Vec3<f32>) -> Vec3<f32> {
+        self.rotation.rotate(&(p * self.scale)) + self.translation
+    }
+
+    pub fn transform_vector(&self, v: &Vec3<f32>) -> Vec3<f32> {
+        self.rotation.rotate(&(v * self.scale))
+    }
+
+    // normals scale by the inverse so they stay perpendicular to the surface
+    pub fn transform_normal(&self, n: &Vec3<f32>) -> Vec3<f32> {
+        self.rotation.rotate(&(n / self.scale)).normalize()
+    }
+
+    // bounds in [min x, min y, min z, max x, max y, max z]
+    pub fn transform_aabb(&self, bounds: &[f32; 6]) -> [f32; 6] {
+        self.matrix().transform_aabb(bounds)
+    }
+
+    // applies `other` first, then self. Exact as long as the scale of self is uniform or
+    // `other` does not rotate, otherwise the shear the product would need is dropped
+    pub fn compose(&self, other: &Transform) -> Transform {
+        Transform {
+            translation: self.transform_point(&other.translation),
+            rotation: self.rotation * other.rotation,
+            scale: self.scale * other.scale,
+        }
+    }
+
+    // exact for uniform scale, see `compose`. `matrix().inverse()` is exact for every transform
+    pub fn inverse(&self) -> Transform {
+        let rotation = self.rotation.conjugate();
+        let scale = Vec3::new(
+            1. / self.scale.v[0],
+            1. / self.scale.v[1],
+            1. / self.scale.v[2],
+        );
+        let translation = rotation.rotate(&self.translation) * scale * -1.;
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    // column-major, ready to upload
+    pub fn to_cols_array(&self) -> [[f32; 4]; 4] {
+        self.matrix().to_cols_array()
+    }
+}
+
+impl Mul for &Transform {
+    type Output = Transform;
+    fn mul(self, rhs: &Transform) -> Transform {
+        self.compose(rhs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use crate::utils::{quaternion::Quat, vector::Vec3};
+
+    use super::Transform;
+
+    fn close_vec(a: &Vec3<f32>, b: &Vec3<f32>) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn transform_test() {
+        let a = Transform::new(
+            Vec3::new(1., 2., 3.),
+            Quat::from_axis_angle(&Vec3::new(1., 1., 0.), 0.8),
+            Vec3::new(2., 2., 2.),
+        );
+        let b = Transform::new(
+            Vec3::new(-0.5, 0., 4.),
+            Quat::from_euler(0.3, -0.2, 1.),
+            Vec3::new(1., 3., 0.5),
+        );
+        let p = Vec3::new(0.4, -1.2, 2.);
+
+        // the struct agrees with its matrix
+        let m = b.matrix();
+        assert!(close_vec(&b.transform_point(&p), &m.transform_point(&p)));
+        assert!(close_vec(&b.transform_vector(&p), &m.transform_vector(&p)));
+        assert!(close_vec(&b.transform_normal(&p), &m.transform_normal(&p)));
+
+        let ab = &a * &b;
+        assert!(close_vec(
+            &ab.transform_point(&p),
+            &a.transform_point(&b.transform_point(&p))
+        ));
+        assert!(close_vec(
+            &(a.matrix() * b.matrix()).transform_point(&p),
+            &ab.transform_point(&p)
+        ));
+        assert!(close_vec(
+            &a.inverse().transform_point(&a.transform_point(&p)),
+            &p
+        ));
+
+        let split = Transform::from_matrix(&b.matrix());
+        assert!(close_vec(&split.translation, &b.translation));
+        assert!(close_vec(&split.scale, &b.scale));
+        assert!(close_vec(
+            &split.transform_point(&p),
+            &b.transform_point(&p)
+        ));
+
+        // all corners of the moved box lie inside the moved bounds
+        let bounds = [-1., 0., 2., 1., 0.5, 3.];
+        let moved = b.transform_aabb(&bounds);
+        for corner in 0..8 {
+            let c = Vec3::new(
+                bounds[if corner & 1 == 0 { 0 } else { 3 }],
+                bounds[if corner & 2 == 0 { 1 } else { 4 }],
+                bounds[if corner & 4 == 0 { 2 } else { 5 }],
+            );
+            let q = b.transform_point(&c);
+            for k in 0..3 {
+                assert!(q.v[k] >= moved[k] - 1e-4 && q.v[k] <= moved[k + 3] + 1e-4);
+            }
+        }
+
+        let turn = Transform::from_rotation(Quat::from_axis_angle(&Vec3::new(0., 0., 1.), PI));
+        assert!(close_vec(
+            &turn.transform_point(&Vec3::new(1., 0., 0.)),
+            &Vec3::new(-1., 0., 0.)
+        ));
+        assert_eq!(Transform::default().to_cols_array()[3], [0., 0., 0., 1.]);
+    }
+}
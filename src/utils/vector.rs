@@ -1,6 +1,6 @@
-use std::{fmt::Display, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign}};
+use std::{fmt::Display, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign}};
 
-use num_traits::{NumCast, ToPrimitive, Zero};
+use num_traits::{Float, NumCast, ToPrimitive, Zero};
 
 pub trait ConvertTo<U> {
     fn convert_to(self) -> U;
@@ -19,7 +19,7 @@ impl<T> ConvertTo<T> for T {
 //     value.into()
 // }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Vec3<T> {
     pub v: [T; 3],
 }
@@ -30,6 +30,12 @@ impl<T: PartialEq> PartialEq for Vec3<T> {
     }
 }
 
+impl<T> From<[T; 3]> for Vec3<T> {
+    fn from(v: [T; 3]) -> Self {
+        Self { v }
+    }
+}
+
 // Addition traits
 // Vec += Number
 impl<T: AddAssign + Copy> AddAssign<T> for Vec3<T>{
@@ -433,6 +439,25 @@ where
     }
 }
 
+// -Vec
+impl<T: Neg<Output = T> + Copy> Neg for Vec3<T> {
+    type Output = Vec3<T>;
+    fn neg(self) -> Self::Output {
+        Self::Output {
+            v: [-self.v[0], -self.v[1], -self.v[2]]
+        }
+    }
+}
+// -&Vec
+impl<T: Neg<Output = T> + Copy> Neg for &Vec3<T> {
+    type Output = Vec3<T>;
+    fn neg(self) -> Self::Output {
+        Self::Output {
+            v: [-self.v[0], -self.v[1], -self.v[2]]
+        }
+    }
+}
+
 // other required implementations
 impl<T> Vec3<T>
 where T:  PartialOrd + Copy + Mul<Output = T> + Copy + Add<Output = T> + Copy + Sub<Output = T> + Copy + ToPrimitive
@@ -445,10 +470,6 @@ where T:  PartialOrd + Copy + Mul<Output = T> + Copy + Add<Output = T> + Copy +
        self.v[0] * self.v[0] + self.v[1] * self.v[1] + self.v[2] * self.v[2]
     }
 
-    pub fn length(&self) -> f64 {
-        self.squared_length().to_f64().unwrap().sqrt()
-    }
-
     pub fn dot(&self, other: &Self) -> T {
         self.v[0] * other.v[0] + self.v[1] * other.v[1] + self.v[2] * other.v[2]
     }
@@ -459,13 +480,6 @@ where T:  PartialOrd + Copy + Mul<Output = T> + Copy + Add<Output = T> + Copy +
         }
     }
 
-    pub fn normalize(&self) -> Vec3<f32> {
-        let length = self.length() as f32;
-        Vec3::<f32>{
-            v: [&self.v[0].to_f32().unwrap() / length, &self.v[1].to_f32().unwrap() / length, &self.v[2].to_f32().unwrap() / length]
-        }
-    }
-    
     pub fn max_component(&self) -> T {
         if self.v[0] > self.v[1] {
            if self.v[2] > self.v[0] {
@@ -509,11 +523,61 @@ where T:  PartialOrd + Copy + Mul<Output = T> + Copy + Add<Output = T> + Copy +
             v: [convert(self.v[0]), convert(self.v[1]), convert(self.v[2])]
         }
     }
-    
-    pub fn angle(&self, other: &Self) -> f64 {
-        (self.dot(other).to_f64().unwrap() / (self.length() * other.length())).acos()
+}
+
+// operations that need a floating point type, computed in the vector's own precision
+impl<T: Float> Vec3<T> {
+    pub fn length(&self) -> T {
+        self.squared_length().sqrt()
+    }
+
+    pub fn normalize(&self) -> Vec3<T> {
+        *self / self.length()
+    }
+
+    pub fn angle(&self, other: &Self) -> T {
+        (self.dot(other) / (self.length() * other.length())).max(-T::one()).min(T::one()).acos()
+    }
+
+    pub fn lerp(&self, other: &Self, t: T) -> Vec3<T> {
+        *self + (*other - *self) * t
+    }
+
+    // mirrors the direction at the plane of the unit `normal`
+    pub fn reflect(&self, normal: &Self) -> Vec3<T> {
+        let two = T::one() + T::one();
+        *self - *normal * (two * self.dot(normal))
+    }
+
+    // bends the unit direction through a surface with unit `normal` facing against it, `eta` is
+    // the ratio of the refractive indices. None on total internal reflection
+    pub fn refract(&self, normal: &Self, eta: T) -> Option<Vec3<T>> {
+        let cos_i = -self.dot(normal);
+        let k = T::one() - eta * eta * (T::one() - cos_i * cos_i);
+        if k < T::zero() {
+            return None;
+        }
+        Some(*self * eta + *normal * (eta * cos_i - k.sqrt()))
+    }
+
+    pub fn abs(&self) -> Vec3<T> {
+        Vec3 { v: self.v.map(|x| x.abs()) }
+    }
+
+    // component-wise minimum
+    pub fn min(&self, other: &Self) -> Vec3<T> {
+        Vec3 { v: [self.v[0].min(other.v[0]), self.v[1].min(other.v[1]), self.v[2].min(other.v[2])] }
+    }
+
+    // component-wise maximum
+    pub fn max(&self, other: &Self) -> Vec3<T> {
+        Vec3 { v: [self.v[0].max(other.v[0]), self.v[1].max(other.v[1]), self.v[2].max(other.v[2])] }
+    }
+
+    // every component within `epsilon`
+    pub fn approx_eq(&self, other: &Self, epsilon: T) -> bool {
+        (0..3).all(|k| (self.v[k] - other.v[k]).abs() <= epsilon)
     }
-    
 }
 
 // implementing display for writing into file
@@ -523,6 +587,134 @@ impl<T: Display> Display for Vec3<T>{
     }
 }
 
+// component-wise arithmetic for the fixed size vectors besides Vec3
+macro_rules! vector_ops {
+    ($name:ident, $n:literal) => {
+        impl<T> From<[T; $n]> for $name<T> {
+            fn from(v: [T; $n]) -> Self {
+                Self { v }
+            }
+        }
+
+        impl<T: Copy + Add<Output = T>> Add for $name<T> {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                Self { v: std::array::from_fn(|k| self.v[k] + rhs.v[k]) }
+            }
+        }
+
+        impl<T: Copy + Sub<Output = T>> Sub for $name<T> {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                Self { v: std::array::from_fn(|k| self.v[k] - rhs.v[k]) }
+            }
+        }
+
+        impl<T: Copy + Mul<Output = T>> Mul for $name<T> {
+            type Output = Self;
+            fn mul(self, rhs: Self) -> Self {
+                Self { v: std::array::from_fn(|k| self.v[k] * rhs.v[k]) }
+            }
+        }
+
+        impl<T: Copy + Mul<Output = T>> Mul<T> for $name<T> {
+            type Output = Self;
+            fn mul(self, rhs: T) -> Self {
+                Self { v: self.v.map(|x| x * rhs) }
+            }
+        }
+
+        impl<T: Copy + Div<Output = T>> Div for $name<T> {
+            type Output = Self;
+            fn div(self, rhs: Self) -> Self {
+                Self { v: std::array::from_fn(|k| self.v[k] / rhs.v[k]) }
+            }
+        }
+
+        impl<T: Copy + Div<Output = T>> Div<T> for $name<T> {
+            type Output = Self;
+            fn div(self, rhs: T) -> Self {
+                Self { v: self.v.map(|x| x / rhs) }
+            }
+        }
+
+        impl<T: Copy + Neg<Output = T>> Neg for $name<T> {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self { v: self.v.map(|x| -x) }
+            }
+        }
+
+        impl<T: Float> $name<T> {
+            pub fn dot(&self, other: &Self) -> T {
+                (0..$n).fold(T::zero(), |sum, k| sum + self.v[k] * other.v[k])
+            }
+
+            pub fn length(&self) -> T {
+                self.dot(self).sqrt()
+            }
+
+            pub fn normalize(&self) -> Self {
+                *self / self.length()
+            }
+
+            pub fn lerp(&self, other: &Self, t: T) -> Self {
+                *self + (*other - *self) * t
+            }
+
+            pub fn abs(&self) -> Self {
+                Self { v: self.v.map(|x| x.abs()) }
+            }
+
+            pub fn min(&self, other: &Self) -> Self {
+                Self { v: std::array::from_fn(|k| self.v[k].min(other.v[k])) }
+            }
+
+            pub fn max(&self, other: &Self) -> Self {
+                Self { v: std::array::from_fn(|k| self.v[k].max(other.v[k])) }
+            }
+
+            pub fn approx_eq(&self, other: &Self, epsilon: T) -> bool {
+                (0..$n).all(|k| (self.v[k] - other.v[k]).abs() <= epsilon)
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec2<T> {
+    pub v: [T; 2],
+}
+
+impl<T> Vec2<T> {
+    pub fn new(x: T, y: T) -> Self {
+        Self { v: [x, y] }
+    }
+}
+
+// homogeneous coordinates and colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec4<T> {
+    pub v: [T; 4],
+}
+
+impl<T: Copy> Vec4<T> {
+    pub fn new(x: T, y: T, z: T, w: T) -> Self {
+        Self { v: [x, y, z, w] }
+    }
+
+    pub fn from_vec3(v: &Vec3<T>, w: T) -> Self {
+        Self { v: [v.v[0], v.v[1], v.v[2], w] }
+    }
+
+    pub fn xyz(&self) -> Vec3<T> {
+        Vec3 { v: [self.v[0], self.v[1], self.v[2]] }
+    }
+}
+
+vector_ops!(Vec2, 2);
+vector_ops!(Vec4, 4);
+
 pub fn write_color(out: &mut [u8], pixel_color: [f64; 4]){
     let r = pixel_color[0];
     let g = pixel_color[1];
@@ -541,3 +733,39 @@ pub fn write_color(out: &mut [u8], pixel_color: [f64; 4]){
     out[3] = a;
 
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Vec2, Vec3, Vec4};
+
+    #[test]
+    fn vector_test() {
+        let a = Vec3::new(3f32, 4., 0.);
+        let b = a;
+        assert_eq!(a.length(), 5.);
+        assert!(a.normalize().approx_eq(&Vec3::new(0.6, 0.8, 0.), 1e-6));
+        assert!((Vec3::new(1f64, 0., 0.).angle(&Vec3::new(0., 2., 0.)) - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        assert_eq!(a.lerp(&Vec3::new(5., 0., 2.), 0.5), Vec3::new(4., 2., 1.));
+        assert_eq!((-b).abs(), a);
+        assert_eq!(a.min(&Vec3::new(1., 5., -1.)), Vec3::new(1., 4., -1.));
+        assert_eq!(a.max(&Vec3::new(1., 5., -1.)), Vec3::new(3., 5., 0.));
+
+        let normal = Vec3::new(0f32, 1., 0.);
+        let incoming = Vec3::new(1f32, -1., 0.).normalize();
+        assert!(incoming.reflect(&normal).approx_eq(&Vec3::new(1., 1., 0.).normalize(), 1e-6));
+        // no bending at equal indices, total internal reflection past the critical angle
+        assert!(incoming.refract(&normal, 1.).unwrap().approx_eq(&incoming, 1e-6));
+        let refracted = incoming.refract(&normal, 1. / 1.5).unwrap();
+        assert!((refracted.length() - 1.).abs() < 1e-6);
+        assert!((refracted.v[0] - incoming.v[0] / 1.5).abs() < 1e-6);
+        assert!(incoming.refract(&normal, 1.5).is_none());
+
+        let p = Vec4::from_vec3(&a, 1.);
+        assert_eq!(p.xyz(), a);
+        assert_eq!((p * 2. - p).v, [3., 4., 0., 1.]);
+        assert!((Vec4::new(1f32, 1., 1., 1.).length() - 2.).abs() < 1e-6);
+        let uv = Vec2::new(0.25f32, 0.5);
+        assert_eq!(uv.lerp(&Vec2::new(0.75, 1.), 0.5), Vec2::new(0.5, 0.75));
+        assert!((uv / Vec2::from([0.25, 0.5])).approx_eq(&Vec2::new(1., 1.), 0.));
+    }
+}
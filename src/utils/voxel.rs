@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use rayon::prelude::*;
+
+use crate::utils::bvh::{Triangle2, BVH};
+use crate::utils::distance::WindingNumbers;
+use crate::utils::vector::Vec3;
+
+// edge length of a sparse brick in voxels, one u64 mask per z slice of
+// BRICK_SIZE x BRICK_SIZE voxels
+pub const BRICK_SIZE: usize = 8;
+
+// dense occupancy grid, x varies fastest
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    pub origin: [f32; 3], // min corner of voxel (0, 0, 0)
+    pub voxel_size: f32,
+    pub dims: [usize; 3],
+    pub occupancy: Vec<bool>,
+}
+
+// only stores the bricks that hold occupied voxels
+#[derive(Clone, Debug)]
+pub struct SparseVoxelGrid {
+    pub origin: [f32; 3],
+    pub voxel_size: f32,
+    pub dims: [usize; 3],
+    pub bricks: BTreeMap<[u32; 3], [u64; BRICK_SIZE]>, // brick coordinate -> mask per z slice
+}
+
+impl VoxelGrid {
+    // empty grid covering `bounds` with `resolution` voxels along its longest axis
+    pub fn new(bounds: &[f32; 6], resolution: usize) -> Self {
+        let resolution = resolution.max(1);
+        let extent = [
+            bounds[3] - bounds[0],
+            bounds[4] - bounds[1],
+            bounds[5] - bounds[2],
+        ];
+        let longest = extent[0].max(extent[1]).max(extent[2]);
+        let voxel_size = if longest > 0. {
+            longest / resolution as f32
+        } else {
+            1.
+        };
+        let dims = extent.map(|e| {
+            if e == longest {
+                resolution
+            } else {
+                // small slack so an axis that is a whole number of voxels long does not get an extra one
+                ((e / voxel_size - 1e-4).ceil() as usize).max(1)
+            }
+        });
+        Self {
+            origin: [bounds[0], bounds[1], bounds[2]],
+            voxel_size,
+            dims,
+            occupancy: vec![false; dims[0] * dims[1] * dims[2]],
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.dims[0] * (y + self.dims[1] * z)
+    }
+
+    pub fn coordinates(&self, index: usize) -> [usize; 3] {
+        [
+            index % self.dims[0],
+            (index / self.dims[0]) % self.dims[1],
+            index / (self.dims[0] * self.dims[1]),
+        ]
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> bool {
+        x < self.dims[0]
+            && y < self.dims[1]
+            && z < self.dims[2]
+            && self.occupancy[self.index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: bool) {
+        let index = self.index(x, y, z);
+        self.occupancy[index] = value;
+    }
+
+    // min and max corner of a voxel
+    pub fn voxel_bounds(&self, x: usize, y: usize, z: usize) -> [f32; 6] {
+        let min = [
+            self.origin[0] + x as f32 * self.voxel_size,
+            self.origin[1] + y as f32 * self.voxel_size,
+            self.origin[2] + z as f32 * self.voxel_size,
+        ];
+        [
+            min[0],
+            min[1],
+            min[2],
+            min[0] + self.voxel_size,
+            min[1] + self.voxel_size,
+            min[2] + self.voxel_size,
+        ]
+    }
+
+    pub fn voxel_center(&self, x: usize, y: usize, z: usize) -> [f32; 3] {
+        [
+            self.origin[0] + (x as f32 + 0.5) * self.voxel_size,
+            self.origin[1] + (y as f32 + 0.5) * self.voxel_size,
+            self.origin[2] + (z as f32 + 0.5) * self.voxel_size,
+        ]
+    }
+
+    pub fn count(&self) -> usize {
+        self.occupancy.par_iter().filter(|&&o| o).count()
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.count() as f32 * self.voxel_size.powi(3)
+    }
+
+    pub fn to_sparse(&self) -> SparseVoxelGrid {
+        let mut sparse = SparseVoxelGrid {
+            origin: self.origin,
+            voxel_size: self.voxel_size,
+            dims: self.dims,
+            bricks: BTreeMap::new(),
+        };
+        for (index, _) in self.occupancy.iter().enumerate().filter(|(_, &o)| o) {
+            let [x, y, z] = self.coordinates(index);
+            sparse.set(x, y, z);
+        }
+        sparse
+    }
+
+    // header "VOXR", dims as 3 u32, origin as 3 f32, voxel size as f32, then one byte per voxel
+    // with x varying fastest. All little endian
+    pub fn write_raw(&self, file_path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(file_path)?);
+        file.write_all(b"VOXR")?;
+        write_header(&mut file, self.dims, self.origin, self.voxel_size)?;
+        let bytes: Vec<u8> = self.occupancy.iter().map(|&o| o as u8).collect();
+        file.write_all(&bytes)?;
+        file.flush()
+    }
+}
+
+fn write_header(
+    file: &mut impl Write,
+    dims: [usize; 3],
+    origin: [f32; 3],
+    voxel_size: f32,
+) -> io::Result<()> {
+    for d in dims {
+        file.write_all(&(d as u32).to_le_bytes())?;
+    }
+    for o in origin {
+        file.write_all(&o.to_le_bytes())?;
+    }
+    file.write_all(&voxel_size.to_le_bytes())
+}
+
+impl SparseVoxelGrid {
+    fn split(x: usize, y: usize, z: usize) -> ([u32; 3], usize, u64) {
+        let brick = [
+            (x / BRICK_SIZE) as u32,
+            (y / BRICK_SIZE) as u32,
+            (z / BRICK_SIZE) as u32,
+        ];
+        let bit = (x % BRICK_SIZE) + BRICK_SIZE * (y % BRICK_SIZE);
+        (brick, z % BRICK_SIZE, 1 << bit)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> bool {
+        let (brick, slice, bit) = Self::split(x, y, z);
+        self.bricks
+            .get(&brick)
+            .is_some_and(|masks| masks[slice] & bit != 0)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize) {
+        let (brick, slice, bit) = Self::split(x, y, z);
+        self.bricks.entry(brick).or_insert([0; BRICK_SIZE])[slice] |= bit;
+    }
+
+    pub fn count(&self) -> usize {
+        self.bricks
+            .values()
+            .flat_map(|masks| masks.iter())
+            .map(|mask| mask.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.count() as f32 * self.voxel_size.powi(3)
+    }
+
+    pub fn to_dense(&self) -> VoxelGrid {
+        let mut dense = VoxelGrid {
+            origin: self.origin,
+            voxel_size: self.voxel_size,
+            dims: self.dims,
+            occupancy: vec![false; self.dims[0] * self.dims[1] * self.dims[2]],
+        };
+        for (brick, masks) in self.bricks.iter() {
+            for (slice, mask) in masks.iter().enumerate() {
+                for bit in (0..64).filter(|bit| mask & (1 << bit) != 0) {
+                    dense.set(
+                        brick[0] as usize * BRICK_SIZE + bit % BRICK_SIZE,
+                        brick[1] as usize * BRICK_SIZE + bit / BRICK_SIZE,
+                        brick[2] as usize * BRICK_SIZE + slice,
+                        true,
+                    );
+                }
+            }
+        }
+        dense
+    }
+
+    // VDB-like brick file: header "VOXB", dims, origin and voxel size as in `write_raw`, the brick
+    // count as u32, then per brick its coordinate as 3 u32 followed by BRICK_SIZE u64 masks
+    pub fn write(&self, file_path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(file_path)?);
+        file.write_all(b"VOXB")?;
+        write_header(&mut file, self.dims, self.origin, self.voxel_size)?;
+        file.write_all(&(self.bricks.len() as u32).to_le_bytes())?;
+        for (brick, masks) in self.bricks.iter() {
+            for c in brick {
+                file.write_all(&c.to_le_bytes())?;
+            }
+            for mask in masks {
+                file.write_all(&mask.to_le_bytes())?;
+            }
+        }
+        file.flush()
+    }
+}
+
+// separating axis test between a triangle and a box (Akenine-Moller): the box normals, the
+// triangle normal and the nine edge cross products
+pub fn triangle_box_overlap(tri: &Triangle2, bounds: &[f32; 6]) -> bool {
+    let center = [
+        (bounds[0] + bounds[3]) * 0.5,
+        (bounds[1] + bounds[4]) * 0.5,
+        (bounds[2] + bounds[5]) * 0.5,
+    ];
+    let half = [
+        (bounds[3] - bounds[0]) * 0.5,
+        (bounds[4] - bounds[1]) * 0.5,
+        (bounds[5] - bounds[2]) * 0.5,
+    ];
+    let relative = |v: [f32; 3]| Vec3::new(v[0] - center[0], v[1] - center[1], v[2] - center[2]);
+    let vertices = [relative(tri.v1), relative(tri.v2), relative(tri.v3)];
+    let edges = [
+        vertices[1] - vertices[0],
+        vertices[2] - vertices[1],
+        vertices[0] - vertices[2],
+    ];
+    let box_axes = [
+        Vec3::new(1., 0., 0.),
+        Vec3::new(0., 1., 0.),
+        Vec3::new(0., 0., 1.),
+    ];
+
+    let mut axes: Vec<Vec3<f32>> = box_axes.to_vec();
+    axes.push(edges[0].cross(&edges[1]));
+    for edge in edges.iter() {
+        for box_axis in box_axes.iter() {
+            axes.push(box_axis.cross(edge));
+        }
+    }
+    axes.iter().all(|axis| {
+        let projections = vertices.iter().map(|v| v.dot(axis));
+        let (min, max) =
+            projections.fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p), hi.max(p)));
+        let radius =
+            half[0] * axis.v[0].abs() + half[1] * axis.v[1].abs() + half[2] * axis.v[2].abs();
+        min <= radius && max >= -radius
+    })
+}
+
+fn grid_bounds(bvh: &BVH) -> [f32; 6] {
+    if bvh.nodes.is_empty() {
+        [0.; 6]
+    } else {
+        bvh.nodes[0].bounds
+    }
+}
+
+// marks every voxel a triangle touches, so the grid never misses a part of the surface
+pub fn voxelize_surface(bvh: &BVH, resolution: usize) -> VoxelGrid {
+    let mut grid = VoxelGrid::new(&grid_bounds(bvh), resolution);
+    let occupied: Vec<usize> = bvh
+        .triangles
+        .par_iter()
+        .flat_map_iter(|tri| {
+            let mut cells = vec![];
+            // voxel range covered by the triangle's bounding box
+            let mut range = [[0; 2]; 3];
+            for (k, r) in range.iter_mut().enumerate() {
+                let lo = tri.v1[k].min(tri.v2[k]).min(tri.v3[k]);
+                let hi = tri.v1[k].max(tri.v2[k]).max(tri.v3[k]);
+                let to_cell = |p: f32| {
+                    (((p - grid.origin[k]) / grid.voxel_size).floor().max(0.) as usize)
+                        .min(grid.dims[k] - 1)
+                };
+                *r = [to_cell(lo), to_cell(hi)];
+            }
+            for z in range[2][0]..=range[2][1] {
+                for y in range[1][0]..=range[1][1] {
+                    for x in range[0][0]..=range[0][1] {
+                        if triangle_box_overlap(tri, &grid.voxel_bounds(x, y, z)) {
+                            cells.push(grid.index(x, y, z));
+                        }
+                    }
+                }
+            }
+            cells
+        })
+        .collect();
+    for index in occupied {
+        grid.occupancy[index] = true;
+    }
+    grid
+}
+
+// marks the voxels whose centers are inside the mesh by their winding number, which tolerates
+// small holes and self intersections. Only meaningful for closed meshes
+pub fn voxelize_solid(bvh: &BVH, resolution: usize) -> VoxelGrid {
+    let mut grid = VoxelGrid::new(&grid_bounds(bvh), resolution);
+    let winding = WindingNumbers::new(bvh);
+    let grid_ref = &grid;
+    let occupancy = (0..grid.occupancy.len())
+        .into_par_iter()
+        .map(|index| {
+            let [x, y, z] = grid_ref.coordinates(index);
+            let c = grid_ref.voxel_center(x, y, z);
+            winding.at(&Vec3::new(c[0], c[1], c[2])) > 0.5
+        })
+        .collect();
+    grid.occupancy = occupancy;
+    grid
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use crate::utils::{bvh::create_bvh, mesh::Mesh};
+
+    use super::{voxelize_solid, voxelize_surface, BRICK_SIZE};
+
+    #[test]
+    fn cube_voxel_test() {
+        let bvh = create_bvh(&Mesh::cuboid([0., 0., 0.], [1., 1., 1.]), 10);
+        let solid = voxelize_solid(&bvh, 10);
+        assert_eq!(solid.dims, [10, 10, 10]);
+        assert_eq!(solid.count(), 1000);
+        assert!((solid.volume() - 1.).abs() < 1e-4);
+
+        // the outer shell of the 10^3 grid
+        let surface = voxelize_surface(&bvh, 10);
+        assert_eq!(surface.count(), 1000 - 8 * 8 * 8);
+        assert!(!surface.get(5, 5, 5));
+
+        let sparse = surface.to_sparse();
+        assert_eq!(sparse.count(), surface.count());
+        assert_eq!(sparse.bricks.len(), 8);
+        assert_eq!(sparse.to_dense().occupancy, surface.occupancy);
+
+        let dir = std::env::temp_dir();
+        let raw = dir.join("cube.voxr");
+        let bricks = dir.join("cube.voxb");
+        surface.write_raw(raw.to_str().unwrap()).unwrap();
+        sparse.write(bricks.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::metadata(raw).unwrap().len(), 4 + 28 + 1000);
+        assert_eq!(
+            std::fs::metadata(bricks).unwrap().len() as usize,
+            4 + 28 + 4 + 8 * (12 + 8 * BRICK_SIZE)
+        );
+    }
+
+    #[test]
+    fn octahedron_voxel_test() {
+        // the solid voxels are exactly those with their centers inside |x| + |y| + |z| < 1
+        let bvh = create_bvh(&Mesh::octahedron([0., 0., 0.], 1.), 10);
+        let solid = voxelize_solid(&bvh, 16);
+        assert_eq!(solid.dims, [16, 16, 16]);
+        for index in 0..solid.occupancy.len() {
+            let [x, y, z] = solid.coordinates(index);
+            let c = solid.voxel_center(x, y, z);
+            assert_eq!(
+                solid.occupancy[index],
+                c[0].abs() + c[1].abs() + c[2].abs() < 1.
+            );
+        }
+        assert!((solid.volume() - 4. / 3.).abs() < 0.1);
+
+        // random points on the triangles have to land in occupied voxels
+        let surface = voxelize_surface(&bvh, 16);
+        let mut rng = rand::thread_rng();
+        for tri in bvh.triangles.iter() {
+            for _ in 0..100 {
+                let (mut u, mut v): (f32, f32) = (rng.gen(), rng.gen());
+                if u + v > 1. {
+                    (u, v) = (1. - u, 1. - v);
+                }
+                let mut cell = [0; 3];
+                for (k, c) in cell.iter_mut().enumerate() {
+                    let p = tri.v1[k] + (tri.v2[k] - tri.v1[k]) * u + (tri.v3[k] - tri.v1[k]) * v;
+                    *c = (((p - surface.origin[k]) / surface.voxel_size) as usize)
+                        .min(surface.dims[k] - 1);
+                }
+                assert!(surface.get(cell[0], cell[1], cell[2]));
+            }
+        }
+        // the shell is hollow
+        assert!(!surface.get(8, 8, 8));
+        assert_eq!(surface.to_sparse().to_dense().occupancy, surface.occupancy);
+    }
+}